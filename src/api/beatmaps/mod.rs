@@ -2,14 +2,24 @@
 use std::sync::Arc;
 
 use axum::{
-    extract::Path, http::StatusCode, response::Result, routing::get, Extension, Json, Router,
+    extract::{Path, Query}, http::StatusCode, response::{IntoResponse, Response, Result}, routing::get, Extension, Json, Router,
 };
+use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
+use tracing::warn;
 
 use crate::{
+    api::{difficulties::{database_error, load_difficulty}, json_error},
     crawler::Context,
-    ops::{beatmaps::{get_beatmap_by_id as get_beatmap_from_db, DatabaseError}, beatmapset::get_beatmapset_by_hash},
-    osu::types::{Beatmap, Beatmapset},
+    difficulty::{
+        calculate_difficulty,
+        mods::Mods,
+        performance::{calculate_performance, PerformanceAttributes, Score, PP_ACCURACIES},
+        DifficultyAttributes,
+    },
+    ops::{beatmaps::{get_beatmap_by_id as get_beatmap_from_db, DatabaseError}, beatmapset::{get_beatmapset_by_beatmap_id, get_beatmapset_by_hash}},
+    osu::{parser::OsuFile, types::{Beatmap, Beatmapset}},
+    storage::{attributes_key, BeatmapStorage},
 };
 
 async fn get_beatmap_by_id(
@@ -54,8 +64,127 @@ async fn get_beatmap_by_hash(
     return Ok(Json(beatmapset.clone()));
 }
 
+async fn get_parsed_beatmap(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let mut ctx = ctx.lock().await;
+    let beatmapset = match get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+    let beatmap = match beatmapset.beatmaps.iter().find(|x| x.map_id == id) {
+        Some(beatmap) => beatmap.clone(),
+        None => return json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    };
+
+    let content = match load_difficulty(&mut ctx, &beatmapset, &beatmap).await {
+        Ok(content) => content,
+        Err(err) => return err.into_response(),
+    };
+
+    match OsuFile::parse(&String::from_utf8_lossy(&content)) {
+        Ok(file) => Json(file).into_response(),
+        Err(err) => json_error(StatusCode::UNPROCESSABLE_ENTITY, err),
+    }
+}
+
+#[derive(Deserialize)]
+struct AttributesQuery {
+    mods: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BeatmapAttributes {
+    beatmap_id: i64,
+    checksum: String,
+    mods: String,
+    mods_value: u32,
+    difficulty: DifficultyAttributes,
+    /// Full combo pp for each of `PP_ACCURACIES`.
+    pp: Vec<PerformanceAttributes>,
+}
+
+async fn get_beatmap_attributes(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+    Query(query): Query<AttributesQuery>,
+) -> Response {
+    let mods = match Mods::parse(query.mods.as_deref().unwrap_or_default()) {
+        Ok(mods) => mods,
+        Err(err) => return json_error(StatusCode::BAD_REQUEST, err),
+    };
+
+    let mut ctx = ctx.lock().await;
+    let beatmapset = match get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+    let beatmap = match beatmapset.beatmaps.iter().find(|x| x.map_id == id) {
+        Some(beatmap) => beatmap.clone(),
+        None => return json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    };
+    if beatmap.mode_int != 0 {
+        return json_error(StatusCode::UNPROCESSABLE_ENTITY, "Only osu!standard beatmaps are supported");
+    }
+
+    let checksum = beatmap.checksum.clone().unwrap_or_default();
+    let key = attributes_key(&checksum, mods.bits());
+    let storage = ctx.storage.clone();
+
+    //The checksum is part of the key, so cached attributes never go stale
+    if let Ok(cached) = storage.get(&key).await {
+        match serde_json::from_slice::<BeatmapAttributes>(&cached) {
+            Ok(attributes) => return Json(attributes).into_response(),
+            Err(err) => warn!("Ignoring unreadable cached attributes {}: {}", key, err),
+        }
+    }
+
+    let content = match load_difficulty(&mut ctx, &beatmapset, &beatmap).await {
+        Ok(content) => content,
+        Err(err) => return err.into_response(),
+    };
+
+    let file = match OsuFile::parse(&String::from_utf8_lossy(&content)) {
+        Ok(file) => file,
+        Err(err) => return json_error(StatusCode::UNPROCESSABLE_ENTITY, err),
+    };
+
+    let difficulty = match calculate_difficulty(&file, mods) {
+        Ok(difficulty) => difficulty,
+        Err(err) => return json_error(StatusCode::UNPROCESSABLE_ENTITY, err),
+    };
+
+    let pp = PP_ACCURACIES
+        .iter()
+        .map(|accuracy| calculate_performance(&difficulty, mods, Score::full_combo(&difficulty, *accuracy)))
+        .collect();
+
+    let attributes = BeatmapAttributes {
+        beatmap_id: beatmap.map_id,
+        checksum,
+        mods: mods.to_string(),
+        mods_value: mods.bits(),
+        difficulty,
+        pp,
+    };
+
+    match serde_json::to_vec(&attributes) {
+        Ok(serialized) => {
+            if let Err(err) = storage.put(&key, serialized).await {
+                warn!("Failed to cache attributes {}: {}", key, err);
+            }
+        }
+        Err(err) => warn!("Failed to serialize attributes {}: {}", key, err),
+    }
+
+    Json(attributes).into_response()
+}
+
 pub fn serve() -> Router {
-    return Router::new()
+    Router::new()
         .route("/api/v1/beatmaps/md5/:checksum", get(get_beatmap_by_hash))
-        .route("/api/v1/beatmaps/:id", get(get_beatmap_by_id));
+        .route("/api/v1/beatmaps/:id/parsed", get(get_parsed_beatmap))
+        .route("/api/v1/beatmaps/:id/attributes", get(get_beatmap_attributes))
+        .route("/api/v1/beatmaps/:id", get(get_beatmap_by_id))
 }
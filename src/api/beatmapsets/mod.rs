@@ -1,13 +1,16 @@
 use std::sync::Arc;
 
 use axum::{
-    Extension, Json, Router, extract::Path, http::StatusCode,
-    routing::get, response::Result
+    Extension, Json, Router, extract::{Path, Query}, http::StatusCode,
+    routing::get, response::{IntoResponse, Response, Result}
 };
+use serde_derive::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 
 
-use crate::{crawler::Context, osu::types::Beatmapset, ops::{beatmapset::get_beatmapset_by_id as fetch_beatmapset_by_id, beatmapset::get_beatmapset_by_beatmap_id as fetch_beatmapset_by_beatmap_id, beatmaps::DatabaseError}};
+use crate::{api::json_error, crawler::Context, osu::types::Beatmapset, ops::{beatmapset::get_beatmapset_by_id as fetch_beatmapset_by_id, beatmapset::get_beatmapset_by_beatmap_id as fetch_beatmapset_by_beatmap_id, beatmapset::{get_beatmapsets_after_id, BeatmapsetSort, SortField}, beatmaps::DatabaseError, cursor::PageCursor}};
+
+const MAX_LISTING_LIMIT: usize = 1000;
 
 async fn get_beatmapset_by_id(
     Extension(ctx): Extension<Arc<Mutex<Context>>>,
@@ -53,8 +56,50 @@ async fn get_beatmapset_by_beatmap_id(
     return Ok(Json(beatmapset))
 }
 
+#[derive(Deserialize)]
+struct ListingQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ListingResponse {
+    beatmapsets: Vec<Beatmapset>,
+    /// Null once every set has been listed.
+    cursor: Option<String>,
+}
+
+/// Every indexed set by id, deleted ones included, for mirrors enumerating the whole index.
+async fn list_beatmapsets(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Query(query): Query<ListingQuery>,
+) -> Response {
+    let ctx = ctx.lock().await;
+    let sort = BeatmapsetSort::new(SortField::Id, false);
+
+    let after = match query.cursor.as_deref().map(PageCursor::decode) {
+        None => 0,
+        Some(Some(cursor)) if cursor.sort == sort => cursor.id,
+        Some(_) => return json_error(StatusCode::BAD_REQUEST, "Invalid cursor"),
+    };
+    let limit = query.limit.unwrap_or(100).clamp(1, MAX_LISTING_LIMIT);
+
+    match get_beatmapsets_after_id(ctx.to_owned(), after, limit).await {
+        Ok(beatmapsets) => {
+            let cursor = match beatmapsets.len() {
+                length if length == limit => beatmapsets.last().and_then(|last| PageCursor::after(sort, last)).map(|cursor| cursor.encode()),
+                _ => None,
+            };
+
+            Json(ListingResponse { beatmapsets, cursor }).into_response()
+        }
+        Err(err) => json_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
 pub fn serve() -> Router {
-    return Router::new()
+    Router::new()
+    .route("/api/v1/beatmapsets", get(list_beatmapsets))
     .route("/api/v1/beatmapsets/:id", get(get_beatmapset_by_id))
-    .route("/api/v1/beatmapsets/beatmap/:id", get(get_beatmapset_by_beatmap_id));
+    .route("/api/v1/beatmapsets/beatmap/:id", get(get_beatmapset_by_beatmap_id))
 }
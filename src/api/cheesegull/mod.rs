@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use serde::Serialize;
+use tokio::sync::Mutex;
+
+use crate::{
+    api::json_error,
+    crawler::Context,
+    ops::{
+        beatmaps::DatabaseError,
+        beatmapset::{get_beatmapset_by_beatmap_id, get_beatmapset_by_hash, get_beatmapset_by_id, search_beatmapsets, BeatmapsetSearch, BeatmapsetSort, SortField},
+    },
+    osu::types::{Beatmap, Beatmapset},
+};
+
+const DEFAULT_AMOUNT: usize = 50;
+const MAX_AMOUNT: usize = 100;
+
+/// Go's zero time, which cheesegull sends for sets that were never ranked.
+const ZERO_DATE: &str = "0001-01-01T00:00:00Z";
+
+const MODES: [&str; 4] = ["osu", "taiko", "fruits", "mania"];
+
+/// osu!'s numeric ranked status, as sent in `RankedStatus` and the `status` search parameter.
+fn status_name(ranked: i64) -> Option<&'static str> {
+    match ranked {
+        -2 => Some("graveyard"),
+        -1 => Some("wip"),
+        0 => Some("pending"),
+        1 => Some("ranked"),
+        2 => Some("approved"),
+        3 => Some("qualified"),
+        4 => Some("loved"),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CheesegullBeatmap {
+    #[serde(rename = "BeatmapID")]
+    pub beatmap_id: i64,
+    #[serde(rename = "ParentSetID")]
+    pub parent_set_id: i64,
+    pub diff_name: String,
+    #[serde(rename = "FileMD5")]
+    pub file_md5: String,
+    pub mode: i64,
+    #[serde(rename = "BPM")]
+    pub bpm: f64,
+    #[serde(rename = "AR")]
+    pub ar: f64,
+    #[serde(rename = "OD")]
+    pub od: f64,
+    #[serde(rename = "CS")]
+    pub cs: f64,
+    #[serde(rename = "HP")]
+    pub hp: f64,
+    pub total_length: i64,
+    pub hit_length: i64,
+    pub playcount: i64,
+    pub passcount: i64,
+    pub max_combo: i64,
+    pub difficulty_rating: f64,
+}
+
+impl From<&Beatmap> for CheesegullBeatmap {
+    fn from(beatmap: &Beatmap) -> Self {
+        CheesegullBeatmap {
+            beatmap_id: beatmap.map_id,
+            parent_set_id: beatmap.mapset_id,
+            diff_name: beatmap.version.clone(),
+            file_md5: beatmap.checksum.clone().unwrap_or_default(),
+            mode: beatmap.mode_int,
+            bpm: beatmap.bpm,
+            ar: beatmap.ar,
+            od: beatmap.od,
+            cs: beatmap.cs,
+            hp: beatmap.hp,
+            total_length: beatmap.seconds_total,
+            hit_length: beatmap.seconds_drain,
+            playcount: beatmap.playcount,
+            passcount: beatmap.passcount,
+            max_combo: beatmap.max_combo.unwrap_or_default(),
+            difficulty_rating: beatmap.stars,
+        }
+    }
+}
+
+/// Sets whose genre or language couldn't be looked up yet have them sent as "any".
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct CheesegullBeatmapset {
+    #[serde(rename = "SetID")]
+    pub set_id: i64,
+    pub children_beatmaps: Vec<CheesegullBeatmap>,
+    pub ranked_status: i64,
+    pub approved_date: String,
+    pub last_update: String,
+    pub last_checked: String,
+    pub artist: String,
+    pub title: String,
+    pub creator: String,
+    pub source: String,
+    pub tags: String,
+    pub has_video: bool,
+    pub genre: i64,
+    pub language: i64,
+    pub favourites: i64,
+}
+
+impl From<&Beatmapset> for CheesegullBeatmapset {
+    fn from(beatmapset: &Beatmapset) -> Self {
+        CheesegullBeatmapset {
+            set_id: beatmapset.mapset_id,
+            children_beatmaps: beatmapset
+                .beatmaps
+                .iter()
+                .filter(|beatmap| beatmap.deleted_at.is_none())
+                .map(CheesegullBeatmap::from)
+                .collect(),
+            ranked_status: beatmapset.ranked,
+            approved_date: beatmapset.ranked_date.clone().unwrap_or(ZERO_DATE.to_string()),
+            last_update: beatmapset.last_updated.clone(),
+            last_checked: beatmapset.last_updated.clone(),
+            artist: beatmapset.artist.clone(),
+            title: beatmapset.title.clone(),
+            creator: beatmapset.creator.clone(),
+            source: beatmapset.source.clone(),
+            tags: beatmapset.tags.clone(),
+            has_video: beatmapset.video,
+            genre: beatmapset.genre.as_ref().map_or(0, |genre| genre.id),
+            language: beatmapset.language.as_ref().map_or(0, |language| language.id),
+            favourites: beatmapset.favourite_count,
+        }
+    }
+}
+
+/// Cheesegull answers lookups of unknown maps with a `null` body.
+fn lookup_error(err: DatabaseError) -> Response {
+    match err {
+        DatabaseError::RecordNotFound => Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .body(Body::from("null"))
+        .unwrap(),
+        DatabaseError::InternalDatabaseError => json_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal database exception"),
+    }
+}
+
+/// Deleted difficulties are left out here as well as from `ChildrenBeatmaps`.
+fn beatmap_response(beatmapset: Beatmapset, find: impl Fn(&Beatmap) -> bool) -> Response {
+    match beatmapset.beatmaps.iter().find(|beatmap| beatmap.deleted_at.is_none() && find(beatmap)) {
+        Some(beatmap) => Json(CheesegullBeatmap::from(beatmap)).into_response(),
+        None => lookup_error(DatabaseError::RecordNotFound),
+    }
+}
+
+async fn get_set(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let ctx = ctx.lock().await;
+
+    match get_beatmapset_by_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => Json(CheesegullBeatmapset::from(&beatmapset)).into_response(),
+        Err(err) => lookup_error(err),
+    }
+}
+
+async fn get_beatmap(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let ctx = ctx.lock().await;
+
+    match get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmap_response(beatmapset, |beatmap| beatmap.map_id == id),
+        Err(err) => lookup_error(err),
+    }
+}
+
+async fn get_beatmap_by_hash(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(hash): Path<String>,
+) -> Response {
+    let hash = hash.to_lowercase();
+    if hash.len() != 32 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return lookup_error(DatabaseError::RecordNotFound);
+    }
+
+    let ctx = ctx.lock().await;
+
+    match get_beatmapset_by_hash(ctx.to_owned(), &hash).await {
+        Ok(beatmapset) => beatmap_response(beatmapset, |beatmap| beatmap.checksum.as_deref() == Some(hash.as_str())),
+        Err(err) => lookup_error(err),
+    }
+}
+
+/// `status` and `mode` may be repeated, so the parameters are read as pairs.
+fn parse_search(params: &[(String, String)]) -> Result<BeatmapsetSearch, String> {
+    let mut search = BeatmapsetSearch {
+        query: String::new(),
+        statuses: Vec::new(),
+        modes: Vec::new(),
+        ranges: Vec::new(),
+        conditions: Vec::new(),
+        sort: BeatmapsetSort::new(SortField::Updated, true),
+        offset: 0,
+        limit: DEFAULT_AMOUNT,
+        facets: false,
+        cursor: None,
+    };
+
+    for (key, value) in params {
+        match key.as_str() {
+            "query" => search.query = value.clone(),
+            "amount" => search.limit = value.parse::<usize>().map_err(|_| format!("Invalid amount: {}", value))?.min(MAX_AMOUNT),
+            "offset" => search.offset = value.parse().map_err(|_| format!("Invalid offset: {}", value))?,
+            "status" => {
+                let status = value.parse().ok().and_then(status_name).ok_or(format!("Invalid status: {}", value))?;
+                search.statuses.push(status.to_string());
+            }
+            // Clients send -1 for every mode.
+            "mode" if value == "-1" => {}
+            "mode" => {
+                let mode = value.parse::<usize>().ok().and_then(|mode| MODES.get(mode)).ok_or(format!("Invalid mode: {}", value))?;
+                search.modes.push(mode.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    if search.statuses.is_empty() {
+        search.statuses = (-2..=4).filter_map(status_name).map(|status| status.to_string()).collect();
+    }
+    if search.modes.is_empty() {
+        search.modes = MODES.iter().map(|mode| mode.to_string()).collect();
+    }
+
+    Ok(search)
+}
+
+async fn search(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response {
+    let search = match parse_search(&params) {
+        Ok(search) => search,
+        Err(err) => return json_error(StatusCode::BAD_REQUEST, err),
+    };
+
+    let ctx = ctx.lock().await;
+
+    match search_beatmapsets(ctx.to_owned(), &search).await {
+        Ok(results) => Json(results.beatmapsets.iter().map(CheesegullBeatmapset::from).collect::<Vec<_>>()).into_response(),
+        Err(err) => lookup_error(err),
+    }
+}
+
+/// Only merged when `cheesegull_api` is enabled in the configuration.
+pub fn serve() -> Router {
+    Router::new()
+    .route("/api/s/:id", get(get_set))
+    .route("/api/b/:id", get(get_beatmap))
+    .route("/api/md5/:hash", get(get_beatmap_by_hash))
+    .route("/api/search", get(search))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::testing::fixture_beatmapset;
+
+    use super::*;
+
+    #[test]
+    fn maps_beatmapsets_to_pascal_case() {
+        let mapped = serde_json::to_value(CheesegullBeatmapset::from(&fixture_beatmapset())).unwrap();
+
+        assert_eq!(mapped["SetID"], 1);
+        assert_eq!(mapped["RankedStatus"], 1);
+        assert_eq!(mapped["ApprovedDate"], "2024-01-02T00:00:00Z");
+        assert_eq!(mapped["HasVideo"], false);
+        assert_eq!(mapped["Favourites"], 5);
+        assert_eq!(
+            mapped["ChildrenBeatmaps"][0],
+            json!({
+                "BeatmapID": 11,
+                "ParentSetID": 1,
+                "DiffName": "Normal",
+                "FileMD5": "3071b450ef4112d0a17259d160b8a90e",
+                "Mode": 0,
+                "BPM": 120.0,
+                "AR": 6.0,
+                "OD": 5.0,
+                "CS": 4.0,
+                "HP": 4.0,
+                "TotalLength": 50,
+                "HitLength": 42,
+                "Playcount": 100,
+                "Passcount": 10,
+                "MaxCombo": 0,
+                "DifficultyRating": 2.1,
+            })
+        );
+
+        let mut unranked = fixture_beatmapset();
+        unranked.ranked_date = None;
+        assert_eq!(CheesegullBeatmapset::from(&unranked).approved_date, ZERO_DATE);
+    }
+
+    #[test]
+    fn hides_deleted_difficulties() {
+        assert_eq!(beatmap_response(fixture_beatmapset(), |beatmap| beatmap.map_id == 11).status(), StatusCode::OK);
+
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.beatmaps[0].deleted_at = Some("2024-01-03T00:00:00Z".to_string());
+        assert_eq!(beatmap_response(beatmapset, |beatmap| beatmap.map_id == 11).status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn parses_search_parameters() {
+        let params = |pairs: &[(&str, &str)]| pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+
+        let search = parse_search(&params(&[("query", "fixture"), ("amount", "500"), ("status", "1"), ("status", "4"), ("mode", "3")])).unwrap();
+        assert_eq!(search.query, "fixture");
+        assert_eq!(search.limit, MAX_AMOUNT);
+        assert_eq!(search.statuses, ["ranked", "loved"]);
+        assert_eq!(search.modes, ["mania"]);
+
+        let search = parse_search(&[]).unwrap();
+        assert_eq!(search.statuses.len(), 7);
+        assert_eq!(search.modes.len(), 4);
+
+        assert!(parse_search(&params(&[("status", "9")])).is_err());
+        assert_eq!(parse_search(&params(&[("mode", "-1")])).unwrap().modes.len(), 4);
+        assert!(parse_search(&params(&[("mode", "4")])).is_err());
+    }
+}
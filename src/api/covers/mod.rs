@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::{body::Body, extract::Path, http::StatusCode, response::Response, routing::get, Extension, Router};
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+use crate::{
+    api::{difficulties::database_error, json_error},
+    crawler::Context,
+    ops::beatmapset::get_beatmapset_by_id,
+    osu::{client::{OsuApi, OsuError}, types::Covers},
+    storage::{cover_key, BeatmapStorage},
+};
+
+/// Versioned urls change with `Beatmapset::covers_version`, so their content never does.
+const COVER_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+fn cover_response(content: Vec<u8>) -> Response {
+    Response::builder()
+    .header("Content-Type", "image/jpeg")
+    .header("Cache-Control", COVER_CACHE_CONTROL)
+    .body(Body::from(content))
+    .unwrap()
+}
+
+/// Sends clients to the url of the current covers, which they revalidate every time.
+fn redirect_to_version(id: i64, version: &str, kind: &str) -> Response {
+    Response::builder()
+    .status(StatusCode::FOUND)
+    .header("Location", format!("/covers/{}/{}/{}.jpg", id, version, kind))
+    .header("Cache-Control", "no-cache")
+    .body(Body::empty())
+    .unwrap()
+}
+
+fn cover_kind(kind: &str) -> Option<String> {
+    let kind = kind.strip_suffix(".jpg").unwrap_or(kind);
+    Covers::KINDS.contains(&kind).then(|| kind.to_string())
+}
+
+async fn get_cover(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path((id, kind)): Path<(i64, String)>,
+) -> Response {
+    let kind = match cover_kind(&kind) {
+        Some(kind) => kind,
+        None => return json_error(StatusCode::NOT_FOUND, "Unknown cover kind"),
+    };
+
+    let ctx = ctx.lock().await;
+
+    match get_beatmapset_by_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => redirect_to_version(id, &beatmapset.covers_version(), &kind),
+        Err(err) => database_error(err),
+    }
+}
+
+async fn get_versioned_cover(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path((id, version, kind)): Path<(i64, String, String)>,
+) -> Response {
+    let kind = match cover_kind(&kind) {
+        Some(kind) => kind,
+        None => return json_error(StatusCode::NOT_FOUND, "Unknown cover kind"),
+    };
+
+    let ctx = ctx.lock().await;
+
+    let beatmapset = match get_beatmapset_by_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+    //Only the current covers are served, anything cached under an older version stays valid
+    let current = beatmapset.covers_version();
+    if version != current {
+        return redirect_to_version(id, &current, &kind);
+    }
+    let key = cover_key(id, &current, &kind);
+
+    if let Ok(cached) = ctx.storage.get(&key).await {
+        return cover_response(cached);
+    }
+
+    //The stored url keeps osu!'s cache busting query string
+    let url = beatmapset.covers.url(&kind).unwrap_or_default().to_string();
+    if url.is_empty() {
+        return json_error(StatusCode::NOT_FOUND, "Cover not found");
+    }
+
+    let cover = match ctx.osu.fetch_asset(&url).await {
+        Ok(cover) => cover,
+        Err(OsuError::NotFound) => return json_error(StatusCode::NOT_FOUND, "Cover not found"),
+        Err(err) => {
+            error!("Failed to fetch cover {}: {}", url, err);
+            return json_error(StatusCode::BAD_GATEWAY, "Failed to fetch cover");
+        }
+    };
+
+    if let Err(err) = ctx.storage.put(&key, cover.clone()).await {
+        warn!("Failed to cache {}: {}", key, err);
+    }
+
+    cover_response(cover)
+}
+
+pub fn serve() -> Router {
+    Router::new()
+    .route("/covers/:id/:kind", get(get_cover))
+    .route("/covers/:id/:version/:kind", get(get_versioned_cover))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        api::router,
+        testing::{context, fixture_beatmapset, index_beatmapsets, FakeOsu},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn redirects_to_immutable_versioned_covers() {
+        let fake = FakeOsu::start().await;
+        let ctx = context(&fake, "api_covers").await;
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.covers.cover = format!("{}/assets/beatmaps/1/covers/cover.jpg?1700000000", fake.base_url);
+        beatmapset.covers.card = format!("{}/assets/beatmaps/1/covers/card.jpg?1700000000", fake.base_url);
+        index_beatmapsets(&ctx, &[beatmapset]).await;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(ctx)).await.unwrap() });
+        let client = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none()).build().unwrap();
+
+        let versioned = "/covers/1/1700000000/card.jpg";
+        for url in ["/covers/1/card.jpg".to_string(), "/covers/1/outdated/card.jpg".to_string()] {
+            let response = client.get(format!("{}{}", base_url, url)).send().await.unwrap();
+            assert_eq!(response.status(), 302);
+            assert_eq!(response.headers()["Location"], versioned);
+        }
+
+        let response = client.get(format!("{}{}", base_url, versioned)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Cache-Control"], COVER_CACHE_CONTROL);
+        assert_eq!(fake.requests("/assets/beatmaps/1/covers/card.jpg"), 1);
+    }
+}
@@ -0,0 +1,320 @@
+use std::{fmt, fs::File, sync::Arc};
+
+use axum::{body::Body, extract::Path, http::StatusCode, response::Response, routing::get, Extension, Router};
+use chrono::Local;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+use crate::{
+    api::json_error,
+    archive::{extract_difficulty, extract_file, ArchiveError},
+    crawler::Context,
+    ops::{beatmaps::DatabaseError, beatmapset::{get_beatmapset_by_beatmap_id, get_beatmapset_by_hash}},
+    osu::{client::{OsuApi, OsuError}, types::{Beatmap, Beatmapset}},
+    storage::{archive_key, copy_to_staging, difficulty_key, missing_difficulty_key, BeatmapStorage, Storage, StorageError},
+};
+
+/// How long a difficulty missing from a freshly downloaded archive is reported as not found
+/// before the archive is downloaded again.
+const MISSING_DIFFICULTY_TTL: i64 = 60 * 60 * 24;
+
+#[derive(Debug)]
+pub enum DifficultyError {
+    NotFound,
+    Deleted,
+    DownloadDisabled,
+    Osu(OsuError),
+    Storage(StorageError),
+    Archive(String),
+}
+
+impl std::error::Error for DifficultyError {}
+
+impl fmt::Display for DifficultyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DifficultyError::NotFound => write!(f, "Difficulty not found in its archive."),
+            DifficultyError::Deleted => write!(f, "Beatmapset has been deleted."),
+            DifficultyError::DownloadDisabled => write!(f, "Download of this beatmapset has been disabled."),
+            DifficultyError::Osu(err) => write!(f, "{}", err),
+            DifficultyError::Storage(err) => write!(f, "{}", err),
+            DifficultyError::Archive(reason) => write!(f, "Failed to read archive: {}", reason),
+        }
+    }
+}
+
+impl DifficultyError {
+    pub fn into_response(self) -> Response {
+        match self {
+            DifficultyError::NotFound | DifficultyError::Osu(OsuError::NotFound) => {
+                json_error(StatusCode::NOT_FOUND, "Difficulty not found")
+            }
+            DifficultyError::Deleted => json_error(StatusCode::GONE, "Beatmapset has been deleted"),
+            DifficultyError::DownloadDisabled => {
+                json_error(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "Download of this beatmapset has been disabled")
+            }
+            DifficultyError::Osu(err) => {
+                error!("Failed to download archive: {}", err);
+                json_error(StatusCode::BAD_GATEWAY, "Failed to download archive")
+            }
+            err => {
+                error!("{}", err);
+                json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to read difficulty")
+            }
+        }
+    }
+}
+
+/// Runs `read` on a staging copy of the cached archive of `id`, off the async runtime.
+pub async fn read_archive<T, F>(storage: &Storage, id: i64, read: F) -> Result<T, DifficultyError>
+where
+    T: Send + 'static,
+    F: FnOnce(File) -> Result<T, ArchiveError> + Send + 'static,
+{
+    let source = copy_to_staging(storage, &archive_key(id)).await.map_err(DifficultyError::Storage)?;
+
+    let result = {
+        let source = source.clone();
+        tokio::task::spawn_blocking(move || read(File::open(source)?)).await
+    };
+    let _ = tokio::fs::remove_file(&source).await;
+
+    result
+        .map_err(|err| DifficultyError::Archive(err.to_string()))?
+        .map_err(|err| DifficultyError::Archive(err.to_string()))
+}
+
+/// Files of deleted or taken down sets are refused like their downloads, so their archives aren't fetched again.
+pub fn ensure_available(beatmapset: &Beatmapset) -> Result<(), DifficultyError> {
+    if beatmapset.deleted_at.is_some() {
+        return Err(DifficultyError::Deleted);
+    }
+    if beatmapset.availability.download_disabled {
+        return Err(DifficultyError::DownloadDisabled);
+    }
+
+    Ok(())
+}
+
+/// Whether `checksum` was missing from a freshly downloaded archive within `MISSING_DIFFICULTY_TTL`.
+async fn recently_missing(storage: &Storage, checksum: &str) -> bool {
+    storage
+        .get(&missing_difficulty_key(checksum))
+        .await
+        .ok()
+        .and_then(|marker| String::from_utf8(marker).ok()?.parse::<i64>().ok())
+        .is_some_and(|missed_at| Local::now().timestamp() - missed_at < MISSING_DIFFICULTY_TTL)
+}
+
+fn set_checksums(beatmapset: &Beatmapset) -> Vec<String> {
+    beatmapset
+        .beatmaps
+        .iter()
+        .filter_map(|beatmap| beatmap.checksum.clone())
+        .collect()
+}
+
+/// A file referenced by the difficulties of `beatmapset` (audio, background), read from its
+/// archive which is downloaded first when missing.
+pub async fn load_archive_file(ctx: &mut Context, beatmapset: &Beatmapset, path: &str) -> Result<Vec<u8>, DifficultyError> {
+    ensure_available(beatmapset)?;
+
+    let storage = ctx.storage.clone();
+    ctx.osu
+        .download_if_not_exists(beatmapset.mapset_id, &storage, false, &set_checksums(beatmapset))
+        .await
+        .map_err(DifficultyError::Osu)?;
+
+    let path = path.to_string();
+    read_archive(&storage, beatmapset.mapset_id, move |file| extract_file(file, &path))
+        .await?
+        .ok_or(DifficultyError::NotFound)
+}
+
+/// The `.osu` of `beatmap`, extracted from the archive of its set (downloaded first when
+/// missing) and cached under `difficulty_key`. Only a file matching `Beatmap.checksum` is returned.
+pub async fn load_difficulty(ctx: &mut Context, beatmapset: &Beatmapset, beatmap: &Beatmap) -> Result<Vec<u8>, DifficultyError> {
+    ensure_available(beatmapset)?;
+
+    let checksum = beatmap.checksum.clone().ok_or(DifficultyError::NotFound)?;
+    let key = difficulty_key(&checksum);
+    let storage = ctx.storage.clone();
+
+    match storage.get(&key).await {
+        Ok(content) => return Ok(content),
+        Err(StorageError::NotFound) => {}
+        Err(err) => return Err(DifficultyError::Storage(err)),
+    }
+
+    //The index is ahead of osu! or the archive never had it, until the miss expires
+    if recently_missing(&storage, &checksum).await {
+        return Err(DifficultyError::NotFound);
+    }
+
+    let checksums = set_checksums(beatmapset);
+    let cached = storage.exists(&archive_key(beatmapset.mapset_id)).await.map_err(DifficultyError::Storage)?;
+
+    //A cached archive from before the difficulty was updated is downloaded again once,
+    //one downloaded just now is as current as osu! has it
+    let passes: &[bool] = if cached { &[false, true] } else { &[false] };
+    for &force in passes {
+        ctx.osu
+            .download_if_not_exists(beatmapset.mapset_id, &storage, force, &checksums)
+            .await
+            .map_err(DifficultyError::Osu)?;
+
+        let expected = checksum.clone();
+        let extracted = read_archive(&storage, beatmapset.mapset_id, move |file| extract_difficulty(file, &expected)).await?;
+
+        if let Some(content) = extracted {
+            storage.put(&key, content.clone()).await.map_err(DifficultyError::Storage)?;
+            return Ok(content);
+        }
+    }
+
+    let missed_at = Local::now().timestamp().to_string().into_bytes();
+    if let Err(err) = storage.put(&missing_difficulty_key(&checksum), missed_at).await {
+        warn!("Failed to record missing difficulty {}: {}", checksum, err);
+    }
+
+    Err(DifficultyError::NotFound)
+}
+
+pub fn database_error(err: DatabaseError) -> Response {
+    match err {
+        DatabaseError::RecordNotFound => json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+        _ => json_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal database exception"),
+    }
+}
+
+async fn serve_difficulty(ctx: &mut Context, beatmapset: Beatmapset, beatmap: Beatmap) -> Response {
+    let content = match load_difficulty(ctx, &beatmapset, &beatmap).await {
+        Ok(content) => content,
+        Err(err) => return err.into_response(),
+    };
+
+    let file_name = format!(
+        "{} - {} ({}) [{}].osu",
+        beatmapset.artist, beatmapset.title, beatmapset.creator, beatmap.version
+    );
+
+    Response::builder()
+    .header("Content-Type", "application/octet-stream")
+    .header("Content-Disposition", format!("attachment; filename={}", file_name))
+    .header("ETag", format!("\"{}\"", beatmap.checksum.unwrap_or_default()))
+    .body(Body::from(content))
+    .unwrap()
+}
+
+async fn get_osu_by_id(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+) -> Response {
+    let mut ctx = ctx.lock().await;
+
+    let beatmapset = match get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+
+    match beatmapset.beatmaps.iter().find(|beatmap| beatmap.map_id == id).cloned() {
+        Some(beatmap) => serve_difficulty(&mut ctx, beatmapset, beatmap).await,
+        None => json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    }
+}
+
+async fn get_osu_by_checksum(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(checksum): Path<String>,
+) -> Response {
+    if checksum.len() != 32 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+        return json_error(StatusCode::BAD_REQUEST, "Checksum must be an md5 hash");
+    }
+    //osu! reports checksums in lowercase
+    let checksum = checksum.to_lowercase();
+
+    let mut ctx = ctx.lock().await;
+
+    let beatmapset = match get_beatmapset_by_hash(ctx.to_owned(), &checksum).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+
+    let beatmap = beatmapset
+        .beatmaps
+        .iter()
+        .find(|beatmap| beatmap.checksum.as_deref() == Some(checksum.as_str()))
+        .cloned();
+
+    match beatmap {
+        Some(beatmap) => serve_difficulty(&mut ctx, beatmapset, beatmap).await,
+        None => json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    }
+}
+
+pub fn serve() -> Router {
+    Router::new()
+    .route("/api/v1/osu/md5/:checksum", get(get_osu_by_checksum))
+    .route("/api/v1/osu/:beatmap_id", get(get_osu_by_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{build_archive, fixture_beatmapset, fixture_difficulty, offline_context, FakeOsu};
+
+    use super::*;
+
+    const DOWNLOAD: &str = "/api/v2/beatmapsets/1/download";
+
+    #[tokio::test]
+    async fn refuses_deleted_and_disabled_sets_without_downloading() {
+        let fake = FakeOsu::start().await;
+        let mut ctx = offline_context(&fake, "difficulty_unavailable").await;
+
+        let mut deleted = fixture_beatmapset();
+        deleted.deleted_at = Some("2024-01-01T00:00:00+00:00".to_string());
+        let beatmap = deleted.beatmaps[0].clone();
+        assert!(matches!(load_difficulty(&mut ctx, &deleted, &beatmap).await, Err(DifficultyError::Deleted)));
+
+        let mut disabled = fixture_beatmapset();
+        disabled.availability.download_disabled = true;
+        assert!(matches!(load_archive_file(&mut ctx, &disabled, "audio.mp3").await, Err(DifficultyError::DownloadDisabled)));
+
+        assert_eq!(fake.requests(DOWNLOAD), 0);
+    }
+
+    #[tokio::test]
+    async fn records_difficulties_missing_from_a_fresh_archive() {
+        let fake = FakeOsu::start().await;
+        let mut ctx = offline_context(&fake, "difficulty_missing").await;
+
+        let mut beatmapset = fixture_beatmapset();
+        let normal = beatmapset.beatmaps.iter().find(|beatmap| beatmap.version == "Normal").unwrap().clone();
+        let stale = beatmapset.beatmaps.iter_mut().find(|beatmap| beatmap.version == "Hard").unwrap();
+        stale.checksum = Some("0".repeat(32));
+        let stale = stale.clone();
+
+        assert!(matches!(load_difficulty(&mut ctx, &beatmapset, &stale).await, Err(DifficultyError::NotFound)));
+        assert_eq!(fake.requests(DOWNLOAD), 1);
+
+        assert!(matches!(load_difficulty(&mut ctx, &beatmapset, &stale).await, Err(DifficultyError::NotFound)));
+        assert_eq!(fake.requests(DOWNLOAD), 1);
+
+        assert_eq!(load_difficulty(&mut ctx, &beatmapset, &normal).await.unwrap(), fixture_difficulty("Normal"));
+    }
+
+    #[tokio::test]
+    async fn downloads_outdated_cached_archives_again() {
+        let fake = FakeOsu::start().await;
+        let mut ctx = offline_context(&fake, "difficulty_outdated").await;
+        let beatmapset = fixture_beatmapset();
+        let hard = beatmapset.beatmaps.iter().find(|beatmap| beatmap.version == "Hard").unwrap().clone();
+
+        //An archive from before Hard was added
+        let outdated = build_archive(&[("normal.osu", &fixture_difficulty("Normal"))]);
+        ctx.storage.put(&archive_key(1), outdated).await.unwrap();
+
+        assert_eq!(load_difficulty(&mut ctx, &beatmapset, &hard).await.unwrap(), fixture_difficulty("Hard"));
+        assert_eq!(fake.requests(DOWNLOAD), 1);
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use axum::{body::Body, extract::Query, http::StatusCode, response::Response, routing::get, Extension, Router};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::{
+    crawler::Context,
+    ops::{
+        beatmaps::DatabaseError,
+        beatmapset::{get_beatmapset_by_beatmap_id, get_beatmapset_by_hash, get_beatmapset_by_id, search_beatmapsets, BeatmapsetSearch, BeatmapsetSort, SortField},
+    },
+    osu::types::Beatmapset,
+};
+
+/// osu!direct pages are fixed at 100 sets, a count of 101 tells stable there is another page.
+const PAGE_SIZE: usize = 100;
+
+const ALL_STATUSES: [&str; 7] = ["ranked", "approved", "qualified", "loved", "pending", "wip", "graveyard"];
+const ALL_MODES: [&str; 4] = ["osu", "taiko", "fruits", "mania"];
+
+fn text_response(status: StatusCode, content: String) -> Response {
+    Response::builder()
+    .status(status)
+    .header("Content-Type", "text/plain; charset=utf-8")
+    .body(Body::from(content))
+    .unwrap()
+}
+
+/// Beatmapset statuses behind osu!direct's `r` filter.
+fn direct_statuses(status: i32) -> Vec<&'static str> {
+    match status {
+        // 7 is "ranked played", which would need the player's scores.
+        0 | 7 => vec!["ranked", "approved"],
+        2 => vec!["pending", "wip"],
+        3 => vec!["qualified"],
+        5 => vec!["graveyard"],
+        8 => vec!["loved"],
+        _ => ALL_STATUSES.to_vec(),
+    }
+}
+
+/// Modes behind osu!direct's `m` filter, -1 being all of them.
+fn direct_modes(mode: i32) -> Vec<&'static str> {
+    match mode {
+        0..=3 => vec![ALL_MODES[mode as usize]],
+        _ => ALL_MODES.to_vec(),
+    }
+}
+
+/// The tabs of osu!direct are sent as queries, they list everything in their order instead.
+fn direct_sort(query: &str) -> (&str, BeatmapsetSort) {
+    match query.to_lowercase().as_str() {
+        "newest" => ("", BeatmapsetSort::new(SortField::Updated, true)),
+        "top rated" => ("", BeatmapsetSort::new(SortField::Rating, true)),
+        "most played" => ("", BeatmapsetSort::new(SortField::Plays, true)),
+        _ => (query, BeatmapsetSort::new(SortField::Updated, true)),
+    }
+}
+
+/// Pipes and line breaks would split rows, commas split the difficulty list.
+fn sanitize(value: &str) -> String {
+    value.replace('|', "I").replace(['\r', '\n'], " ")
+}
+
+/// The forum topic of a set is the last segment of its legacy thread url.
+fn thread_id(beatmapset: &Beatmapset) -> i64 {
+    beatmapset
+        .legacy_thread_url
+        .as_deref()
+        .and_then(|url| url.trim_end_matches('/').rsplit('/').next())
+        .and_then(|topic| topic.parse().ok())
+        .unwrap_or(0)
+}
+
+/// `filename|artist|title|creator|status|rating|last update|set id|thread id|video|storyboard|size|size without video`,
+/// statuses are osu!'s numeric ones. Sizes are placeholder zeros, archive sizes are not known before download.
+fn set_row(beatmapset: &Beatmapset) -> String {
+    format!(
+        "{id}.osz|{}|{}|{}|{}|{:.1}|{}|{id}|{}|{}|{}|0|0",
+        sanitize(&beatmapset.artist),
+        sanitize(&beatmapset.title),
+        sanitize(&beatmapset.creator),
+        beatmapset.ranked,
+        beatmapset.rating,
+        beatmapset.last_updated,
+        thread_id(beatmapset),
+        beatmapset.video as u8,
+        beatmapset.storyboard as u8,
+        id = beatmapset.mapset_id,
+    )
+}
+
+/// `set_row` followed by the difficulties, easiest first.
+fn search_row(beatmapset: &Beatmapset) -> String {
+    let mut beatmaps: Vec<_> = beatmapset.beatmaps.iter().filter(|beatmap| beatmap.deleted_at.is_none()).collect();
+    beatmaps.sort_by(|a, b| a.mode_int.cmp(&b.mode_int).then(a.stars.total_cmp(&b.stars)));
+
+    let difficulties = beatmaps
+        .iter()
+        .map(|beatmap| {
+            format!(
+                "[{:.2}★] {} {{cs: {} / od: {} / ar: {} / hp: {}}}@{}",
+                beatmap.stars,
+                sanitize(&beatmap.version).replace(',', " "),
+                beatmap.cs,
+                beatmap.od,
+                beatmap.ar,
+                beatmap.hp,
+                beatmap.mode_int
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(",");
+
+    format!("{}|{}", set_row(beatmapset), difficulties)
+}
+
+fn search_results(beatmapsets: &[Beatmapset]) -> String {
+    let count = match beatmapsets.len() {
+        PAGE_SIZE => PAGE_SIZE + 1,
+        count => count,
+    };
+
+    let mut lines = vec![count.to_string()];
+    lines.extend(beatmapsets.iter().map(search_row));
+    lines.join("\n")
+}
+
+#[derive(Deserialize)]
+struct DirectSearchQuery {
+    q: Option<String>,
+    r: Option<i32>,
+    m: Option<i32>,
+    p: Option<usize>,
+}
+
+async fn direct_search(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Query(query): Query<DirectSearchQuery>,
+) -> Response {
+    let ctx = ctx.lock().await;
+
+    let (text, sort) = direct_sort(query.q.as_deref().unwrap_or(""));
+    let search = BeatmapsetSearch {
+        query: text.to_string(),
+        statuses: direct_statuses(query.r.unwrap_or(4)).iter().map(|status| status.to_string()).collect(),
+        modes: direct_modes(query.m.unwrap_or(-1)).iter().map(|mode| mode.to_string()).collect(),
+        ranges: Vec::new(),
+        conditions: Vec::new(),
+        sort,
+        offset: query.p.unwrap_or(0).saturating_mul(PAGE_SIZE),
+        limit: PAGE_SIZE,
+        facets: false,
+        cursor: None,
+    };
+
+    match search_beatmapsets(ctx.to_owned(), &search).await {
+        Ok(results) => text_response(StatusCode::OK, search_results(&results.beatmapsets)),
+        // A negative count makes stable show the next line as the error.
+        Err(_) => text_response(StatusCode::OK, "-1\nFailed to search beatmaps".to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct DirectSetQuery {
+    s: Option<i64>,
+    b: Option<i64>,
+    c: Option<String>,
+}
+
+fn is_checksum(value: &str) -> bool {
+    value.len() == 32 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+async fn direct_search_set(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Query(query): Query<DirectSetQuery>,
+) -> Response {
+    let ctx = ctx.lock().await;
+
+    let beatmapset = match (query.s, query.b, query.c) {
+        (Some(id), _, _) => get_beatmapset_by_id(ctx.to_owned(), id).await,
+        (_, Some(id), _) => get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await,
+        (_, _, Some(checksum)) if is_checksum(&checksum) => get_beatmapset_by_hash(ctx.to_owned(), checksum.to_lowercase()).await,
+        _ => Err(DatabaseError::RecordNotFound),
+    };
+
+    // Stable treats an empty body as "not found".
+    match beatmapset {
+        Ok(beatmapset) => text_response(StatusCode::OK, set_row(&beatmapset)),
+        Err(DatabaseError::RecordNotFound) => text_response(StatusCode::OK, String::new()),
+        Err(DatabaseError::InternalDatabaseError) => text_response(StatusCode::INTERNAL_SERVER_ERROR, String::new()),
+    }
+}
+
+pub fn serve() -> Router {
+    Router::new()
+    .route("/web/osu-search.php", get(direct_search))
+    .route("/web/osu-search-set.php", get(direct_search_set))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixture_beatmapset;
+
+    use super::*;
+
+    #[test]
+    fn maps_direct_filters() {
+        assert_eq!(direct_statuses(0), ["ranked", "approved"]);
+        assert_eq!(direct_statuses(7), direct_statuses(0));
+        assert_eq!(direct_statuses(8), ["loved"]);
+        assert_eq!(direct_statuses(4).len(), ALL_STATUSES.len());
+
+        assert_eq!(direct_modes(2), ["fruits"]);
+        assert_eq!(direct_modes(-1).len(), 4);
+
+        assert_eq!(direct_sort("Most Played"), ("", BeatmapsetSort::new(SortField::Plays, true)));
+        assert_eq!(direct_sort("camellia"), ("camellia", BeatmapsetSort::new(SortField::Updated, true)));
+    }
+
+    #[test]
+    fn formats_rows() {
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.title = "Fixture|Song".to_string();
+        beatmapset.rating = 9.46;
+        beatmapset.legacy_thread_url = Some("https://osu.ppy.sh/community/forums/topics/1234".to_string());
+
+        assert_eq!(
+            set_row(&beatmapset),
+            "1.osz|Fixture Artist|FixtureISong|mirria|1|9.5|2024-01-01T00:00:00Z|1|1234|0|0|0|0"
+        );
+        assert_eq!(
+            search_row(&beatmapset),
+            "1.osz|Fixture Artist|FixtureISong|mirria|1|9.5|2024-01-01T00:00:00Z|1|1234|0|0|0|0|\
+            [2.10★] Normal {cs: 4 / od: 5 / ar: 6 / hp: 4}@0,[4.30★] Hard {cs: 4 / od: 8 / ar: 9 / hp: 6}@0"
+        );
+
+        let page = vec![fixture_beatmapset(); PAGE_SIZE];
+        assert!(search_results(&page).starts_with("101\n"));
+        assert_eq!(search_results(&[]), "0");
+
+        assert_eq!(
+            set_row(&fixture_beatmapset()),
+            "1.osz|Fixture Artist|Fixture Song|mirria|1|0.0|2024-01-01T00:00:00Z|1|0|0|0|0|0"
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::http::{header, HeaderMap};
+
+/// What a `Range` header asks for, resolved against the size of the archive.
+#[derive(Debug, PartialEq)]
+pub enum RangeRequest {
+    Full,
+    /// Inclusive byte offsets.
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+/// Only single `bytes=` ranges are honoured, anything else (multiple ranges, other units,
+/// garbage) is answered with the full archive as RFC 9110 allows.
+pub fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let spec = match value.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return RangeRequest::Full,
+    };
+
+    let (start, end) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return RangeRequest::Full,
+    };
+
+    match (start.trim(), end.trim()) {
+        ("", "") => RangeRequest::Full,
+        // Suffix range, the last `length` bytes.
+        ("", length) => match length.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(_) if size == 0 => RangeRequest::Unsatisfiable,
+            Ok(length) => RangeRequest::Partial(size.saturating_sub(length), size - 1),
+            Err(_) => RangeRequest::Full,
+        },
+        (start, end) => {
+            let start = match start.parse::<u64>() {
+                Ok(start) => start,
+                Err(_) => return RangeRequest::Full,
+            };
+            let end = match end {
+                "" => None,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => Some(end),
+                    _ => return RangeRequest::Full,
+                },
+            };
+
+            if start >= size {
+                return RangeRequest::Unsatisfiable;
+            }
+
+            RangeRequest::Partial(start, end.map_or(size - 1, |end| end.min(size - 1)))
+        }
+    }
+}
+
+pub fn http_date(timestamp: i64) -> String {
+    httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(timestamp.max(0) as u64))
+}
+
+fn parse_http_date(value: &str) -> Option<i64> {
+    let time = httpdate::parse_http_date(value.trim()).ok()?;
+    Some(time.duration_since(UNIX_EPOCH).ok()?.as_secs() as i64)
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+/// Weak comparison, `W/"x"` matches `"x"`, as `If-None-Match` requires.
+fn etag_list_contains(value: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+
+    value
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+/// Whether the client's cached copy (validated by `If-None-Match`, or `If-Modified-Since`
+/// when there is no `If-None-Match`) is still current, so a 304 can be sent.
+pub fn not_modified(headers: &HeaderMap, etag: &str, last_modified: i64) -> bool {
+    if let Some(value) = header_str(headers, header::IF_NONE_MATCH) {
+        return etag_list_contains(value, etag);
+    }
+
+    match header_str(headers, header::IF_MODIFIED_SINCE).and_then(parse_http_date) {
+        Some(since) => last_modified <= since,
+        None => false,
+    }
+}
+
+/// A `Range` only applies when `If-Range` is absent or still names the current archive,
+/// either by strong ETag or by exact `Last-Modified` date.
+pub fn range_applies(headers: &HeaderMap, etag: &str, last_modified: i64) -> bool {
+    match header_str(headers, header::IF_RANGE) {
+        None => true,
+        Some(value) if value.trim().starts_with('"') => value.trim() == etag,
+        Some(value) => parse_http_date(value) == Some(last_modified),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), RangeRequest::Partial(0, 99));
+        assert_eq!(parse_range("bytes=500-", 1000), RangeRequest::Partial(500, 999));
+        assert_eq!(parse_range("bytes=-100", 1000), RangeRequest::Partial(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), RangeRequest::Partial(0, 999));
+        assert_eq!(parse_range("bytes=900-5000", 1000), RangeRequest::Partial(900, 999));
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn ignores_ranges_it_does_not_serve() {
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=9-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn evaluates_preconditions() {
+        let etag = "\"1-1700000000-1000\"";
+        let last_modified = 1_700_000_000;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\", W/\"1-1700000000-1000\""));
+        assert!(not_modified(&headers, etag, last_modified));
+
+        // If-None-Match wins over If-Modified-Since.
+        headers.insert(header::IF_NONE_MATCH, HeaderValue::from_static("\"other\""));
+        headers.insert(header::IF_MODIFIED_SINCE, HeaderValue::from_str(&http_date(last_modified)).unwrap());
+        assert!(!not_modified(&headers, etag, last_modified));
+
+        headers.remove(header::IF_NONE_MATCH);
+        assert!(not_modified(&headers, etag, last_modified));
+        assert!(!not_modified(&headers, etag, last_modified + 1));
+
+        let mut headers = HeaderMap::new();
+        assert!(range_applies(&headers, etag, last_modified));
+        headers.insert(header::IF_RANGE, HeaderValue::from_static("\"stale\""));
+        assert!(!range_applies(&headers, etag, last_modified));
+        headers.insert(header::IF_RANGE, HeaderValue::from_str(&http_date(last_modified)).unwrap());
+        assert!(range_applies(&headers, etag, last_modified));
+    }
+}
@@ -1,16 +1,18 @@
-use std::{sync::Arc, path::Path as path_sys};
+pub mod conditional;
 
-use axum::{extract::Path, Extension, Router, routing::get, response::Response, body::Body};
+use std::{fs::File, sync::Arc};
+
+use axum::{extract::{Path, Query}, Extension, Router, routing::get, response::Response, body::Body, http::{header::RANGE, HeaderMap, StatusCode}};
 use chrono::{DateTime, Local};
-use serde_json::json;
-use tokio::{io::AsyncReadExt, sync::Mutex};
+use serde_derive::Deserialize;
+use tokio::sync::Mutex;
 use tracing::{error, info};
 
-use crate::{crawler::Context, osu::client::OsuApi, ops::{beatmapset::get_beatmapset_by_id, DownloadIndex}};
+use crate::{api::{json_error, downloads::conditional::{http_date, not_modified, parse_range, range_applies, RangeRequest}}, crawler::Context, osu::client::{OsuApi, OsuError}, ops::{beatmapset::get_beatmapset_by_id, filter::Filter, DownloadIndex}, archive::{strip_videos, ArchiveError}, storage::{archive_key, copy_to_staging, novideo_archive_key, staging_path, BeatmapStorage, Storage}};
 
 async fn create_new_index(ctx: Context, id: i64) -> Option<DownloadIndex> {
-    let download_index = Some(DownloadIndex { id: id, date: Local::now().timestamp()});
-    if let Err(err) = ctx.meili_client.index("downloads").add_documents(&[download_index.clone().unwrap()], Some("id")).await {
+    let download_index = Some(DownloadIndex { id, date: Local::now().timestamp(), failure: None, failed_at: None });
+    if let Err(err) = ctx.index("downloads").add_documents(&[download_index.clone().unwrap()], Some("id")).await {
         error!("Failed to create index: {}", err);
     }else{
         info!("Created new index: {:#?}", download_index);
@@ -19,11 +21,17 @@ async fn create_new_index(ctx: Context, id: i64) -> Option<DownloadIndex> {
     download_index
 }
 
+async fn update_index(ctx: &Context, index: &DownloadIndex) {
+    if let Err(err) = ctx.index("downloads").add_documents(std::slice::from_ref(index), Some("id")).await {
+        error!("Failed to update index of {}: {}", index.id, err);
+    }
+}
+
 async fn get_index_or_create(ctx: Context, id: i64) -> Option<DownloadIndex> {
-    let index_response = ctx.meili_client
+    let index_response = ctx
     .index("downloads")
     .search()
-    .with_filter(format!("id = {}", id).as_str())
+    .with_filter(&Filter::eq("id", id).to_string())
     .execute::<DownloadIndex>()
     .await;
     
@@ -39,7 +47,7 @@ async fn get_index_or_create(ctx: Context, id: i64) -> Option<DownloadIndex> {
 
     let index = index_response.unwrap();
 
-    if index.hits.len() < 1 {
+    if index.hits.is_empty() {
         //Creating new index
         let download_index = create_new_index(ctx.clone(), id).await;
 
@@ -54,75 +62,246 @@ async fn get_index_or_create(ctx: Context, id: i64) -> Option<DownloadIndex> {
     None
 }
 
+/// Builds `novideo/{id}.osz` from the cached archive, going through staging files
+/// so neither archive has to fit in memory.
+async fn create_novideo_archive(storage: &Storage, id: i64) -> Result<(), String> {
+    let source = copy_to_staging(storage, &archive_key(id)).await.map_err(|err| err.to_string())?;
+    let destination = staging_path(storage, &format!("{}-novideo.osz", id));
+
+    let stripped = {
+        let (source, destination) = (source.clone(), destination.clone());
+        tokio::task::spawn_blocking(move || -> Result<Vec<String>, ArchiveError> {
+            strip_videos(File::open(source)?, File::create(destination)?)
+        })
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|stripped| stripped.map_err(|err| err.to_string()))
+    };
+    let _ = tokio::fs::remove_file(&source).await;
+
+    match stripped {
+        Ok(removed) => info!("Removed {:?} from {}", removed, id),
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&destination).await;
+            return Err(err);
+        }
+    }
+
+    storage.put_file(&novideo_archive_key(id), &destination).await.map_err(|err| err.to_string())
+}
+
+#[derive(Deserialize)]
+struct DownloadQuery {
+    #[serde(rename = "noVideo")]
+    no_video: Option<String>,
+}
+
+impl DownloadQuery {
+    fn no_video(&self) -> bool {
+        matches!(self.no_video.as_deref(), Some("1") | Some("true"))
+    }
+}
+
 async fn download(
     Extension(ctx): Extension<Arc<Mutex<Context>>>,
-    Path(id): Path<i64>
+    Path(id): Path<i64>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap
 ) -> Response {
+    serve_archive(ctx, id, query.no_video(), headers).await
+}
+
+async fn download_novideo(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+    headers: HeaderMap
+) -> Response {
+    serve_archive(ctx, id, true, headers).await
+}
+
+/// osu!direct asks for `/d/{id}n` when "no video" is ticked.
+async fn download_direct(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<String>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap
+) -> Response {
+    let (id, no_video) = match id.strip_suffix('n') {
+        Some(id) => (id, true),
+        None => (id.as_str(), query.no_video()),
+    };
 
+    match id.parse::<i64>() {
+        Ok(id) => serve_archive(ctx, id, no_video, headers).await,
+        Err(_) => json_error(StatusCode::BAD_REQUEST, "Invalid beatmapset id"),
+    }
+}
+
+async fn serve_archive(ctx: Arc<Mutex<Context>>, id: i64, no_video: bool, headers: HeaderMap) -> Response {
     let mut ctx = ctx.lock().await;
-    let config = ctx.config.clone();
+    let storage = ctx.storage.clone();
     let mut redownload_required = false;
 
     let index = get_index_or_create(ctx.to_owned(), id).await;
     if index.is_none() {
-        Response::builder().status(500).body(Body::from(json!({"ok": false, "message": "Internal database exception"}).to_string())).unwrap();
+        return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Internal database exception");
     }
-   
-    let beatmapset = get_beatmapset_by_id(ctx.to_owned(), id).await;
+    let mut index = index.unwrap();
+    //Archives of sets missing from the index are dated by their first download
+    let mut last_modified = index.date;
 
-    match beatmapset {
-        Ok(set) => {
-            //parsing time from last_updated field
-            let date = DateTime::parse_from_rfc3339(&set.last_updated);
-            if date.is_err() {
-                return Response::builder().body(Body::from(json!({"ok": false, "message": "Failed to parse date"}).to_string())).unwrap();
-            }
-            let date = date.unwrap();
-            let last_updated = date.timestamp();
+    let beatmapset = get_beatmapset_by_id(ctx.to_owned(), id).await.ok();
 
-            //if last updated is bigger than last download date
-            if last_updated > index.unwrap().date {
-                info!("Redownloading {}, it is too old", id);
+    if let Some(set) = &beatmapset {
+        if set.deleted_at.is_some() {
+            return json_error(StatusCode::GONE, "Beatmapset has been deleted");
+        }
 
-                redownload_required = true;
-            }
+        if set.availability.download_disabled {
+            return json_error(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS, "Download of this beatmapset has been disabled");
+        }
+
+        //parsing time from last_updated field
+        let date = DateTime::parse_from_rfc3339(&set.last_updated);
+        if date.is_err() {
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to parse date");
+        }
+        let date = date.unwrap();
+        last_modified = date.timestamp();
+
+        //if last updated is bigger than last download date
+        if last_modified > index.date {
+            info!("Redownloading {}, it is too old", id);
+
+            redownload_required = true;
+        }
+    }
+
+    let checksums: Vec<String> = beatmapset
+        .iter()
+        .flat_map(|set| set.beatmaps.iter().filter_map(|beatmap| beatmap.checksum.clone()))
+        .collect();
+
+    if let Err(err) = ctx.osu.download_if_not_exists(id, &storage, redownload_required, &checksums).await {
+        if let OsuError::NotFound = err {
+            return json_error(StatusCode::NOT_FOUND, "Beatmapset not found");
+        }
+
+        if let OsuError::InvalidArchive(reason) = err {
+            index.failure = Some(reason);
+            index.failed_at = Some(Local::now().timestamp());
+            update_index(&ctx, &index).await;
+
+            return json_error(StatusCode::BAD_GATEWAY, "osu! returned an invalid archive");
         }
-        _ => {}
+
+        return json_error(StatusCode::BAD_GATEWAY, "Failed to download file");
     }
 
-    if ctx.osu.download_if_not_exists(id.clone(), config.beatmaps_folder.clone(), redownload_required).await.is_err() {
-        return Response::builder().body(Body::from(json!({"ok": false, "message": "Failed to download file"}).to_string())).unwrap();
+    //A stale date would have the fresh archive downloaded again on every request
+    if redownload_required || index.failure.is_some() {
+        if redownload_required {
+            index.date = Local::now().timestamp();
+        }
+        index.failure = None;
+        index.failed_at = None;
+        update_index(&ctx, &index).await;
     }
 
-    let data_folder = path_sys::new(ctx.config.beatmaps_folder.as_str());
-    let mut file = match tokio::fs::File::open(data_folder.join(format!("{}.osz", id))).await {
-        Ok(file) => file,
+    let key = match no_video {
+        true => novideo_archive_key(id),
+        false => archive_key(id),
+    };
+
+    if no_video {
+        let exists = storage.exists(&key).await.unwrap_or(false);
+        if !exists {
+            if let Err(err) = create_novideo_archive(&storage, id).await {
+                error!("Failed to create no video archive of {}: {}", id, err);
+                return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to download file");
+            }
+        }
+    }
+
+    let size = match storage.size(&key).await {
+        Ok(size) => size,
         Err(err) => {
-            error!("Failed to open file: {}", err);
-            return Response::builder().body(Body::from(json!({"ok": false, "message": "Failed to download file"}).to_string())).unwrap()
+            error!("Failed to read archive: {}", err);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to download file")
         },
     };
 
-    let mut content = Vec::new();
-    file.read_to_end(&mut content).await.unwrap();
-    
-    let mut file_name = format!("{}.osz", id);    
-    let beatmapset = get_beatmapset_by_id(ctx.to_owned(), id).await;
-    
-    if let Ok(beatmapset) = beatmapset {
-        file_name = format!("{} {} - {}.osz", beatmapset.mapset_id, beatmapset.artist, beatmapset.title);
+    //The archive only changes when the set is updated, so its date and size identify it
+    let variant = if no_video { "-novideo" } else { "" };
+    let etag = format!("\"{}-{}-{}{}\"", id, last_modified, size, variant);
+
+    let mut file_name = format!("{}", id);
+    if let Some(beatmapset) = beatmapset {
+        file_name = format!("{} {} - {}", beatmapset.mapset_id, beatmapset.artist, beatmapset.title);
+    }
+    if no_video {
+        file_name.push_str(" [no video]");
     }
+    file_name.push_str(".osz");
+
+    let response = Response::builder()
+    .header("ETag", &etag)
+    .header("Last-Modified", http_date(last_modified))
+    .header("Accept-Ranges", "bytes");
+
+    if not_modified(&headers, &etag, last_modified) {
+        return response
+        .status(StatusCode::NOT_MODIFIED)
+        .body(Body::empty())
+        .unwrap();
+    }
+
+    let range = match headers.get(RANGE).and_then(|value| value.to_str().ok()) {
+        Some(range) if range_applies(&headers, &etag, last_modified) => parse_range(range, size),
+        _ => RangeRequest::Full,
+    };
+
+    let (response, archive) = match range {
+        RangeRequest::Unsatisfiable => {
+            return response
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header("Content-Range", format!("bytes */{}", size))
+            .body(Body::empty())
+            .unwrap();
+        }
+        RangeRequest::Partial(start, end) => (
+            response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", format!("bytes {}-{}/{}", start, end, size)),
+            storage.get_range(&key, start, end).await,
+        ),
+        RangeRequest::Full => (response, storage.get_stream(&key).await),
+    };
 
-    Response::builder()
+    let archive = match archive {
+        Ok(archive) => archive,
+        Err(err) => {
+            error!("Failed to read archive: {}", err);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to download file")
+        },
+    };
+
+    let response = match archive.length {
+        Some(length) => response.header("Content-Length", length),
+        None => response,
+    };
+
+    response
     .header("Content-Type", "application/x-osu-beatmap-archive")
     .header("Content-Disposition", format!("attachment; filename={}", file_name))
-    .body(Body::from(content))
-    .unwrap()    
+    .body(Body::from_stream(archive.body))
+    .unwrap()
 }
 
-
 pub fn serve() -> Router {
-    return Router::new()
+    Router::new()
     .route("/api/v1/download/:id", get(download))
-    .route("/d/:id", get(download));
+    .route("/api/v1/download/:id/novideo", get(download_novideo))
+    .route("/d/:id", get(download_direct))
+    .route("/d/:id/novideo", get(download_novideo))
 }
@@ -0,0 +1,204 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::StatusCode,
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use serde::Deserialize;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+use crate::{
+    api::{
+        difficulties::{database_error, ensure_available, load_archive_file, load_difficulty, DifficultyError},
+        json_error,
+    },
+    crawler::Context,
+    media::{image_content_type, mp3_preview, thumbnail, MediaError, ThumbnailSize},
+    ops::beatmapset::{get_beatmapset_by_beatmap_id, get_beatmapset_by_id},
+    osu::{parser::OsuFile, types::{Beatmap, Beatmapset}},
+    storage::{background_key, preview_key, BeatmapStorage, Storage},
+};
+
+fn media_response(content_type: &str, content: Vec<u8>) -> Response {
+    Response::builder()
+    .header("Content-Type", content_type)
+    .header("Cache-Control", "public, max-age=86400")
+    .body(Body::from(content))
+    .unwrap()
+}
+
+async fn cache(storage: &Storage, key: &str, content: Vec<u8>) {
+    if let Err(err) = storage.put(key, content).await {
+        warn!("Failed to cache {}: {}", key, err);
+    }
+}
+
+fn media_error(err: MediaError) -> Response {
+    match err {
+        MediaError::UnsupportedAudio => json_error(StatusCode::UNPROCESSABLE_ENTITY, err),
+        MediaError::Image(_) => {
+            error!("{}", err);
+            json_error(StatusCode::UNPROCESSABLE_ENTITY, "Background is not a supported image")
+        }
+    }
+}
+
+async fn parse_difficulty(ctx: &mut Context, beatmapset: &Beatmapset, beatmap: &Beatmap) -> Result<OsuFile, Response> {
+    let content = load_difficulty(ctx, beatmapset, beatmap).await.map_err(DifficultyError::into_response)?;
+
+    OsuFile::parse(&String::from_utf8_lossy(&content)).map_err(|err| json_error(StatusCode::UNPROCESSABLE_ENTITY, err))
+}
+
+async fn get_preview(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(file): Path<String>,
+) -> Response {
+    let id = match file.strip_suffix(".mp3").and_then(|id| id.parse::<i64>().ok()) {
+        Some(id) => id,
+        None => return json_error(StatusCode::NOT_FOUND, "Preview not found"),
+    };
+
+    let mut ctx = ctx.lock().await;
+    let storage = ctx.storage.clone();
+    let key = preview_key(id);
+
+    if let Ok(cached) = storage.get(&key).await {
+        return media_response("audio/mpeg", cached);
+    }
+
+    let beatmapset = match get_beatmapset_by_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+
+    //Every difficulty of a set shares the audio, stable uses the preview time of the first one
+    let beatmap = match beatmapset.beatmaps.iter().find(|beatmap| beatmap.checksum.is_some()) {
+        Some(beatmap) => beatmap.clone(),
+        None => return json_error(StatusCode::NOT_FOUND, "Preview not found"),
+    };
+
+    let osu = match parse_difficulty(&mut ctx, &beatmapset, &beatmap).await {
+        Ok(osu) => osu,
+        Err(response) => return response,
+    };
+
+    let audio = match load_archive_file(&mut ctx, &beatmapset, &osu.general.audio_filename).await {
+        Ok(audio) => audio,
+        Err(DifficultyError::NotFound) => return json_error(StatusCode::NOT_FOUND, "Audio not found in archive"),
+        Err(err) => return err.into_response(),
+    };
+
+    let preview_time = osu.general.preview_time as f64;
+    let clip = match tokio::task::spawn_blocking(move || mp3_preview(&audio, preview_time)).await {
+        Ok(Ok(clip)) => clip,
+        Ok(Err(err)) => return media_error(err),
+        Err(err) => {
+            error!("Failed to create preview of {}: {}", id, err);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create preview");
+        }
+    };
+
+    cache(&storage, &key, clip.clone()).await;
+    media_response("audio/mpeg", clip)
+}
+
+#[derive(Deserialize)]
+struct BackgroundQuery {
+    size: Option<String>,
+}
+
+async fn load_background(ctx: &mut Context, beatmapset: &Beatmapset, beatmap: &Beatmap, checksum: &str) -> Result<Vec<u8>, Response> {
+    let key = background_key(checksum, "full");
+    if let Ok(cached) = ctx.storage.get(&key).await {
+        return Ok(cached);
+    }
+
+    let osu = parse_difficulty(ctx, beatmapset, beatmap).await?;
+    let path = match osu.events.background {
+        Some(path) => path,
+        None => return Err(json_error(StatusCode::NOT_FOUND, "Beatmap has no background")),
+    };
+
+    let background = match load_archive_file(ctx, beatmapset, &path).await {
+        Ok(background) => background,
+        Err(DifficultyError::NotFound) => return Err(json_error(StatusCode::NOT_FOUND, "Background not found in archive")),
+        Err(err) => return Err(err.into_response()),
+    };
+
+    cache(&ctx.storage, &key, background.clone()).await;
+    Ok(background)
+}
+
+async fn get_background(
+    Extension(ctx): Extension<Arc<Mutex<Context>>>,
+    Path(id): Path<i64>,
+    Query(query): Query<BackgroundQuery>,
+) -> Response {
+    let size = match query.size.as_deref().map(ThumbnailSize::parse) {
+        None => None,
+        Some(Some(size)) => Some(size),
+        Some(None) => return json_error(StatusCode::BAD_REQUEST, "Size must be one of large, medium or small"),
+    };
+
+    let mut ctx = ctx.lock().await;
+
+    let beatmapset = match get_beatmapset_by_beatmap_id(ctx.to_owned(), id).await {
+        Ok(beatmapset) => beatmapset,
+        Err(err) => return database_error(err),
+    };
+
+    let beatmap = match beatmapset.beatmaps.iter().find(|beatmap| beatmap.map_id == id) {
+        Some(beatmap) => beatmap.clone(),
+        None => return json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    };
+    let checksum = match &beatmap.checksum {
+        Some(checksum) => checksum.clone(),
+        None => return json_error(StatusCode::NOT_FOUND, "Beatmap not found"),
+    };
+    //Backgrounds are cached by checksum and outlive the files of a set taken down since
+    if let Err(err) = ensure_available(&beatmapset) {
+        return err.into_response();
+    }
+
+    let storage = ctx.storage.clone();
+    let key = size.map(|size| background_key(&checksum, size.name()));
+
+    if let Some(key) = &key {
+        if let Ok(cached) = storage.get(key).await {
+            return media_response("image/jpeg", cached);
+        }
+    }
+
+    let background = match load_background(&mut ctx, &beatmapset, &beatmap, &checksum).await {
+        Ok(background) => background,
+        Err(response) => return response,
+    };
+
+    let (size, key) = match (size, key) {
+        (Some(size), Some(key)) => (size, key),
+        _ => return media_response(image_content_type(&background), background),
+    };
+
+    let resized = match tokio::task::spawn_blocking(move || thumbnail(&background, size)).await {
+        Ok(Ok(resized)) => resized,
+        Ok(Err(err)) => return media_error(err),
+        Err(err) => {
+            error!("Failed to create thumbnail of {}: {}", id, err);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create thumbnail");
+        }
+    };
+
+    cache(&storage, &key, resized.clone()).await;
+    media_response("image/jpeg", resized)
+}
+
+pub fn serve() -> Router {
+    Router::new()
+    .route("/preview/:file", get(get_preview))
+    .route("/bg/:beatmap_id", get(get_background))
+}
@@ -1,12 +1,18 @@
 pub mod beatmaps;
 pub mod beatmapsets;
+pub mod cheesegull;
+pub mod covers;
+pub mod difficulties;
+pub mod direct;
 pub mod downloads;
+pub mod media;
 pub mod search;
 
 use std::sync::Arc;
 
-use axum::{routing::get, Extension, Router};
+use axum::{body::Body, http::StatusCode, response::Response, routing::get, Extension, Router};
 use axum_prometheus::{metrics_exporter_prometheus::PrometheusBuilder, PrometheusMetricLayerBuilder};
+use serde_json::json;
 use tokio::sync::Mutex;
 use tower::ServiceBuilder;
 use tower_http::trace::{DefaultMakeSpan, TraceLayer};
@@ -14,27 +20,132 @@ use tracing::Level;
 
 use crate::crawler::Context;
 
+pub fn json_error(status: StatusCode, message: impl ToString) -> Response {
+    Response::builder()
+    .status(status)
+    .header("Content-Type", "application/json")
+    .body(Body::from(json!({"ok": false, "message": message.to_string()}).to_string()))
+    .unwrap()
+}
 
-pub async fn serve(ctx: Context) {
+pub fn router(ctx: Context) -> Router {
+    let cheesegull_api = ctx.config.cheesegull_api;
     let ctx = Arc::new(Mutex::new(ctx.clone()));
-    let prometeus_layer = PrometheusMetricLayerBuilder::new().with_prefix("mirria").build();
-    let metric_handle = PrometheusBuilder::new()
-    .install_recorder()
-    .unwrap();
 
     let layer_ctx = ServiceBuilder::new()
         .layer(TraceLayer::new_for_http()
         .make_span_with(DefaultMakeSpan::new().level(Level::INFO).include_headers(true)))
         .layer(Extension(ctx));
 
-    let router = Router::new()
+    let mut router = Router::new()
         .merge(crate::api::beatmapsets::serve())
         .merge(crate::api::beatmaps::serve())
+        .merge(crate::api::covers::serve())
+        .merge(crate::api::difficulties::serve())
+        .merge(crate::api::direct::serve())
         .merge(crate::api::downloads::serve())
-        .merge(crate::api::search::serve())
+        .merge(crate::api::media::serve())
+        .merge(crate::api::search::serve());
+
+    if cheesegull_api {
+        router = router.merge(crate::api::cheesegull::serve());
+    }
+
+    router.layer(layer_ctx)
+}
+
+pub async fn serve(ctx: Context) {
+    let prometeus_layer = PrometheusMetricLayerBuilder::new().with_prefix("mirria").build();
+    let metric_handle = PrometheusBuilder::new()
+    .install_recorder()
+    .unwrap();
+
+    let router = router(ctx)
         .route("/metrics", get(|| async move { metric_handle.render() }))
-        .layer(layer_ctx)
         .layer(prometeus_layer);
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, router).await.unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        ops::{beatmapset::get_beatmapset_by_id, DownloadIndex},
+        osu::types::Beatmapset,
+        testing::{context, fixture_archive, fixture_beatmapset, index_beatmapsets, FakeOsu},
+    };
+
+    use super::*;
+
+    async fn serve_router(ctx: Context) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router(ctx)).await.unwrap() });
+
+        format!("http://{}", address)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn serves_beatmapset_and_download() {
+        let fake = FakeOsu::start().await;
+        let ctx = context(&fake, "api_router").await;
+        index_beatmapsets(&ctx, &[fixture_beatmapset()]).await;
+        assert!(get_beatmapset_by_id(ctx.clone(), 1).await.is_ok());
+
+        let base_url = serve_router(ctx).await;
+
+        let beatmapset: Beatmapset = reqwest::get(format!("{}/api/v1/beatmapsets/1", base_url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(beatmapset, fixture_beatmapset());
+
+        let response = reqwest::get(format!("{}/d/1", base_url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.bytes().await.unwrap().to_vec(), fixture_archive());
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1/download"), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn redownloads_outdated_archive_once() {
+        let fake = FakeOsu::start().await;
+        let ctx = context(&fake, "api_redownload").await;
+        index_beatmapsets(&ctx, &[fixture_beatmapset()]).await;
+        let outdated = DownloadIndex { id: 1, date: 0, failure: None, failed_at: None };
+        ctx.index("downloads")
+            .add_documents(&[outdated], Some("id"))
+            .await
+            .unwrap()
+            .wait_for_completion(&ctx.meili_client, None, Some(Duration::from_secs(30)))
+            .await
+            .unwrap();
+
+        let base_url = serve_router(ctx.clone()).await;
+
+        let response = reqwest::get(format!("{}/d/1", base_url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1/download"), 1);
+
+        let mut redated = false;
+        for _ in 0..60 {
+            let index: DownloadIndex = ctx.index("downloads").get_document("1").await.unwrap();
+            if index.date > 0 {
+                redated = true;
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        assert!(redated);
+
+        let response = reqwest::get(format!("{}/d/1", base_url)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.bytes().await.unwrap().to_vec(), fixture_archive());
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1/download"), 1);
+    }
+}
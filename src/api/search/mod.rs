@@ -1,18 +1,26 @@
+pub mod query;
+
 use std::sync::Arc;
 
 use axum::{
     extract::{Query, Request},
     http::StatusCode,
-    response::Result,
+    response::{IntoResponse, Response},
     routing::get,
     Extension, Json, Router,
 };
 use serde_derive::{Deserialize, Serialize};
 
 use tokio::sync::Mutex;
-use tracing::error;
-
-use crate::{crawler::Context, osu::types::Beatmapset};
+use crate::{
+    api::{json_error, search::query::apply_query},
+    crawler::Context,
+    ops::{
+        beatmapset::{search_beatmapsets, BeatmapsetSearch, BeatmapsetSort, DifficultyAttribute, DifficultyRange, Facets, SortField},
+        cursor::PageCursor,
+    },
+    osu::types::Beatmapset,
+};
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub enum OsuRuleset {
@@ -45,72 +53,210 @@ struct SearchQuery {
     pub statuses: Option<Vec<String>>,
     pub sort: Option<String>,
     pub modes: Option<Vec<OsuRuleset>>,
+    /// Counts hits per status, mode, genre, language and video.
+    pub facets: Option<bool>,
+    /// `cursor` of a previous response, continues after it instead of using `offset`.
+    /// Text, difficulty and relevance sorts have none.
+    pub cursor: Option<String>,
+    /// Lengths are in seconds.
+    pub min_stars: Option<f64>,
+    pub max_stars: Option<f64>,
+    pub min_ar: Option<f64>,
+    pub max_ar: Option<f64>,
+    pub min_od: Option<f64>,
+    pub max_od: Option<f64>,
+    pub min_cs: Option<f64>,
+    pub max_cs: Option<f64>,
+    pub min_hp: Option<f64>,
+    pub max_hp: Option<f64>,
+    pub min_bpm: Option<f64>,
+    pub max_bpm: Option<f64>,
+    pub min_length: Option<f64>,
+    pub max_length: Option<f64>,
+    pub min_drain: Option<f64>,
+    pub max_drain: Option<f64>,
+    pub min_combo: Option<f64>,
+    pub max_combo: Option<f64>,
+}
+
+impl SearchQuery {
+    fn ranges(&self) -> Result<Vec<DifficultyRange>, String> {
+        let bounds = [
+            (DifficultyAttribute::Stars, self.min_stars, self.max_stars),
+            (DifficultyAttribute::ApproachRate, self.min_ar, self.max_ar),
+            (DifficultyAttribute::OverallDifficulty, self.min_od, self.max_od),
+            (DifficultyAttribute::CircleSize, self.min_cs, self.max_cs),
+            (DifficultyAttribute::DrainRate, self.min_hp, self.max_hp),
+            (DifficultyAttribute::Bpm, self.min_bpm, self.max_bpm),
+            (DifficultyAttribute::Length, self.min_length, self.max_length),
+            (DifficultyAttribute::DrainLength, self.min_drain, self.max_drain),
+            (DifficultyAttribute::MaxCombo, self.min_combo, self.max_combo),
+        ];
+
+        let mut ranges = Vec::new();
+        for (attribute, min, max) in bounds {
+            if min.is_none() && max.is_none() {
+                continue;
+            }
+            if !min.unwrap_or(0.0).is_finite() || !max.unwrap_or(0.0).is_finite() {
+                return Err(format!("Invalid {} range", attribute.name()));
+            }
+            if let (Some(min), Some(max)) = (min, max) {
+                if min > max {
+                    return Err(format!("min_{name} is greater than max_{name}", name = attribute.name()));
+                }
+            }
+
+            ranges.push(DifficultyRange { attribute, min, max, exclusive: false });
+        }
+
+        Ok(ranges)
+    }
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    /// Estimated by Meilisearch, exact when filtering by difficulty ranges.
+    total: usize,
+    offset: usize,
+    limit: usize,
+    beatmapsets: Vec<Beatmapset>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    facets: Option<Facets>,
+    /// Continues after this page, null on the last page and for sorts without cursors.
+    cursor: Option<String>,
 }
 
 async fn search(
     Extension(ctx): Extension<Arc<Mutex<Context>>>,
     Query(_query): Query<SearchQuery>,
     request: Request,
-) -> Result<Json<Vec<Beatmapset>>, StatusCode> {
+) -> Response {
     let ctx = ctx.lock().await;
     let parsed_query =
         serde_qs::from_str(urlencoding::decode(request.uri().query().unwrap_or("")).unwrap_or("".into()).to_string().as_str());
 
-    if let Err(_err) = parsed_query {
-        return Err(StatusCode::BAD_REQUEST);
+    if let Err(err) = parsed_query {
+        return json_error(StatusCode::BAD_REQUEST, err);
     }
 
     let parsed_query: SearchQuery = parsed_query.unwrap();
+    let ranges = match parsed_query.ranges() {
+        Ok(ranges) => ranges,
+        Err(err) => return json_error(StatusCode::BAD_REQUEST, err),
+    };
 
-    let mapped_statuses = parsed_query
+    let statuses = parsed_query
         .statuses
         .unwrap_or(Vec::from(
-            ["ranked", "loved", "aproved", "qualified"].map(|x| x.to_string()),
-        ))
-        .join(", ");
-    let modes = parsed_query.modes.unwrap_or(vec![OsuRuleset::Osu, OsuRuleset::Taiko, OsuRuleset::Fruits, OsuRuleset::Mania]).iter().map(
-        |x| {
-            return format!("(beatmaps.mode = '{}')", serialize_ruleset(x.clone())).to_string();
+            ["ranked", "loved", "approved", "qualified"].map(|x| x.to_string()),
+        ));
+    let modes = parsed_query
+        .modes
+        .unwrap_or(vec![OsuRuleset::Osu, OsuRuleset::Taiko, OsuRuleset::Fruits, OsuRuleset::Mania])
+        .into_iter()
+        .map(serialize_ruleset)
+        .collect();
+
+    let sort = match parsed_query.sort.as_deref() {
+        None => BeatmapsetSort::new(SortField::Updated, true),
+        // Kept for clients from before sorts had a direction.
+        Some("playcount") => BeatmapsetSort::new(SortField::Plays, true),
+        Some(sort) => match BeatmapsetSort::parse(sort) {
+            Some(sort) => sort,
+            None => return json_error(StatusCode::BAD_REQUEST, format!("Unknown sort: {}", sort)),
         },
-    ).collect::<Vec<String>>().join(" OR ");
-
-    let sorting = match parsed_query
-        .sort
-        .unwrap_or("updated_desc".to_string())
-        .as_str()
-    {
-        "updated_asc" => "last_updated:asc",
-        "playcount" => "play_count:asc",
-        _ => "last_updated:desc",
     };
 
-    let response = ctx
-        .meili_client
-        .index("beatmapset")
-        .search()
-        .with_query((parsed_query.query.unwrap_or("".to_string())).as_str())
-        .with_filter(format!("(status IN [{}]) AND ({})", mapped_statuses, modes).as_str())
-        .with_sort(&[sorting])
-        .with_offset(parsed_query.offset.unwrap_or(0) as usize)
-        .with_limit(parsed_query.limit.unwrap_or(50) as usize)
-        .execute::<Beatmapset>()
-        .await;
-
-    if response.is_err() {
-        let error = response.unwrap_err();
-        error!("{}", error);
-        return Err(StatusCode::INTERNAL_SERVER_ERROR);
-    }
+    let cursor = match parsed_query.cursor.as_deref().map(PageCursor::decode) {
+        None => None,
+        Some(_) if !PageCursor::supports(sort) => {
+            return json_error(StatusCode::BAD_REQUEST, format!("Sort {} can't be paged with a cursor", sort))
+        }
+        Some(Some(cursor)) if cursor.sort == sort => Some(cursor),
+        Some(Some(_)) => return json_error(StatusCode::BAD_REQUEST, "Cursor belongs to a different sort"),
+        Some(None) => return json_error(StatusCode::BAD_REQUEST, "Invalid cursor"),
+    };
 
-    let beatmapets = response.unwrap();
+    let mut search = BeatmapsetSearch {
+        query: String::new(),
+        statuses,
+        modes,
+        ranges,
+        conditions: Vec::new(),
+        sort,
+        offset: match cursor {
+            Some(_) => 0,
+            None => parsed_query.offset.unwrap_or(0) as usize,
+        },
+        limit: parsed_query.limit.unwrap_or(50) as usize,
+        facets: parsed_query.facets.unwrap_or(false),
+        cursor,
+    };
 
-    let hits = beatmapets.hits.clone();
+    if let Err(err) = apply_query(&parsed_query.query.unwrap_or("".to_string()), &mut search) {
+        return json_error(StatusCode::BAD_REQUEST, err);
+    }
 
-    return Ok(Json(
-        hits.iter().map(|set| set.clone().result).clone().collect(),
-    ));
+    match search_beatmapsets(ctx.to_owned(), &search).await {
+        Ok(results) => Json(SearchResponse {
+            total: results.total,
+            offset: search.offset,
+            limit: search.limit,
+            beatmapsets: results.beatmapsets,
+            facets: results.facets,
+            cursor: results.next.as_ref().map(PageCursor::encode),
+        })
+        .into_response(),
+        Err(err) => json_error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
 }
 
 pub fn serve() -> Router {
-    return Router::new().route("/api/v1/search", get(search));
+    Router::new().route("/api/v1/search", get(search))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{fixture_beatmapset, offline_context, FakeOsu};
+
+    use super::*;
+
+    fn ranges(query: &str) -> Result<Vec<DifficultyRange>, String> {
+        serde_qs::from_str::<SearchQuery>(query).unwrap().ranges()
+    }
+
+    #[test]
+    fn parses_difficulty_ranges() {
+        let parsed = ranges("query=camellia&min_stars=5.5&max_ar=9&min_length=60&max_length=120").unwrap();
+        assert_eq!(
+            parsed,
+            vec![
+                DifficultyRange { attribute: DifficultyAttribute::Stars, min: Some(5.5), max: None, exclusive: false },
+                DifficultyRange { attribute: DifficultyAttribute::ApproachRate, min: None, max: Some(9.0), exclusive: false },
+                DifficultyRange { attribute: DifficultyAttribute::Length, min: Some(60.0), max: Some(120.0), exclusive: false },
+            ]
+        );
+
+        assert!(ranges("").unwrap().is_empty());
+        assert!(ranges("min_bpm=200&max_bpm=100").is_err());
+        assert!(ranges("min_stars=inf").is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_cursors_of_sorts_without_them() {
+        let fake = FakeOsu::start().await;
+        let ctx = offline_context(&fake, "search_cursor_sorts").await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            axum::serve(listener, serve().layer(Extension(Arc::new(Mutex::new(ctx))))).await.unwrap()
+        });
+
+        let updated = PageCursor::after(BeatmapsetSort::new(SortField::Updated, true), &fixture_beatmapset()).unwrap();
+        for sort in ["title_asc", "difficulty_desc", "relevance_desc", "plays_desc"] {
+            let url = format!("{}/api/v1/search?sort={}&cursor={}", base_url, sort, updated.encode());
+            assert_eq!(reqwest::get(url).await.unwrap().status(), 400, "{}", sort);
+        }
+    }
 }
@@ -0,0 +1,295 @@
+use std::fmt;
+
+use crate::ops::{
+    beatmapset::{BeatmapsetSearch, DifficultyAttribute, DifficultyRange},
+    filter::{Filter, Operator},
+};
+
+const STATUSES: [&str; 7] = ["ranked", "approved", "qualified", "loved", "pending", "wip", "graveyard"];
+
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    InvalidValue(String, String),
+    UnsupportedOperator(String, String),
+}
+
+impl std::error::Error for QueryError {}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueryError::InvalidValue(key, value) => write!(f, "Invalid value for {}: {:?}", key, value),
+            QueryError::UnsupportedOperator(key, operator) => write!(f, "{} doesn't support {}", key, operator),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TokenOperator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl TokenOperator {
+    /// Longest first, so `>=` isn't read as `>` followed by `=value`.
+    const SYMBOLS: [(&'static str, TokenOperator); 8] = [
+        ("==", TokenOperator::Equal),
+        ("!=", TokenOperator::NotEqual),
+        (">=", TokenOperator::GreaterOrEqual),
+        ("<=", TokenOperator::LessOrEqual),
+        ("=", TokenOperator::Equal),
+        (":", TokenOperator::Equal),
+        (">", TokenOperator::Greater),
+        ("<", TokenOperator::Less),
+    ];
+
+    fn symbol(self) -> &'static str {
+        TokenOperator::SYMBOLS.iter().find(|(_, operator)| *operator == self).map(|(symbol, _)| *symbol).unwrap()
+    }
+}
+
+/// Splits on whitespace outside of double quotes, quotes are kept.
+fn tokenize(query: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+
+    for c in query.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                token.push(c);
+            }
+            c if c.is_whitespace() && !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+/// `key`, operator and value of a `key<operator>value` token.
+fn split_token(token: &str) -> Option<(String, TokenOperator, String)> {
+    let key_length = token.find(|c: char| !c.is_ascii_alphabetic())?;
+    if key_length == 0 {
+        return None;
+    }
+
+    let (key, rest) = token.split_at(key_length);
+    let (symbol, operator) = TokenOperator::SYMBOLS.iter().find(|(symbol, _)| rest.starts_with(symbol))?;
+    let value = rest[symbol.len()..].trim_matches('"');
+
+    Some((key.to_lowercase(), *operator, value.to_string()))
+}
+
+fn difficulty_attribute(key: &str) -> Option<DifficultyAttribute> {
+    match key {
+        "stars" | "star" | "sr" => Some(DifficultyAttribute::Stars),
+        "ar" => Some(DifficultyAttribute::ApproachRate),
+        "od" => Some(DifficultyAttribute::OverallDifficulty),
+        "cs" => Some(DifficultyAttribute::CircleSize),
+        "hp" | "dr" => Some(DifficultyAttribute::DrainRate),
+        "bpm" => Some(DifficultyAttribute::Bpm),
+        "length" => Some(DifficultyAttribute::Length),
+        "drain" => Some(DifficultyAttribute::DrainLength),
+        "combo" => Some(DifficultyAttribute::MaxCombo),
+        _ => None,
+    }
+}
+
+fn text_attribute(key: &str) -> Option<&'static str> {
+    match key {
+        "creator" | "mapper" => Some("creator"),
+        "artist" => Some("artist"),
+        "title" => Some("title"),
+        _ => None,
+    }
+}
+
+/// Half the precision values are shown with, so `stars=5.2` matches what is displayed as 5.2.
+fn tolerance(attribute: DifficultyAttribute) -> f64 {
+    match attribute {
+        DifficultyAttribute::Stars | DifficultyAttribute::Bpm => 0.005,
+        DifficultyAttribute::ApproachRate
+        | DifficultyAttribute::OverallDifficulty
+        | DifficultyAttribute::CircleSize
+        | DifficultyAttribute::DrainRate => 0.05,
+        DifficultyAttribute::Length | DifficultyAttribute::DrainLength | DifficultyAttribute::MaxCombo => 0.5,
+    }
+}
+
+/// Seconds, or a duration like `1m30s` or `1.5m`.
+fn parse_duration(value: &str) -> Option<f64> {
+    if let Ok(seconds) = value.parse::<f64>() {
+        return Some(seconds);
+    }
+
+    let mut total = 0.0;
+    let mut number = String::new();
+    for c in value.chars() {
+        let unit = match c {
+            'h' => 3600.0,
+            'm' => 60.0,
+            's' => 1.0,
+            c => {
+                number.push(c);
+                continue;
+            }
+        };
+        total += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+
+    match number.is_empty() && !value.is_empty() {
+        true => Some(total),
+        false => None,
+    }
+}
+
+fn difficulty_range(attribute: DifficultyAttribute, operator: TokenOperator, value: f64) -> Option<DifficultyRange> {
+    let range = DifficultyRange { attribute, min: None, max: None, exclusive: false };
+
+    match operator {
+        TokenOperator::Equal => Some(DifficultyRange {
+            min: Some(value - tolerance(attribute)),
+            max: Some(value + tolerance(attribute)),
+            ..range
+        }),
+        TokenOperator::Greater => Some(DifficultyRange { min: Some(value), exclusive: true, ..range }),
+        TokenOperator::GreaterOrEqual => Some(DifficultyRange { min: Some(value), ..range }),
+        TokenOperator::Less => Some(DifficultyRange { max: Some(value), exclusive: true, ..range }),
+        TokenOperator::LessOrEqual => Some(DifficultyRange { max: Some(value), ..range }),
+        TokenOperator::NotEqual => None,
+    }
+}
+
+/// Applies osu!'s inline filters (`stars>5 ar>=9 length<2m status=ranked creator="some mapper"`) in `query`
+/// to `search` and leaves the remaining words as its text query. Unknown keys are searched for as text.
+pub fn apply_query(query: &str, search: &mut BeatmapsetSearch) -> Result<(), QueryError> {
+    let mut text = Vec::new();
+
+    for token in tokenize(query) {
+        let (key, operator, value) = match split_token(&token) {
+            Some(parts) => parts,
+            None => {
+                text.push(token);
+                continue;
+            }
+        };
+
+        let invalid_value = || QueryError::InvalidValue(key.clone(), value.clone());
+        let unsupported = || QueryError::UnsupportedOperator(key.clone(), operator.symbol().to_string());
+
+        if let Some(attribute) = difficulty_attribute(&key) {
+            let number = match attribute {
+                DifficultyAttribute::Length | DifficultyAttribute::DrainLength => parse_duration(&value),
+                _ => value.parse::<f64>().ok(),
+            }
+            .filter(|number| number.is_finite())
+            .ok_or_else(invalid_value)?;
+
+            search.ranges.push(difficulty_range(attribute, operator, number).ok_or_else(unsupported)?);
+        } else if key == "status" {
+            let status = value.to_lowercase();
+            if !STATUSES.contains(&status.as_str()) {
+                return Err(invalid_value());
+            }
+
+            match operator {
+                TokenOperator::Equal => search.statuses = vec![status],
+                TokenOperator::NotEqual => search.statuses.retain(|existing| *existing != status),
+                _ => return Err(unsupported()),
+            }
+        } else if let Some(attribute) = text_attribute(&key) {
+            if value.is_empty() {
+                return Err(invalid_value());
+            }
+
+            let operator = match operator {
+                TokenOperator::Equal => Operator::Equal,
+                TokenOperator::NotEqual => Operator::NotEqual,
+                _ => return Err(unsupported()),
+            };
+            search.conditions.push(Filter::compare(attribute, operator, value.as_str()));
+        } else {
+            text.push(token);
+        }
+    }
+
+    search.query = text.join(" ");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ops::beatmapset::{BeatmapsetSort, SortField};
+
+    use super::*;
+
+    fn search() -> BeatmapsetSearch {
+        BeatmapsetSearch {
+            query: String::new(),
+            statuses: vec!["ranked".to_string(), "loved".to_string()],
+            modes: vec!["osu".to_string()],
+            ranges: Vec::new(),
+            conditions: Vec::new(),
+            sort: BeatmapsetSort::new(SortField::Updated, true),
+            offset: 0,
+            limit: 50,
+            facets: false,
+            cursor: None,
+        }
+    }
+
+    fn applied(query: &str) -> BeatmapsetSearch {
+        let mut search = search();
+        apply_query(query, &mut search).unwrap();
+        search
+    }
+
+    #[test]
+    fn extracts_filters_from_the_query() {
+        let search = applied("camellia stars>5 ar>=9 length<2m30s status=loved creator=\"some mapper\" re:zero");
+
+        assert_eq!(search.query, "camellia re:zero");
+        assert_eq!(search.statuses, ["loved"]);
+        assert_eq!(
+            search.ranges,
+            vec![
+                DifficultyRange { attribute: DifficultyAttribute::Stars, min: Some(5.0), max: None, exclusive: true },
+                DifficultyRange { attribute: DifficultyAttribute::ApproachRate, min: Some(9.0), max: None, exclusive: false },
+                DifficultyRange { attribute: DifficultyAttribute::Length, min: None, max: Some(150.0), exclusive: true },
+            ]
+        );
+        assert_eq!(search.conditions, vec![Filter::eq("creator", "some mapper")]);
+
+        assert_eq!(applied("status!=loved").statuses, ["ranked"]);
+        assert_eq!(applied("SR=5.2").ranges[0].min, Some(5.2 - 0.005));
+        assert_eq!(applied("\"quoted phrase\" bpm<=180").query, "\"quoted phrase\"");
+    }
+
+    #[test]
+    fn rejects_malformed_filters() {
+        let error = |query: &str| apply_query(query, &mut search()).unwrap_err();
+
+        assert_eq!(error("stars>abc"), QueryError::InvalidValue("stars".to_string(), "abc".to_string()));
+        assert_eq!(error("ar>"), QueryError::InvalidValue("ar".to_string(), String::new()));
+        assert_eq!(error("status=unranked"), QueryError::InvalidValue("status".to_string(), "unranked".to_string()));
+        assert_eq!(error("stars!=5"), QueryError::UnsupportedOperator("stars".to_string(), "!=".to_string()));
+        assert_eq!(error("creator>peppy"), QueryError::UnsupportedOperator("creator".to_string(), ">".to_string()));
+        assert!(matches!(error("length<2x"), QueryError::InvalidValue(..)));
+        assert!(matches!(error("length<"), QueryError::InvalidValue(..)));
+        assert!(matches!(error("bpm>NaN"), QueryError::InvalidValue(..)));
+    }
+}
@@ -0,0 +1,287 @@
+use std::{
+    collections::HashSet,
+    fmt,
+    io::{Read, Seek, Write},
+};
+
+use md5::{Digest, Md5};
+use zip::{result::ZipError, ZipArchive, ZipWriter};
+
+#[derive(Debug)]
+pub enum ArchiveError {
+    Zip(ZipError),
+    Io(std::io::Error),
+    NoDifficulties,
+    ChecksumMismatch,
+}
+
+impl ArchiveError {
+    /// Short label for metrics.
+    pub fn reason(&self) -> &'static str {
+        match self {
+            ArchiveError::Zip(_) => "not_zip",
+            ArchiveError::Io(_) => "io",
+            ArchiveError::NoDifficulties => "no_difficulties",
+            ArchiveError::ChecksumMismatch => "checksum_mismatch",
+        }
+    }
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArchiveError::Zip(err) => write!(f, "Invalid archive: {}", err),
+            ArchiveError::Io(err) => write!(f, "Archive io error: {}", err),
+            ArchiveError::NoDifficulties => write!(f, "Archive contains no .osu files"),
+            ArchiveError::ChecksumMismatch => write!(f, "No .osu file in the archive matches a known checksum"),
+        }
+    }
+}
+
+impl From<ZipError> for ArchiveError {
+    fn from(err: ZipError) -> Self {
+        ArchiveError::Zip(err)
+    }
+}
+
+impl From<std::io::Error> for ArchiveError {
+    fn from(err: std::io::Error) -> Self {
+        ArchiveError::Io(err)
+    }
+}
+
+/// Archive paths use either separator and osu! treats them case-insensitively.
+fn normalize_path(path: &str) -> String {
+    path.trim().trim_matches('"').replace('\\', "/").to_lowercase()
+}
+
+pub fn is_difficulty(name: &str) -> bool {
+    name.to_lowercase().ends_with(".osu")
+}
+
+pub fn md5_hex(data: &[u8]) -> String {
+    hex::encode(Md5::digest(data))
+}
+
+/// Checks that `source` is a zip holding at least one `.osu` whose MD5 is in `checksums`,
+/// which is what tells a real beatmap archive apart from an error page or a truncated file.
+pub fn verify_archive<R: Read + Seek>(source: R, checksums: &[String]) -> Result<(), ArchiveError> {
+    let mut archive = ZipArchive::new(source)?;
+    let mut difficulties = 0;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if !is_difficulty(file.name()) {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        difficulties += 1;
+
+        let checksum = md5_hex(&content);
+        if checksums.iter().any(|expected| expected.eq_ignore_ascii_case(&checksum)) {
+            return Ok(());
+        }
+    }
+
+    match difficulties {
+        0 => Err(ArchiveError::NoDifficulties),
+        _ => Err(ArchiveError::ChecksumMismatch),
+    }
+}
+
+/// The `.osu` in `source` whose MD5 is `checksum`, if any.
+pub fn extract_difficulty<R: Read + Seek>(source: R, checksum: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+    let mut archive = ZipArchive::new(source)?;
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if !is_difficulty(file.name()) {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        if md5_hex(&content).eq_ignore_ascii_case(checksum) {
+            return Ok(Some(content));
+        }
+    }
+
+    Ok(None)
+}
+
+/// The file at `path` (as referenced by a `.osu`, so compared case-insensitively) in `source`.
+pub fn extract_file<R: Read + Seek>(source: R, path: &str) -> Result<Option<Vec<u8>>, ArchiveError> {
+    let mut archive = ZipArchive::new(source)?;
+    let path = normalize_path(path);
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if normalize_path(file.name()) != path {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        return Ok(Some(content));
+    }
+
+    Ok(None)
+}
+
+/// Files referenced by `Video` events (`Video,offset,"file"` or `1,offset,"file"`) of a `.osu`.
+pub fn video_files(osu: &str) -> Vec<String> {
+    let mut in_events = false;
+    let mut videos = Vec::new();
+
+    for line in osu.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            in_events = line == "[Events]";
+            continue;
+        }
+
+        if !in_events || line.starts_with("//") {
+            continue;
+        }
+
+        let mut fields = line.splitn(3, ',');
+        let kind = fields.next().unwrap_or_default().trim();
+        let _offset = fields.next();
+
+        if let (true, Some(file)) = (kind == "Video" || kind == "1", fields.next()) {
+            // Anything after the file name (x/y offsets) is not part of it.
+            let file = match file.trim().strip_prefix('"') {
+                Some(quoted) => quoted.split('"').next().unwrap_or_default(),
+                None => file.split(',').next().unwrap_or_default(),
+            };
+            videos.push(normalize_path(file));
+        }
+    }
+
+    videos
+}
+
+/// Copies `source` into `destination` without the videos referenced by its difficulties,
+/// entries are copied as-is without being recompressed. Returns the removed paths.
+pub fn strip_videos<R: Read + Seek, W: Write + Seek>(source: R, destination: W) -> Result<Vec<String>, ArchiveError> {
+    let mut archive = ZipArchive::new(source)?;
+    let mut videos = HashSet::new();
+
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        if !is_difficulty(file.name()) {
+            continue;
+        }
+
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+        videos.extend(video_files(&String::from_utf8_lossy(&content)));
+    }
+
+    let mut writer = ZipWriter::new(destination);
+    let mut removed = Vec::new();
+
+    for index in 0..archive.len() {
+        let file = archive.by_index_raw(index)?;
+
+        if videos.contains(&normalize_path(file.name())) {
+            removed.push(file.name().to_string());
+            continue;
+        }
+
+        writer.raw_copy_file(file)?;
+    }
+
+    writer.finish()?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::testing::{build_archive, fixture_archive, fixture_beatmapset};
+
+    use super::*;
+
+    fn names(archive: &[u8]) -> Vec<String> {
+        let mut archive = ZipArchive::new(Cursor::new(archive)).unwrap();
+        (0..archive.len()).map(|index| archive.by_index(index).unwrap().name().to_string()).collect()
+    }
+
+    const DIFFICULTY: &str = "osu file format v14\r\n\r\n[Events]\r\n//Background and Video events\r\n\
+        0,0,\"bg.jpg\",0,0\r\nVideo,-120,\"Video\\Intro.MP4\"\r\n1,0,clip.avi\r\n\r\n[TimingPoints]\r\n0,500,4,2,0,100,1,0\r\n";
+
+    #[test]
+    fn verifies_difficulty_checksums() {
+        let archive = build_archive(&[("map [Normal].osu", DIFFICULTY.as_bytes()), ("bg.jpg", b"jpeg")]);
+        let checksum = md5_hex(DIFFICULTY.as_bytes());
+
+        assert!(verify_archive(Cursor::new(&archive), &["0".repeat(32), checksum.to_uppercase()]).is_ok());
+        assert!(matches!(
+            verify_archive(Cursor::new(&archive), &["0".repeat(32)]),
+            Err(ArchiveError::ChecksumMismatch)
+        ));
+        assert!(matches!(
+            verify_archive(Cursor::new(build_archive(&[("bg.jpg", b"jpeg")])), std::slice::from_ref(&checksum)),
+            Err(ArchiveError::NoDifficulties)
+        ));
+        assert!(matches!(
+            verify_archive(Cursor::new(b"<html>Service Unavailable</html>"), &[checksum]),
+            Err(ArchiveError::Zip(_))
+        ));
+    }
+
+    #[test]
+    fn extracts_difficulty_by_checksum() {
+        let beatmapset = fixture_beatmapset();
+        let hard = beatmapset.beatmaps.iter().find(|beatmap| beatmap.version == "Hard").unwrap();
+        let checksum = hard.checksum.clone().unwrap();
+
+        let content = extract_difficulty(Cursor::new(fixture_archive()), &checksum).unwrap().unwrap();
+
+        assert_eq!(md5_hex(&content), checksum);
+        assert!(String::from_utf8_lossy(&content).contains("Version:Hard"));
+        assert!(extract_difficulty(Cursor::new(fixture_archive()), &"0".repeat(32)).unwrap().is_none());
+    }
+
+    #[test]
+    fn extracts_referenced_files() {
+        let archive = build_archive(&[("map [Normal].osu", DIFFICULTY.as_bytes()), ("SB\\BG.jpg", b"jpeg")]);
+
+        assert_eq!(extract_file(Cursor::new(&archive), "sb/bg.JPG").unwrap().unwrap(), b"jpeg");
+        assert!(extract_file(Cursor::new(&archive), "audio.mp3").unwrap().is_none());
+    }
+
+    #[test]
+    fn finds_video_events() {
+        assert_eq!(video_files(DIFFICULTY), vec!["video/intro.mp4", "clip.avi"]);
+        assert!(video_files("[General]\r\nVideo,0,\"not-an-event.mp4\"\r\n").is_empty());
+    }
+
+    #[test]
+    fn strips_referenced_videos_only() {
+        let source = build_archive(&[
+            ("map [Normal].osu", DIFFICULTY.as_bytes()),
+            ("bg.jpg", b"jpeg"),
+            ("video/intro.mp4", b"mp4"),
+            ("clip.avi", b"avi"),
+            ("unreferenced.mp4", b"mp4"),
+        ]);
+
+        let mut destination = Cursor::new(Vec::new());
+        let removed = strip_videos(Cursor::new(source), &mut destination).unwrap();
+
+        assert_eq!(removed, vec!["video/intro.mp4", "clip.avi"]);
+        assert_eq!(
+            names(destination.get_ref()),
+            vec!["map [Normal].osu", "bg.jpg", "unreferenced.mp4"]
+        );
+    }
+}
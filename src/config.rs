@@ -1,3 +1,6 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use confy::ConfyError;
 use serde_derive::{Serialize, Deserialize};
 
 pub const CONFIG_VERSION: i32 = 3;
@@ -5,9 +8,138 @@ pub const CONFIG_VERSION: i32 = 3;
 #[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct Meili {
     pub url: String,
-    pub key: String
+    pub key: String,
+    /// Put in front of every index name, so several instances can share one Meilisearch.
+    #[serde(default)]
+    pub index_prefix: String
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct S3 {
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key: String,
+    pub secret_key: String
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Http {
+    /// Seconds to wait for a connection to osu!.
+    pub connect_timeout: u64,
+    /// Seconds a whole request, including the body, may take. Archives streamed to the storage
+    /// or to clients are only bound by `read_timeout`, as they may take any time.
+    pub timeout: u64,
+    /// Seconds a streamed body may go without receiving anything.
+    #[serde(default = "default_read_timeout")]
+    pub read_timeout: u64,
+    pub max_retries: u32,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64
+}
+
+fn default_read_timeout() -> u64 {
+    30
 }
 
+impl ::std::default::Default for Http {
+    fn default() -> Self {
+        Self {
+            connect_timeout: 10,
+            timeout: 300,
+            read_timeout: default_read_timeout(),
+            max_retries: 4,
+            backoff_base_ms: 500,
+            backoff_max_ms: 30_000
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimit {
+    pub per_minute: u32,
+    pub burst: u32
+}
+
+fn default_reconcile_rate_limit() -> RateLimit {
+    RateLimit { per_minute: 10, burst: 2 }
+}
+
+fn default_asset_rate_limit() -> RateLimit {
+    RateLimit { per_minute: 120, burst: 20 }
+}
+
+/// Separate budgets, so bursts of downloads don't starve the crawler and vice versa.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RateLimits {
+    pub api: RateLimit,
+    pub download: RateLimit,
+    /// Batched lookups of the reconciliation sweep, kept low so it never slows down crawling.
+    #[serde(default = "default_reconcile_rate_limit")]
+    pub reconcile: RateLimit,
+    /// Covers and other static files proxied from osu!'s asset server.
+    #[serde(default = "default_asset_rate_limit")]
+    pub assets: RateLimit
+}
+
+impl ::std::default::Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            api: RateLimit { per_minute: 60, burst: 10 },
+            download: RateLimit { per_minute: 30, burst: 5 },
+            reconcile: default_reconcile_rate_limit(),
+            assets: default_asset_rate_limit()
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CrawlPass {
+    pub status: String,
+    /// Seconds from the end of one sweep of this status to the start of the next.
+    pub interval: i64
+}
+
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
+pub struct CrawlPassState {
+    pub cursor: String,
+    pub last_completed: i64
+}
+
+fn default_crawl_passes() -> Vec<CrawlPass> {
+    [
+        ("ranked", 60 * 60),
+        ("loved", 60 * 60),
+        ("qualified", 60 * 60),
+        ("pending", 60 * 60 * 24),
+        ("wip", 60 * 60 * 24),
+        ("graveyard", 60 * 60 * 24 * 7),
+    ]
+    .iter()
+    .map(|&(status, interval)| CrawlPass { status: status.to_string(), interval })
+    .collect()
+}
+
+fn default_osu_base_url() -> String {
+    String::from("https://osu.ppy.sh")
+}
+
+/// Credentials of the official osu! client, which is allowed to use the password grant.
+fn default_osu_client_id() -> String {
+    String::from("5")
+}
+
+fn default_osu_client_secret() -> String {
+    String::from("FGc9GAtyHzeQDshWP5Ah7dega8hJACAJpQtw6OXk")
+}
+
+fn default_storage() -> String {
+    String::from("local")
+}
+
+fn default_reconcile_interval() -> i64 {
+    60 * 60 * 24
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Configuration {
@@ -17,9 +149,39 @@ pub struct Configuration {
     pub osu_access_token: String,
     pub osu_refresh_token: String,
     pub osu_token_expires_at: i64,
-    pub cursor: String,
+    /// Root of the osu! website, every API and OAuth endpoint is resolved against it.
+    #[serde(default = "default_osu_base_url")]
+    pub osu_base_url: String,
+    #[serde(default = "default_osu_client_id")]
+    pub osu_client_id: String,
+    #[serde(default = "default_osu_client_secret")]
+    pub osu_client_secret: String,
     pub meilisearch: Meili,
-    pub beatmaps_folder: String
+    pub beatmaps_folder: String,
+    /// Archive storage backend, either `local` (the beatmaps folder) or `s3`.
+    #[serde(default = "default_storage")]
+    pub storage: String,
+    #[serde(default)]
+    pub s3: S3,
+    #[serde(default)]
+    pub http: Http,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    #[serde(default = "default_crawl_passes")]
+    pub crawl_passes: Vec<CrawlPass>,
+    #[serde(default)]
+    pub crawl_pass_state: HashMap<String, CrawlPassState>,
+    /// Seconds between two reconciliation sweeps over the indexed beatmapsets.
+    #[serde(default = "default_reconcile_interval")]
+    pub reconcile_interval: i64,
+    #[serde(default)]
+    pub reconcile_cursor: i64,
+    /// When the last sweep finished, the next one starts `reconcile_interval` seconds after it.
+    #[serde(default)]
+    pub reconcile_completed: i64,
+    /// Serves cheesegull's `/api/s`, `/api/b`, `/api/md5` and `/api/search` for private servers built against it.
+    #[serde(default)]
+    pub cheesegull_api: bool
 }
 
 
@@ -32,9 +194,21 @@ impl ::std::default::Default for Configuration  {
             osu_access_token: String::new(),
             osu_refresh_token: String::new(),
             osu_token_expires_at: 0,
-            cursor: String::new(),
+            osu_base_url: default_osu_base_url(),
+            osu_client_id: default_osu_client_id(),
+            osu_client_secret: default_osu_client_secret(),
             meilisearch: Default::default(),
-            beatmaps_folder: String::new()
+            beatmaps_folder: String::new(),
+            storage: default_storage(),
+            s3: Default::default(),
+            http: Default::default(),
+            rate_limits: Default::default(),
+            crawl_passes: default_crawl_passes(),
+            crawl_pass_state: HashMap::new(),
+            reconcile_interval: default_reconcile_interval(),
+            reconcile_cursor: 0,
+            reconcile_completed: 0,
+            cheesegull_api: false
         }
     }
 }
@@ -45,6 +219,34 @@ impl Configuration {
     }
 }
 
+/// Location of the stored configuration, `MIRRIA_CONFIG_PATH` overrides confy's default one.
+pub fn config_path() -> Result<PathBuf, ConfyError> {
+    match std::env::var_os("MIRRIA_CONFIG_PATH") {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => confy::get_configuration_file_path("mirria", None),
+    }
+}
+
+pub fn load_stored() -> Result<Configuration, ConfyError> {
+    confy::load_path(config_path()?)
+}
+
+pub fn store(config: Configuration) -> Result<(), ConfyError> {
+    confy::store_path(config_path()?, config)
+}
+
+static STORE_LOCK: Mutex<()> = Mutex::new(());
+
+/// Reloads the stored configuration, applies `update` and stores it back.
+/// Crawler loops and token refreshes run concurrently, so every write goes through here.
+pub fn update_stored(update: impl FnOnce(&mut Configuration)) -> Result<(), ConfyError> {
+    let _guard = STORE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    let mut config = load_stored()?;
+    update(&mut config);
+    store(config)
+}
+
 #[derive(clap::Parser, Clone)]
 pub struct Config {
     #[clap(long, env)]
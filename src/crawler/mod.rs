@@ -1,89 +1,458 @@
-use std::{sync::Arc, time::{Duration, Instant}};
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
 
-use meilisearch_sdk::client::Client;
+use axum_prometheus::metrics_exporter_prometheus::PrometheusBuilder;
+use chrono::{DateTime, Local};
+use meilisearch_sdk::{client::Client, indexes::Index};
 use tokio::{sync::Mutex, time};
 use tracing::{error, info, warn};
 
 use crate::{
-    config::Configuration,
-    osu::client::{OsuApi, OsuClient},
+    config::{update_stored, Configuration, CrawlPass, CrawlPassState},
+    ops::beatmapset::{add_beatmapsets, get_beatmapsets_after_id, get_beatmapsets_by_ids, get_latest_updated_beatmapset},
+    osu::{client::{OsuApi, OsuClient, OsuError}, types::{Beatmapset, Covers}},
+    storage::{archive_key, cover_key, derived_keys, BeatmapStorage, Storage},
 };
 
+/// Upper bound of pages walked by a single recent pass when there is nothing indexed yet,
+/// otherwise the first pass on a fresh install would walk the whole catalogue backwards.
+const RECENT_MAX_PAGES: usize = 20;
+
+/// Indexed sets checked per request, the most `/api/v2/beatmaps` looks up at once.
+const RECONCILE_BATCH_SIZE: usize = 50;
+
 #[derive(Clone, Debug)]
 pub struct Context {
     pub config: Arc<Configuration>,
     pub meili_client: Arc<Client>,
-    pub osu: OsuClient
+    pub osu: OsuClient,
+    pub storage: Storage
+}
+
+impl Context {
+    /// Index `name` behind the configured prefix.
+    pub fn index(&self, name: &str) -> Index {
+        self.meili_client.index(format!("{}{}", self.config.meilisearch.index_prefix, name))
+    }
+}
+
+
+fn last_updated_timestamp(beatmapset: &Beatmapset) -> Option<i64> {
+    DateTime::parse_from_rfc3339(&beatmapset.last_updated)
+        .ok()
+        .map(|date| date.timestamp())
 }
 
+/// osu!'s search results carry no genre or language. Sets indexed with them keep theirs,
+/// the others are looked up in full once, so every set costs one extra request over its lifetime.
+async fn with_genre_and_language(context: &mut Context, mut beatmapsets: Vec<Beatmapset>) -> Vec<Beatmapset> {
+    let ids: Vec<i64> = beatmapsets.iter().map(|beatmapset| beatmapset.mapset_id).collect();
+    let indexed: HashMap<i64, Beatmapset> = match get_beatmapsets_by_ids(context.to_owned(), &ids).await {
+        Ok(indexed) => indexed.into_iter().map(|beatmapset| (beatmapset.mapset_id, beatmapset)).collect(),
+        Err(_) => return beatmapsets,
+    };
+
+    for beatmapset in beatmapsets.iter_mut() {
+        if beatmapset.genre.is_some() && beatmapset.language.is_some() {
+            continue;
+        }
 
-async fn crawl_search(context: Mutex<Context>) {
-    let cursor = Mutex::new(String::new());
-    *cursor.lock().await = context.lock().await.config.cursor.clone();
-    let mut last_save = Instant::now();
+        let details = match indexed.get(&beatmapset.mapset_id) {
+            Some(indexed) if indexed.genre.is_some() && indexed.language.is_some() => indexed.clone(),
+            _ => match context.osu.fetch_beatmapset(beatmapset.mapset_id).await {
+                Ok(details) => details,
+                Err(err) => {
+                    //Indexed without them, the next sweep tries again
+                    warn!("Failed to look up genre and language of {} ({})", beatmapset.mapset_id, err);
+                    continue;
+                }
+            },
+        };
+
+        beatmapset.genre = details.genre;
+        beatmapset.language = details.language;
+    }
+
+    beatmapsets
+}
+
+/// Polls `updated_desc` from the top until it reaches sets which are already indexed,
+/// so new and re-uploaded maps show up without waiting for the backfill.
+async fn crawl_recent(context: Mutex<Context>) {
+    let mut watermark = match get_latest_updated_beatmapset(context.lock().await.to_owned()).await {
+        Ok(beatmapset) => last_updated_timestamp(&beatmapset),
+        Err(_) => None,
+    };
 
     loop {
         let mut context = context.lock().await;
-        let mut cursor = cursor.lock().await;
-        info!("Crawling beatmaps with cursor {}", cursor);
+        let mut cursor: Option<String> = None;
+        let mut newest: Option<i64> = None;
+        let mut pages = 0;
+
+        info!("Crawling recently updated beatmaps, watermark: {:?}", watermark);
+
+        loop {
+            let beatmaps = context
+                .osu
+                .search_beatmapsets(
+                    true,
+                    String::from("updated_desc"),
+                    String::from("any"),
+                    cursor.clone(),
+                )
+                .await;
+
+            if let Err(err) = &beatmaps {
+                warn!("Failed to crawl recent maps ({}), gonna retry on the next pass.", err);
+                break;
+            }
+
+            let beatmaps = beatmaps.unwrap();
+            let crawled_beatmaps = with_genre_and_language(&mut context, beatmaps.beatmapsets).await;
+            pages += 1;
+
+            let timestamps: Vec<i64> = crawled_beatmaps.iter().filter_map(last_updated_timestamp).collect();
+            if let Some(&max) = timestamps.iter().max() {
+                newest = Some(newest.map_or(max, |newest| newest.max(max)));
+            }
+
+            info!("Crawled {} recently updated beatmaps", crawled_beatmaps.len());
+
+            if let Err(err) = add_beatmapsets(&context, &crawled_beatmaps).await {
+                error!("{}", err);
+                break;
+            }
+
+            let reached_indexed = match watermark {
+                Some(watermark) => timestamps.iter().any(|&timestamp| timestamp <= watermark),
+                None => pages >= RECENT_MAX_PAGES,
+            };
+
+            if reached_indexed || crawled_beatmaps.len() < 50 || beatmaps.cursor_string.is_none() {
+                break;
+            }
+
+            cursor = beatmaps.cursor_string;
+        }
+
+        if newest.is_some() && newest > watermark {
+            watermark = newest;
+        }
+
+        let _ = time::sleep(Duration::from_secs(30)).await;
+    }
+}
+
+fn store_pass_state(status: &str, state: &CrawlPassState) {
+    let result = update_stored(|config| {
+        config.crawl_pass_state.insert(status.to_string(), state.clone());
+    });
+
+    if let Err(err) = result {
+        error!("Failed to save {} pass state: {}", status, err);
+    }
+}
+
+/// Sweeps a single status from its persisted cursor to the end, then waits until
+/// the pass is due again.
+async fn crawl_status(context: Mutex<Context>, pass: CrawlPass) {
+    let mut state = context
+        .lock()
+        .await
+        .config
+        .crawl_pass_state
+        .get(&pass.status)
+        .cloned()
+        .unwrap_or_default();
+
+    loop {
+        let next_run = state.last_completed + pass.interval;
+        let now = Local::now().timestamp();
+
+        if state.cursor.is_empty() && now < next_run {
+            let _ = time::sleep(Duration::from_secs((next_run - now) as u64)).await;
+            continue;
+        }
+
+        let mut context = context.lock().await;
+        info!("Crawling {} beatmaps with cursor {}", pass.status, state.cursor);
 
         let beatmaps = context
             .osu
             .search_beatmapsets(
                 true,
                 String::from("updated_asc"),
-                String::from("any"),
-                Some(cursor.to_owned()),
+                pass.status.clone(),
+                Some(state.cursor.clone()),
             )
             .await;
-        //Setting new cursor
 
-        if !beatmaps.is_some() {
-            warn!("Failed to crawl maps, gonna retry in 1 minute.");
+        if let Err(err) = &beatmaps {
+            warn!("Failed to crawl {} maps ({}), gonna retry in 1 minute.", pass.status, err);
             let _ = time::sleep(Duration::from_secs(60)).await;
             continue;
         }
 
         let beatmaps = beatmaps.unwrap();
+        let crawled_beatmaps = with_genre_and_language(&mut context, beatmaps.beatmapsets).await;
+        info!("Crawled {} {} beatmaps", crawled_beatmaps.len(), pass.status);
+
+        if let Err(err) = add_beatmapsets(&context, &crawled_beatmaps).await {
+            error!("{}", err);
+            let _ = time::sleep(Duration::from_secs(60)).await;
+            continue;
+        }
+
+        match beatmaps.cursor_string {
+            Some(cursor) if crawled_beatmaps.len() >= 50 => {
+                state.cursor = cursor;
+            }
+            _ => {
+                info!("Finished {} pass, next one in {} seconds", pass.status, pass.interval);
+                state.cursor = String::new();
+                state.last_completed = Local::now().timestamp();
+            }
+        }
+
+        store_pass_state(&pass.status, &state);
+    }
+}
 
+async fn remove_cached_archive(storage: &Storage, beatmapset: &Beatmapset) {
+    let id = beatmapset.mapset_id;
+    let version = beatmapset.covers_version();
+    let covers = Covers::KINDS.iter().map(|kind| cover_key(id, &version, kind));
 
-        if Instant::now().duration_since(last_save) > Duration::from_secs(30) {
-            last_save = Instant::now();
-            let mut config: Configuration = confy::load("mirria", None).unwrap();
-            config.cursor = cursor.to_string();
-            confy::store("mirria", None, config).unwrap();
-            info!("Saving cursor to config");
+    for key in [archive_key(id)].into_iter().chain(derived_keys(id)).chain(covers) {
+        match storage.delete(&key).await {
+            Ok(_) => info!("Removed cached {}", key),
+            Err(err) => error!("Failed to remove cached {}: {}", key, err),
         }
+    }
+}
+
+fn store_reconcile_state(cursor: i64, completed: i64) {
+    let result = update_stored(|config| {
+        config.reconcile_cursor = cursor;
+        config.reconcile_completed = completed;
+    });
+
+    if let Err(err) = result {
+        error!("Failed to save reconciliation cursor: {}", err);
+    }
+}
+
+/// Re-checks every indexed beatmapset against osu!, one batched beatmap lookup per page of sets.
+/// Sets missing from a lookup are fetched on their own and marked deleted when osu! doesn't know
+/// them anymore. Availability is kept current by the crawl passes, as search results carry it,
+/// so the cached files of deleted and taken-down sets are dropped here.
+async fn reconcile_beatmapsets(context: Mutex<Context>) {
+    let (mut cursor, mut completed, interval) = {
+        let context = context.lock().await;
+        (context.config.reconcile_cursor, context.config.reconcile_completed, context.config.reconcile_interval)
+    };
 
-        let crawled_beatmaps = beatmaps.beatmapsets;
-        info!("Crawled {} beatmaps", crawled_beatmaps.len());
-     
-        let index = context.meili_client.index("beatmapset");
+    loop {
+        let next_run = completed + interval;
+        let now = Local::now().timestamp();
 
-        let result = index.add_documents(&crawled_beatmaps.to_vec(), Some("id")).await;
-        if result.is_err() {
-            error!("{}", result.err().unwrap());
-            break;
+        if cursor == 0 && now < next_run {
+            let _ = time::sleep(Duration::from_secs((next_run - now) as u64)).await;
+            continue;
         }
 
-        if crawled_beatmaps.len() < 50 {
-            info!("End of search reached, waiting 3 minutes for new beatmaps");
-            let _ = time::sleep(Duration::from_secs(60*3)).await;
+        let mut context = context.lock().await;
+        let beatmapsets = get_beatmapsets_after_id(context.to_owned(), cursor, RECONCILE_BATCH_SIZE).await;
+
+        if beatmapsets.is_err() {
+            warn!("Failed to fetch indexed beatmapsets, gonna retry in 1 minute.");
+            let _ = time::sleep(Duration::from_secs(60)).await;
             continue;
         }
 
-        if let Some(beatmap_cursor) = beatmaps.cursor_string {
-            *cursor = beatmap_cursor;
+        let beatmapsets = beatmapsets.unwrap();
+
+        if beatmapsets.is_empty() {
+            info!("Reconciliation finished, next one in {} seconds", interval);
+            cursor = 0;
+            completed = Local::now().timestamp();
+            store_reconcile_state(cursor, completed);
+            continue;
         }
 
-        let _ = time::sleep(Duration::from_secs(3)).await;
+        let ids: Vec<i64> = beatmapsets
+            .iter()
+            .filter(|beatmapset| beatmapset.deleted_at.is_none())
+            .filter_map(|beatmapset| beatmapset.beatmaps.first().map(|beatmap| beatmap.map_id))
+            .collect();
+
+        let found: HashSet<i64> = match context.osu.lookup_beatmaps(&ids).await {
+            Ok(beatmaps) => beatmaps.into_iter().map(|beatmap| beatmap.mapset_id).collect(),
+            Err(err) => {
+                warn!("Failed to look up beatmaps ({}), gonna retry in 1 minute.", err);
+                let _ = time::sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+        };
+
+        let mut failed = false;
+
+        for indexed in beatmapsets {
+            let mut current = indexed.clone();
+
+            // A missing beatmap may also have been removed from a set which is still there.
+            if indexed.deleted_at.is_none() && !found.contains(&indexed.mapset_id) {
+                current = match context.osu.fetch_beatmapset(indexed.mapset_id).await {
+                    Ok(beatmapset) => beatmapset,
+                    Err(OsuError::NotFound) => Beatmapset {
+                        deleted_at: Some(Local::now().to_rfc3339()),
+                        ..indexed.clone()
+                    },
+                    Err(err) => {
+                        //The cursor stays before the set so it is checked again
+                        warn!("Failed to reconcile {} ({}), gonna retry in 1 minute.", indexed.mapset_id, err);
+                        failed = true;
+                        break;
+                    }
+                };
+            }
+
+            if current.deleted_at.is_some() || current.availability.download_disabled {
+                remove_cached_archive(&context.storage, &current).await;
+            }
+
+            if current.deleted_at != indexed.deleted_at || current.availability != indexed.availability {
+                info!("Beatmapset {} changed availability, deleted at: {:?}", current.mapset_id, current.deleted_at);
+
+                if let Err(err) = add_beatmapsets(&context, std::slice::from_ref(&current)).await {
+                    error!("{}", err);
+                }
+            }
+
+            cursor = indexed.mapset_id;
+        }
+
+        store_reconcile_state(cursor, completed);
+
+        if failed {
+            let _ = time::sleep(Duration::from_secs(60)).await;
+        }
     }
 }
 
 pub async fn serve(context: Context) {
-    let crawler_ctx = Mutex::new(context.clone());
+    if let Err(err) = PrometheusBuilder::new().install() {
+        error!("Failed to start metrics exporter: {}", err);
+    }
+
+    let recent_ctx = Mutex::new(context.clone());
+    let reconcile_ctx = Mutex::new(context.clone());
+
+    let mut handles = vec![
+        tokio::spawn(async move {
+            crawl_recent(recent_ctx).await
+        }),
+        tokio::spawn(async move {
+            reconcile_beatmapsets(reconcile_ctx).await
+        }),
+    ];
+
+    for pass in context.config.crawl_passes.clone() {
+        let pass_ctx = Mutex::new(context.clone());
+        handles.push(tokio::spawn(async move {
+            crawl_status(pass_ctx, pass).await
+        }));
+    }
+
+    for handle in handles {
+        let _ = handle.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, future::Future, path::Path};
+
+    use crate::{
+        ops::beatmapset::get_beatmapset_by_id,
+        testing::{context, generated_beatmapsets, index_beatmapsets, FakeOsu},
+    };
+
+    use super::*;
+
+    /// Polls `check` for up to 30 seconds, the crawl loops index in the background.
+    async fn eventually<F: Future<Output = bool>>(mut check: impl FnMut() -> F) -> bool {
+        for _ in 0..60 {
+            if check().await {
+                return true;
+            }
+            time::sleep(Duration::from_millis(500)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn crawl_status_indexes_every_page() {
+        let beatmapsets = generated_beatmapsets(500_000, 120);
+        let pass = CrawlPass { status: beatmapsets[0].status.clone(), interval: 60 * 60 };
+        let fake = FakeOsu::with_beatmapsets(beatmapsets, HashMap::new()).await;
+        let context = context(&fake, "crawl_status").await;
+
+        let crawler = tokio::spawn(crawl_status(Mutex::new(context.clone()), pass));
+        let indexed = eventually(|| async { get_beatmapset_by_id(context.clone(), 500_119).await.is_ok() }).await;
+        crawler.abort();
+
+        assert!(indexed, "last generated beatmapset was never indexed");
+        let first = get_beatmapset_by_id(context.clone(), 500_000).await.unwrap();
+        assert!(fake.requests("/api/v2/beatmapsets/search") >= 3);
+        // Search results carry no genre or language, every set is looked up in full once for them.
+        assert_eq!(first.genre.unwrap().name, "Video Game");
+        assert_eq!(first.language.unwrap().name, "Instrumental");
+        assert_eq!(fake.requests("/api/v2/beatmapsets/500000"), 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn crawl_recent_walks_back_from_the_newest() {
+        let fake = FakeOsu::with_beatmapsets(generated_beatmapsets(600_000, 120), HashMap::new()).await;
+        let context = context(&fake, "crawl_recent").await;
+
+        let crawler = tokio::spawn(crawl_recent(Mutex::new(context.clone())));
+        let indexed = eventually(|| async { get_beatmapset_by_id(context.clone(), 600_000).await.is_ok() }).await;
+        crawler.abort();
 
-    let _ = tokio::try_join!(tokio::spawn(async move {
-        crawl_search(crawler_ctx).await
-    }));
+        assert!(indexed, "oldest generated beatmapset was never indexed");
+        assert!(get_beatmapset_by_id(context.clone(), 600_119).await.is_ok());
+        assert!(fake.requests("/api/v2/beatmapsets/search") >= 3);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn reconcile_marks_deleted_sets_and_drops_their_files() {
+        let beatmapsets = generated_beatmapsets(700_000, 3);
+        let fake = FakeOsu::with_beatmapsets(beatmapsets[..2].to_vec(), HashMap::new()).await;
+        let context = context(&fake, "reconcile").await;
+
+        index_beatmapsets(&context, &beatmapsets).await;
+        let cached = Path::new(&context.config.beatmaps_folder).join(archive_key(700_002));
+        std::fs::write(&cached, b"archive").unwrap();
+
+        let reconciler = tokio::spawn(reconcile_beatmapsets(Mutex::new(context.clone())));
+        let deleted = eventually(|| async {
+            get_beatmapset_by_id(context.clone(), 700_002)
+                .await
+                .is_ok_and(|beatmapset| beatmapset.deleted_at.is_some())
+        })
+        .await;
+        reconciler.abort();
+
+        assert!(deleted, "beatmapset missing on osu! was never marked deleted");
+        assert!(!cached.exists());
+        assert!(get_beatmapset_by_id(context.clone(), 700_000).await.is_ok());
+        // One batched lookup, only the missing set is fetched on its own.
+        assert_eq!(fake.requests("/api/v2/beatmaps"), 1);
+        assert_eq!(fake.requests("/api/v2/beatmapsets/700002"), 1);
+        assert_eq!(fake.requests("/api/v2/beatmapsets/700000"), 0);
+    }
 }
@@ -0,0 +1,252 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::osu::parser::OsuFile;
+
+use self::{
+    mods::Mods,
+    objects::{difficulty_range, Scaling},
+    performance::PERFORMANCE_BASE_MULTIPLIER,
+    skills::{Aim, Flashlight, Speed},
+};
+
+pub mod mods;
+mod objects;
+pub mod path;
+pub mod performance;
+mod skills;
+
+const DIFFICULTY_MULTIPLIER: f64 = 0.0675;
+
+#[derive(Debug)]
+pub enum CalculateError {
+    UnsupportedMode(u8),
+}
+
+impl std::error::Error for CalculateError {}
+
+impl fmt::Display for CalculateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CalculateError::UnsupportedMode(mode) => write!(f, "Difficulty calculation is not supported for mode {}", mode),
+        }
+    }
+}
+
+/// Star rating and what the performance calculation needs, for one set of mods.
+/// Approach rate and overall difficulty include the clock rate.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DifficultyAttributes {
+    pub star_rating: f64,
+    pub aim: f64,
+    pub speed: f64,
+    pub flashlight: f64,
+    pub slider_factor: f64,
+    pub speed_note_count: f64,
+    pub approach_rate: f64,
+    pub overall_difficulty: f64,
+    pub circle_size: f64,
+    pub drain_rate: f64,
+    pub clock_rate: f64,
+    pub max_combo: u32,
+    pub circles: u32,
+    pub sliders: u32,
+    pub spinners: u32,
+}
+
+/// osu!standard difficulty of `osu` with `mods`, ported from osu!'s difficulty calculator as it was
+/// before the 2024 rework (difficulty multiplier 0.0675, performance base multiplier 1.14).
+pub fn calculate_difficulty(osu: &OsuFile, mods: Mods) -> Result<DifficultyAttributes, CalculateError> {
+    if osu.general.mode != 0 {
+        return Err(CalculateError::UnsupportedMode(osu.general.mode));
+    }
+
+    let clock_rate = mods.clock_rate();
+    let circle_size = mods.adjust(osu.difficulty.circle_size, true);
+    let approach_rate = mods.adjust(osu.difficulty.approach_rate, false);
+    let overall_difficulty = mods.adjust(osu.difficulty.overall_difficulty, false);
+    let drain_rate = mods.adjust(osu.difficulty.hp_drain_rate, false);
+
+    let scaling = Scaling::new(circle_size, approach_rate, mods);
+    let great_window = difficulty_range(overall_difficulty, 80.0, 50.0, 20.0);
+
+    let hit_objects = objects::convert(osu, mods, &scaling);
+    let difficulty_objects = skills::difficulty_objects(&hit_objects, &scaling, clock_rate, great_window);
+
+    let mut aim = Aim::new(true);
+    let mut aim_no_sliders = Aim::new(false);
+    let mut speed = Speed::default();
+    let mut flashlight = Flashlight::new(scaling, mods.contains(Mods::HIDDEN));
+    skills::process(&difficulty_objects, &mut aim, &mut aim_no_sliders, &mut speed, &mut flashlight);
+
+    let mut aim_rating = aim.difficulty_value().sqrt() * DIFFICULTY_MULTIPLIER;
+    let aim_no_sliders_rating = aim_no_sliders.difficulty_value().sqrt() * DIFFICULTY_MULTIPLIER;
+    let mut speed_rating = speed.difficulty_value().sqrt() * DIFFICULTY_MULTIPLIER;
+    let mut flashlight_rating = match mods.contains(Mods::FLASHLIGHT) {
+        true => flashlight.difficulty_value().sqrt() * DIFFICULTY_MULTIPLIER,
+        false => 0.0,
+    };
+
+    let slider_factor = if aim_rating > 0.0 { aim_no_sliders_rating / aim_rating } else { 1.0 };
+
+    if mods.contains(Mods::TOUCH_DEVICE) {
+        aim_rating = aim_rating.powf(0.8);
+        flashlight_rating = flashlight_rating.powf(0.8);
+    }
+    if mods.contains(Mods::RELAX) {
+        aim_rating *= 0.9;
+        speed_rating = 0.0;
+        flashlight_rating *= 0.7;
+    }
+
+    let base_aim = (5.0 * (aim_rating / DIFFICULTY_MULTIPLIER).max(1.0) - 4.0).powi(3) / 100_000.0;
+    let base_speed = (5.0 * (speed_rating / DIFFICULTY_MULTIPLIER).max(1.0) - 4.0).powi(3) / 100_000.0;
+    let base_flashlight = flashlight_rating.powi(2) * 25.0;
+    let base_performance = (base_aim.powf(1.1) + base_speed.powf(1.1) + base_flashlight.powf(1.1)).powf(1.0 / 1.1);
+
+    let star_rating = if base_performance > 0.00001 {
+        PERFORMANCE_BASE_MULTIPLIER.cbrt() * 0.027 * ((100_000.0 / 2.0_f64.powf(1.0 / 1.1) * base_performance).cbrt() + 4.0)
+    } else {
+        0.0
+    };
+
+    let preempt = scaling.preempt / clock_rate;
+    let approach_rate = if preempt > 1200.0 {
+        (1800.0 - preempt) / 120.0
+    } else {
+        (1200.0 - preempt) / 150.0 + 5.0
+    };
+
+    let count = |predicate: fn(&objects::OsuObject) -> bool| hit_objects.iter().filter(|object| predicate(object)).count() as u32;
+
+    Ok(DifficultyAttributes {
+        star_rating,
+        aim: aim_rating,
+        speed: speed_rating,
+        flashlight: flashlight_rating,
+        slider_factor,
+        speed_note_count: speed.relevant_note_count(),
+        approach_rate,
+        overall_difficulty: (80.0 - great_window / clock_rate) / 6.0,
+        circle_size,
+        drain_rate,
+        clock_rate,
+        max_combo: objects::max_combo(&hit_objects),
+        circles: count(|object| !object.is_slider() && !object.is_spinner()),
+        sliders: count(|object| object.is_slider()),
+        spinners: count(|object| object.is_spinner()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixture_difficulty;
+
+    use super::{
+        performance::{calculate_performance, Score, PP_ACCURACIES},
+        *,
+    };
+
+    fn attributes(version: &str, mods: &str) -> DifficultyAttributes {
+        let osu = OsuFile::parse(&String::from_utf8(fixture_difficulty(version)).unwrap()).unwrap();
+        calculate_difficulty(&osu, Mods::parse(mods).unwrap()).unwrap()
+    }
+
+    fn circles(objects: &str) -> OsuFile {
+        let header = concat!(
+            "osu file format v14\n\n",
+            "[General]\nAudioFilename: audio.mp3\nMode: 0\n\n",
+            "[Difficulty]\nHPDrainRate:5\nCircleSize:4\nOverallDifficulty:10\nApproachRate:9\nSliderMultiplier:1.4\nSliderTickRate:1\n\n",
+            "[TimingPoints]\n0,500,4,2,1,60,1,0\n\n",
+            "[HitObjects]\n",
+        );
+        OsuFile::parse(&format!("{}{}", header, objects)).unwrap()
+    }
+
+    /// A single circle leaves aim and speed at osu!'s floor of `(5 * 1 - 4)^3 / 100000`, so its
+    /// star rating and pp reduce to closed forms of osu!'s formulas.
+    #[test]
+    fn matches_reference_values_of_a_single_circle() {
+        let attributes = calculate_difficulty(&circles("256,192,1000,1,0,0:0:0:0:\n"), Mods::default()).unwrap();
+        assert_eq!((attributes.aim, attributes.speed), (0.0, 0.0));
+
+        // cbrt(1.14) * 0.027 * (cbrt(1) + 4)
+        assert!((attributes.star_rating - 0.141_026_930).abs() < 1e-6, "{}", attributes.star_rating);
+
+        let performance = calculate_performance(&attributes, Mods::default(), Score::full_combo(&attributes, 100.0));
+        // 1.52163^10 * 2.83 * (1 / 1000)^0.3
+        assert!((performance.accuracy_value - 23.707_024_935).abs() < 1e-6, "{}", performance.accuracy_value);
+        assert!((performance.pp - 27.026_011_177).abs() < 1e-6, "{}", performance.pp);
+    }
+
+    /// Two circles 200ms and more than `single_spacing_threshold` apart give one speed strain of
+    /// `2 / 200 * 1375`, worked through osu!'s strain reduction by hand. Aim only starts at the third object.
+    #[test]
+    fn matches_reference_values_of_two_circles() {
+        let attributes = calculate_difficulty(&circles("100,192,1000,1,0,0:0:0:0:\n300,192,1200,1,0,0:0:0:0:\n"), Mods::default()).unwrap();
+        assert_eq!(attributes.aim, 0.0);
+
+        // sqrt(13.75 * 0.75 * 1.04) * 0.0675
+        assert!((attributes.speed - 0.221_056_059).abs() < 1e-6, "{}", attributes.speed);
+        assert!((attributes.star_rating - 0.395_746_951).abs() < 1e-6, "{}", attributes.star_rating);
+
+        let performance = calculate_performance(&attributes, Mods::default(), Score::full_combo(&attributes, 100.0));
+        assert!((performance.speed - 0.019_509_850).abs() < 1e-6, "{}", performance.speed);
+        assert!((performance.accuracy_value - 29.186_771_306).abs() < 1e-6, "{}", performance.accuracy_value);
+        assert!((performance.pp - 33.282_654_993).abs() < 1e-6, "{}", performance.pp);
+    }
+
+    #[test]
+    fn counts_objects_and_combo() {
+        let normal = attributes("Normal", "");
+        assert_eq!((normal.circles, normal.sliders, normal.spinners), (53, 13, 1));
+        // Every slider has at least a head and a tail.
+        assert!(normal.max_combo > 53 + 13 * 2);
+
+        let hard = attributes("Hard", "");
+        assert_eq!((hard.circles, hard.sliders, hard.spinners), (106, 26, 1));
+        assert!(hard.star_rating > normal.star_rating);
+        assert!(normal.star_rating > 0.0);
+    }
+
+    #[test]
+    fn applies_mods() {
+        let nomod = attributes("Hard", "");
+        let double_time = attributes("Hard", "DT");
+        let half_time = attributes("Hard", "HT");
+        let hard_rock = attributes("Hard", "HR");
+        let easy = attributes("Hard", "EZ");
+
+        assert!(double_time.star_rating > nomod.star_rating);
+        assert!(half_time.star_rating < nomod.star_rating);
+        assert!(hard_rock.star_rating > nomod.star_rating);
+        assert!(easy.star_rating < nomod.star_rating);
+
+        assert_eq!(nomod.approach_rate, 9.0);
+        assert!((double_time.approach_rate - 10.33).abs() < 0.01);
+        assert_eq!(hard_rock.circle_size, 5.2);
+        assert_eq!(double_time.max_combo, nomod.max_combo);
+        assert_eq!(attributes("Hard", "NC"), double_time);
+    }
+
+    #[test]
+    fn performance_grows_with_accuracy() {
+        let hard = attributes("Hard", "HD");
+        let pp: Vec<f64> = PP_ACCURACIES
+            .iter()
+            .map(|accuracy| calculate_performance(&hard, Mods::parse("HD").unwrap(), Score::full_combo(&hard, *accuracy)).pp)
+            .collect();
+
+        assert!(pp.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", pp);
+
+        let perfect = Score::full_combo(&hard, 100.0);
+        let choked = Score {
+            miss: 1,
+            great: perfect.great - 1,
+            max_combo: perfect.max_combo / 2,
+            ..perfect
+        };
+        assert!(calculate_performance(&hard, Mods::default(), choked).pp < calculate_performance(&hard, Mods::default(), perfect).pp);
+    }
+}
@@ -0,0 +1,157 @@
+use std::fmt;
+
+/// osu!stable mod bitflags. Only the bits that change difficulty or pp are kept, so
+/// `HDDT`, `HDNC` and `HDDTSD` share an attributes cache entry.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Mods(u32);
+
+const ACRONYMS: [(&str, u32); 13] = [
+    ("NF", Mods::NO_FAIL),
+    ("EZ", Mods::EASY),
+    ("TD", Mods::TOUCH_DEVICE),
+    ("HD", Mods::HIDDEN),
+    ("HR", Mods::HARD_ROCK),
+    ("SD", Mods::SUDDEN_DEATH),
+    ("DT", Mods::DOUBLE_TIME),
+    ("RX", Mods::RELAX),
+    ("HT", Mods::HALF_TIME),
+    ("NC", Mods::NIGHTCORE),
+    ("FL", Mods::FLASHLIGHT),
+    ("SO", Mods::SPUN_OUT),
+    ("PF", Mods::PERFECT),
+];
+
+impl Mods {
+    pub const NO_FAIL: u32 = 1;
+    pub const EASY: u32 = 1 << 1;
+    pub const TOUCH_DEVICE: u32 = 1 << 2;
+    pub const HIDDEN: u32 = 1 << 3;
+    pub const HARD_ROCK: u32 = 1 << 4;
+    pub const SUDDEN_DEATH: u32 = 1 << 5;
+    pub const DOUBLE_TIME: u32 = 1 << 6;
+    pub const RELAX: u32 = 1 << 7;
+    pub const HALF_TIME: u32 = 1 << 8;
+    pub const NIGHTCORE: u32 = 1 << 9;
+    pub const FLASHLIGHT: u32 = 1 << 10;
+    pub const SPUN_OUT: u32 = 1 << 12;
+    pub const PERFECT: u32 = 1 << 14;
+
+    const RELEVANT: u32 = Mods::NO_FAIL
+        | Mods::EASY
+        | Mods::TOUCH_DEVICE
+        | Mods::HIDDEN
+        | Mods::HARD_ROCK
+        | Mods::DOUBLE_TIME
+        | Mods::RELAX
+        | Mods::HALF_TIME
+        | Mods::FLASHLIGHT
+        | Mods::SPUN_OUT;
+
+    pub fn new(bits: u32) -> Mods {
+        let mut bits = bits;
+        // Nightcore is sent together with DoubleTime by stable, but not always by others.
+        if bits & Mods::NIGHTCORE != 0 {
+            bits |= Mods::DOUBLE_TIME;
+        }
+
+        Mods(bits & Mods::RELEVANT)
+    }
+
+    /// Accepts the bitflag number (`72`) or acronyms (`HDDT`, `+HD,DT`), case-insensitively.
+    pub fn parse(value: &str) -> Result<Mods, String> {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(Mods::default());
+        }
+
+        if let Ok(bits) = value.parse::<u32>() {
+            return Ok(Mods::new(bits));
+        }
+
+        let acronyms: String = value
+            .trim_start_matches('+')
+            .chars()
+            .filter(|c| !matches!(c, ',' | ' '))
+            .collect::<String>()
+            .to_uppercase();
+
+        if !acronyms.len().is_multiple_of(2) || !acronyms.is_ascii() {
+            return Err(format!("Invalid mods: {}", value));
+        }
+
+        let mut bits = 0;
+        for index in (0..acronyms.len()).step_by(2) {
+            let acronym = &acronyms[index..index + 2];
+            match ACRONYMS.iter().find(|(name, _)| *name == acronym) {
+                Some((_, bit)) => bits |= bit,
+                None => return Err(format!("Unknown mod: {}", acronym)),
+            }
+        }
+
+        Ok(Mods::new(bits))
+    }
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, bit: u32) -> bool {
+        self.0 & bit != 0
+    }
+
+    pub fn clock_rate(self) -> f64 {
+        if self.contains(Mods::DOUBLE_TIME) {
+            1.5
+        } else if self.contains(Mods::HALF_TIME) {
+            0.75
+        } else {
+            1.0
+        }
+    }
+
+    /// Circle size, approach rate, overall difficulty and drain rate scaled by HR/EZ.
+    pub fn adjust(self, value: f64, circle_size: bool) -> f64 {
+        if self.contains(Mods::HARD_ROCK) {
+            (value * if circle_size { 1.3 } else { 1.4 }).min(10.0)
+        } else if self.contains(Mods::EASY) {
+            value * 0.5
+        } else {
+            value
+        }
+    }
+}
+
+impl fmt::Display for Mods {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "NM");
+        }
+
+        for (acronym, bit) in ACRONYMS {
+            if self.contains(bit) {
+                write!(f, "{}", acronym)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_acronyms_and_bits() {
+        assert_eq!(Mods::parse("HDDT").unwrap().bits(), 72);
+        assert_eq!(Mods::parse("+hd,dt").unwrap(), Mods::new(72));
+        assert_eq!(Mods::parse("72").unwrap(), Mods::new(72));
+        assert_eq!(Mods::parse("").unwrap(), Mods::default());
+
+        // Nightcore implies DoubleTime, SuddenDeath and Perfect don't matter.
+        assert_eq!(Mods::parse("HDNC").unwrap(), Mods::new(72));
+        assert_eq!(Mods::parse("HRSDPF").unwrap().to_string(), "HR");
+
+        assert!(Mods::parse("HDX").is_err());
+        assert!(Mods::parse("XX").is_err());
+    }
+}
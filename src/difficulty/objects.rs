@@ -0,0 +1,418 @@
+use crate::osu::parser::{HitObjectKind, OsuFile, TimingPoint};
+
+use super::{
+    mods::Mods,
+    path::{SliderPath, Vec2},
+};
+
+const OBJECT_RADIUS: f64 = 64.0;
+const STACK_DISTANCE: f64 = 3.0;
+const BASE_SCORING_DISTANCE: f64 = 100.0;
+/// The tail judgement of a slider happens this much before its end, same as stable.
+const LEGACY_LAST_TICK_OFFSET: f64 = 36.0;
+/// Playfield height, Hard Rock flips objects vertically within it.
+const PLAYFIELD_HEIGHT: f64 = 384.0;
+
+pub const NORMALISED_RADIUS: f64 = 50.0;
+const ASSUMED_SLIDER_RADIUS: f64 = NORMALISED_RADIUS * 1.8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NestedKind {
+    Tick,
+    Repeat,
+    Tail,
+}
+
+#[derive(Debug, Clone)]
+pub struct Nested {
+    pub time: f64,
+    /// Relative to the slider head.
+    pub offset: Vec2,
+    pub kind: NestedKind,
+}
+
+#[derive(Debug, Clone)]
+pub struct Slider {
+    pub end_time: f64,
+    pub span_duration: f64,
+    pub repeats: usize,
+    pub path: SliderPath,
+    /// Ordered by time, the head is not included.
+    pub nested: Vec<Nested>,
+    /// Where the cursor has to be at the end of the slider when following it lazily.
+    pub lazy_end_offset: Vec2,
+    pub lazy_travel_distance: f64,
+    pub lazy_travel_time: f64,
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectKind {
+    Circle,
+    Slider(Box<Slider>),
+    Spinner { end_time: f64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct OsuObject {
+    pub position: Vec2,
+    pub start_time: f64,
+    pub stack_height: i32,
+    pub kind: ObjectKind,
+}
+
+impl OsuObject {
+    pub fn end_time(&self) -> f64 {
+        match &self.kind {
+            ObjectKind::Circle => self.start_time,
+            ObjectKind::Slider(slider) => slider.end_time,
+            ObjectKind::Spinner { end_time } => *end_time,
+        }
+    }
+
+    pub fn is_slider(&self) -> bool {
+        matches!(self.kind, ObjectKind::Slider(_))
+    }
+
+    pub fn is_spinner(&self) -> bool {
+        matches!(self.kind, ObjectKind::Spinner { .. })
+    }
+
+    /// Unstacked position of the slider tail, the head for anything else.
+    pub fn end_position(&self) -> Vec2 {
+        match &self.kind {
+            ObjectKind::Slider(slider) => self.position + slider.path.position_at(if slider.repeats % 2 == 0 { 1.0 } else { 0.0 }),
+            _ => self.position,
+        }
+    }
+}
+
+/// Approach rate, circle size and their derived values after mods.
+#[derive(Debug, Clone, Copy)]
+pub struct Scaling {
+    pub radius: f64,
+    /// Unaffected by the clock rate, like the object times.
+    pub preempt: f64,
+    pub fade_in: f64,
+}
+
+impl Scaling {
+    pub fn new(circle_size: f64, approach_rate: f64, mods: Mods) -> Scaling {
+        let preempt = difficulty_range(approach_rate, 1800.0, 1200.0, 450.0);
+        let fade_in = if mods.contains(Mods::HIDDEN) {
+            preempt * 0.4
+        } else {
+            400.0 * (preempt / 450.0).min(1.0)
+        };
+
+        Scaling {
+            radius: OBJECT_RADIUS * (1.0 - 0.7 * (circle_size - 5.0) / 5.0) / 2.0,
+            preempt,
+            fade_in,
+        }
+    }
+
+    pub fn stack_offset(&self, stack_height: i32) -> Vec2 {
+        let offset = stack_height as f64 * (self.radius / OBJECT_RADIUS) * -6.4;
+        Vec2::new(offset, offset)
+    }
+}
+
+/// Maps a 0-10 difficulty value onto `min` (at 0), `mid` (at 5) and `max` (at 10).
+pub fn difficulty_range(difficulty: f64, min: f64, mid: f64, max: f64) -> f64 {
+    if difficulty > 5.0 {
+        mid + (max - mid) * (difficulty - 5.0) / 5.0
+    } else if difficulty < 5.0 {
+        mid - (mid - min) * (5.0 - difficulty) / 5.0
+    } else {
+        mid
+    }
+}
+
+/// Beat length of the red line and slider velocity multiplier of the green line at `time`.
+/// Red lines reset the slider velocity to 1.
+fn timing_at(timing_points: &[TimingPoint], time: f64) -> (f64, f64) {
+    let beat_length = timing_points
+        .iter()
+        .filter(|point| point.uninherited)
+        .take_while(|point| point.time <= time)
+        .last()
+        .or_else(|| timing_points.iter().find(|point| point.uninherited))
+        .map_or(1000.0, |point| point.beat_length);
+
+    let velocity = match timing_points.iter().take_while(|point| point.time <= time).last() {
+        Some(point) if !point.uninherited && point.beat_length < 0.0 => (-100.0 / point.beat_length).clamp(0.1, 10.0),
+        _ => 1.0,
+    };
+
+    (beat_length, velocity)
+}
+
+fn convert_slider(
+    osu: &OsuFile,
+    position: Vec2,
+    start_time: f64,
+    curve_type: &str,
+    curve_points: &[Vec2],
+    slides: i32,
+    length: f64,
+) -> Slider {
+    let mut control_points = vec![Vec2::default()];
+    control_points.extend(curve_points.iter().map(|point| *point - position));
+
+    let path = SliderPath::new(curve_type, &control_points, Some(length));
+    let distance = path.distance();
+    let spans = slides.max(1) as usize;
+
+    let (beat_length, slider_velocity) = timing_at(&osu.timing_points, start_time);
+    let scoring_distance = BASE_SCORING_DISTANCE * osu.difficulty.slider_multiplier * slider_velocity;
+    let velocity = scoring_distance / beat_length;
+    let span_duration = if velocity > 0.0 { distance / velocity } else { 0.0 };
+    let end_time = start_time + span_duration * spans as f64;
+
+    // Before v8 tick spacing ignored the slider velocity.
+    let tick_velocity = if osu.format_version < 8 { 1.0 } else { slider_velocity };
+    let tick_distance = BASE_SCORING_DISTANCE * osu.difficulty.slider_multiplier * tick_velocity / osu.difficulty.slider_tick_rate.max(0.01);
+    let tick_distance = tick_distance.clamp(0.0, distance);
+    let min_distance_from_end = velocity * 10.0;
+
+    let mut nested = Vec::new();
+    for span in 0..spans {
+        let span_start = start_time + span as f64 * span_duration;
+        let reversed = span % 2 == 1;
+
+        let mut ticks = Vec::new();
+        if tick_distance > 0.0 {
+            let mut tick = tick_distance;
+            while tick <= distance && tick < distance - min_distance_from_end {
+                let path_progress = tick / distance;
+                let time_progress = if reversed { 1.0 - path_progress } else { path_progress };
+                ticks.push(Nested {
+                    time: span_start + time_progress * span_duration,
+                    offset: path.position_at(path_progress),
+                    kind: NestedKind::Tick,
+                });
+                tick += tick_distance;
+            }
+        }
+        if reversed {
+            ticks.reverse();
+        }
+        nested.extend(ticks);
+
+        if span + 1 < spans {
+            nested.push(Nested {
+                time: span_start + span_duration,
+                offset: path.position_at(((span + 1) % 2) as f64),
+                kind: NestedKind::Repeat,
+            });
+        }
+    }
+
+    nested.push(Nested {
+        time: (start_time + (end_time - start_time) / 2.0).max(end_time - LEGACY_LAST_TICK_OFFSET),
+        offset: path.position_at(if spans % 2 == 1 { 1.0 } else { 0.0 }),
+        kind: NestedKind::Tail,
+    });
+
+    Slider {
+        end_time,
+        span_duration,
+        repeats: spans - 1,
+        path,
+        nested,
+        lazy_end_offset: Vec2::default(),
+        lazy_travel_distance: 0.0,
+        lazy_travel_time: 0.0,
+    }
+}
+
+/// Follows the slider with the least movement a player could get away with,
+/// sets `lazy_end_offset`, `lazy_travel_distance` and `lazy_travel_time`.
+fn compute_lazy_travel(slider: &mut Slider, radius: f64, start_time: f64) {
+    let tail_time = slider.nested.last().map_or(start_time, |nested| nested.time);
+    slider.lazy_travel_time = tail_time - start_time;
+
+    let mut end_progress = if slider.span_duration > 0.0 {
+        slider.lazy_travel_time / slider.span_duration
+    } else {
+        0.0
+    };
+    end_progress = if end_progress % 2.0 >= 1.0 { 1.0 - end_progress % 1.0 } else { end_progress % 1.0 };
+    let lazy_end = slider.path.position_at(end_progress);
+
+    let scaling_factor = NORMALISED_RADIUS / radius;
+    let mut cursor = Vec2::default();
+    let mut travel = 0.0;
+    let count = slider.nested.len();
+
+    for (index, nested) in slider.nested.iter().enumerate() {
+        let mut movement = nested.offset - cursor;
+        let mut required = ASSUMED_SLIDER_RADIUS;
+        let last = index == count - 1;
+
+        if last {
+            let lazy_movement = lazy_end - cursor;
+            if lazy_movement.length() < movement.length() {
+                movement = lazy_movement;
+            }
+        } else if nested.kind == NestedKind::Repeat {
+            required = NORMALISED_RADIUS;
+        }
+
+        let mut movement_length = scaling_factor * movement.length();
+        if movement_length > required {
+            cursor = cursor + movement * ((movement_length - required) / movement_length);
+            movement_length *= (movement_length - required) / movement_length;
+            travel += movement_length;
+        }
+    }
+
+    slider.lazy_end_offset = cursor;
+    slider.lazy_travel_distance = travel * (1.0 + slider.repeats as f64 / 2.5).powf(1.0 / 2.5);
+}
+
+/// The osu!standard objects of `osu` with Hard Rock applied, stacked and with their
+/// slider paths resolved.
+pub fn convert(osu: &OsuFile, mods: Mods, scaling: &Scaling) -> Vec<OsuObject> {
+    let flip = |point: Vec2| match mods.contains(Mods::HARD_ROCK) {
+        true => Vec2::new(point.x, PLAYFIELD_HEIGHT - point.y),
+        false => point,
+    };
+
+    let mut objects: Vec<OsuObject> = osu
+        .hit_objects
+        .iter()
+        .filter_map(|object| {
+            let position = flip(Vec2::new(object.x, object.y));
+            let kind = match &object.kind {
+                HitObjectKind::Circle => ObjectKind::Circle,
+                HitObjectKind::Slider { curve_type, curve_points, slides, length } => {
+                    let points: Vec<Vec2> = curve_points.iter().map(|(x, y)| flip(Vec2::new(*x, *y))).collect();
+                    let slider = convert_slider(osu, position, object.time, curve_type, &points, *slides, *length);
+                    ObjectKind::Slider(Box::new(slider))
+                }
+                HitObjectKind::Spinner { end_time } => ObjectKind::Spinner { end_time: *end_time },
+                HitObjectKind::Hold { .. } => return None,
+            };
+
+            Some(OsuObject {
+                position,
+                start_time: object.time,
+                stack_height: 0,
+                kind,
+            })
+        })
+        .collect();
+
+    let stack_threshold = scaling.preempt * osu.general.stack_leniency;
+    if osu.format_version >= 6 {
+        apply_stacking(&mut objects, stack_threshold);
+    } else {
+        apply_stacking_old(&mut objects, stack_threshold);
+    }
+
+    for object in &mut objects {
+        let start_time = object.start_time;
+        if let ObjectKind::Slider(slider) = &mut object.kind {
+            compute_lazy_travel(slider, scaling.radius, start_time);
+        }
+    }
+
+    objects
+}
+
+fn apply_stacking(objects: &mut [OsuObject], stack_threshold: f64) {
+    for i in (1..objects.len()).rev() {
+        if objects[i].stack_height != 0 || objects[i].is_spinner() {
+            continue;
+        }
+
+        let mut current = i;
+
+        if objects[current].is_slider() {
+            for n in (0..i).rev() {
+                if objects[n].is_spinner() {
+                    continue;
+                }
+                if objects[current].start_time - objects[n].start_time > stack_threshold {
+                    break;
+                }
+
+                if objects[n].end_position().distance(objects[current].position) < STACK_DISTANCE {
+                    objects[n].stack_height = objects[current].stack_height + 1;
+                    current = n;
+                }
+            }
+            continue;
+        }
+
+        for n in (0..i).rev() {
+            if objects[n].is_spinner() {
+                continue;
+            }
+            if objects[current].start_time - objects[n].end_time() > stack_threshold {
+                break;
+            }
+
+            // Objects stacked below a slider tail are moved away from it instead.
+            if objects[n].is_slider() && objects[n].end_position().distance(objects[current].position) < STACK_DISTANCE {
+                let offset = objects[current].stack_height - objects[n].stack_height + 1;
+                let tail = objects[n].end_position();
+
+                for object in &mut objects[n + 1..=i] {
+                    if tail.distance(object.position) < STACK_DISTANCE {
+                        object.stack_height -= offset;
+                    }
+                }
+                break;
+            }
+
+            if objects[n].position.distance(objects[current].position) < STACK_DISTANCE {
+                objects[n].stack_height = objects[current].stack_height + 1;
+                current = n;
+            }
+        }
+    }
+}
+
+/// Stacking of beatmaps before v6.
+fn apply_stacking_old(objects: &mut [OsuObject], stack_threshold: f64) {
+    for i in 0..objects.len() {
+        if objects[i].stack_height != 0 && !objects[i].is_slider() {
+            continue;
+        }
+
+        let mut start_time = objects[i].end_time();
+        let mut slider_stack = 0;
+        let end_position = match &objects[i].kind {
+            ObjectKind::Slider(slider) => objects[i].position + slider.path.position_at(1.0),
+            _ => objects[i].position,
+        };
+
+        for j in i + 1..objects.len() {
+            if objects[j].start_time - stack_threshold > start_time {
+                break;
+            }
+
+            if objects[j].position.distance(objects[i].position) < STACK_DISTANCE {
+                objects[i].stack_height += 1;
+                start_time = objects[j].end_time();
+            } else if objects[j].position.distance(end_position) < STACK_DISTANCE {
+                slider_stack += 1;
+                objects[j].stack_height -= slider_stack;
+                start_time = objects[j].end_time();
+            }
+        }
+    }
+}
+
+pub fn max_combo(objects: &[OsuObject]) -> u32 {
+    objects
+        .iter()
+        .map(|object| match &object.kind {
+            // The head plus ticks, repeats and tail.
+            ObjectKind::Slider(slider) => 1 + slider.nested.len() as u32,
+            _ => 1,
+        })
+        .sum()
+}
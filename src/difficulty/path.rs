@@ -0,0 +1,333 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Vec2 {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Vec2 {
+    pub fn new(x: f64, y: f64) -> Vec2 {
+        Vec2 { x, y }
+    }
+
+    pub fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    pub fn dot(self, other: Vec2) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    pub fn distance(self, other: Vec2) -> f64 {
+        (self - other).length()
+    }
+}
+
+impl Add for Vec2 {
+    type Output = Vec2;
+
+    fn add(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vec2 {
+    type Output = Vec2;
+
+    fn sub(self, other: Vec2) -> Vec2 {
+        Vec2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f64> for Vec2 {
+    type Output = Vec2;
+
+    fn mul(self, factor: f64) -> Vec2 {
+        Vec2::new(self.x * factor, self.y * factor)
+    }
+}
+
+/// Points per catmull segment, same as osu!.
+const CATMULL_DETAIL: usize = 50;
+/// Maximum distance between the arc of a perfect circle slider and its approximation.
+const CIRCULAR_ARC_TOLERANCE: f64 = 0.1;
+
+/// The path a slider ball travels, relative to the slider head, approximated by
+/// line segments and cut or extended to the length given in the `.osu`.
+#[derive(Debug, Clone)]
+pub struct SliderPath {
+    points: Vec<Vec2>,
+    cumulative_length: Vec<f64>,
+}
+
+impl SliderPath {
+    /// `control_points` start with the head at `(0, 0)`.
+    pub fn new(curve_type: &str, control_points: &[Vec2], expected_distance: Option<f64>) -> SliderPath {
+        let points = match curve_type {
+            "L" => control_points.to_vec(),
+            "P" if control_points.len() == 3 => {
+                circular_arc(control_points).unwrap_or_else(|| bezier_segments(control_points))
+            }
+            "C" => catmull(control_points),
+            _ => bezier_segments(control_points),
+        };
+
+        let mut path = SliderPath {
+            points,
+            cumulative_length: Vec::new(),
+        };
+        path.calculate_length(expected_distance.filter(|distance| *distance > 0.0));
+        path
+    }
+
+    pub fn distance(&self) -> f64 {
+        self.cumulative_length.last().copied().unwrap_or_default()
+    }
+
+    fn calculate_length(&mut self, expected_distance: Option<f64>) {
+        let mut calculated = 0.0;
+        self.cumulative_length = vec![0.0];
+
+        for pair in self.points.windows(2) {
+            calculated += pair[0].distance(pair[1]);
+            self.cumulative_length.push(calculated);
+        }
+
+        let expected = match expected_distance {
+            Some(expected) if expected != calculated => expected,
+            _ => return,
+        };
+
+        // A path ending in a repeated point is not extended.
+        let count = self.points.len();
+        if count >= 2 && self.points[count - 1] == self.points[count - 2] && expected > calculated {
+            return;
+        }
+
+        self.cumulative_length.pop();
+        let mut end = self.points.len() - 1;
+
+        if calculated > expected {
+            while self.cumulative_length.last().is_some_and(|length| *length >= expected) {
+                self.cumulative_length.pop();
+                self.points.remove(end);
+                end -= 1;
+            }
+        }
+
+        if end == 0 {
+            self.points.truncate(1);
+            self.cumulative_length = vec![0.0];
+            return;
+        }
+
+        let direction = self.points[end] - self.points[end - 1];
+        let direction = direction * (1.0 / direction.length().max(f64::EPSILON));
+        let last_length = self.cumulative_length.last().copied().unwrap_or_default();
+
+        self.points[end] = self.points[end - 1] + direction * (expected - last_length);
+        self.points.truncate(end + 1);
+        self.cumulative_length.push(expected);
+    }
+
+    /// Position at `progress` (0 is the head, 1 the end of the first span) along the path.
+    pub fn position_at(&self, progress: f64) -> Vec2 {
+        if self.points.is_empty() {
+            return Vec2::default();
+        }
+
+        let distance = progress.clamp(0.0, 1.0) * self.distance();
+        let index = self.cumulative_length.partition_point(|length| *length < distance);
+
+        if index == 0 {
+            return self.points[0];
+        }
+        if index >= self.points.len() {
+            return self.points[self.points.len() - 1];
+        }
+
+        let (start, end) = (self.points[index - 1], self.points[index]);
+        let (start_length, end_length) = (self.cumulative_length[index - 1], self.cumulative_length[index]);
+        if end_length - start_length <= f64::EPSILON {
+            return start;
+        }
+
+        start + (end - start) * ((distance - start_length) / (end_length - start_length))
+    }
+}
+
+/// Bezier sliders are split into separate curves wherever a control point repeats.
+fn bezier_segments(control_points: &[Vec2]) -> Vec<Vec2> {
+    let mut points = Vec::new();
+    let mut start = 0;
+
+    for end in 1..=control_points.len() {
+        let split = end == control_points.len() || control_points[end] == control_points[end - 1];
+        if !split {
+            continue;
+        }
+
+        for point in bezier(&control_points[start..end]) {
+            if points.last() != Some(&point) {
+                points.push(point);
+            }
+        }
+        start = end;
+    }
+
+    points
+}
+
+fn bezier(control_points: &[Vec2]) -> Vec<Vec2> {
+    if control_points.len() < 3 {
+        return control_points.to_vec();
+    }
+
+    let polygon_length: f64 = control_points.windows(2).map(|pair| pair[0].distance(pair[1])).sum();
+    let steps = ((polygon_length / 2.0).ceil() as usize).clamp(16, 512);
+    let mut scratch = control_points.to_vec();
+
+    (0..=steps)
+        .map(|step| {
+            let t = step as f64 / steps as f64;
+            scratch.copy_from_slice(control_points);
+            for level in (1..scratch.len()).rev() {
+                for index in 0..level {
+                    scratch[index] = scratch[index] * (1.0 - t) + scratch[index + 1] * t;
+                }
+            }
+            scratch[0]
+        })
+        .collect()
+}
+
+fn circular_arc(control_points: &[Vec2]) -> Option<Vec<Vec2>> {
+    let (a, b, c) = (control_points[0], control_points[1], control_points[2]);
+
+    // Nearly straight "circles" are drawn as beziers.
+    if ((b.y - a.y) * (c.x - a.x) - (b.x - a.x) * (c.y - a.y)).abs() < 1e-3 {
+        return None;
+    }
+
+    let d = 2.0 * (a.x * (b - c).y + b.x * (c - a).y + c.x * (a - b).y);
+    let (a_sq, b_sq, c_sq) = (a.dot(a), b.dot(b), c.dot(c));
+    let centre = Vec2::new(
+        (a_sq * (b - c).y + b_sq * (c - a).y + c_sq * (a - b).y) / d,
+        (a_sq * (c - b).x + b_sq * (a - c).x + c_sq * (b - a).x) / d,
+    );
+
+    let (d_a, d_c) = (a - centre, c - centre);
+    let radius = d_a.length();
+    let theta_start = d_a.y.atan2(d_a.x);
+    let mut theta_end = d_c.y.atan2(d_c.x);
+    while theta_end < theta_start {
+        theta_end += 2.0 * PI;
+    }
+
+    let mut direction = 1.0;
+    let mut theta_range = theta_end - theta_start;
+
+    let ortho_a_to_c = Vec2::new((c - a).y, -(c - a).x);
+    if ortho_a_to_c.dot(b - a) < 0.0 {
+        direction = -1.0;
+        theta_range = 2.0 * PI - theta_range;
+    }
+
+    let amount = if 2.0 * radius <= CIRCULAR_ARC_TOLERANCE {
+        2
+    } else {
+        let step = 2.0 * (1.0 - CIRCULAR_ARC_TOLERANCE / radius).acos();
+        ((theta_range / step).ceil() as usize).max(2)
+    };
+
+    Some(
+        (0..amount)
+            .map(|index| {
+                let theta = theta_start + direction * (index as f64 / (amount - 1) as f64) * theta_range;
+                centre + Vec2::new(theta.cos(), theta.sin()) * radius
+            })
+            .collect(),
+    )
+}
+
+fn catmull(control_points: &[Vec2]) -> Vec<Vec2> {
+    let count = control_points.len();
+    let mut points = Vec::with_capacity(count * CATMULL_DETAIL * 2);
+
+    for index in 0..count.saturating_sub(1) {
+        let v1 = if index > 0 { control_points[index - 1] } else { control_points[index] };
+        let v2 = control_points[index];
+        let v3 = if index + 1 < count { control_points[index + 1] } else { v2 + v2 - v1 };
+        let v4 = if index + 2 < count { control_points[index + 2] } else { v3 + v3 - v2 };
+
+        for step in 0..CATMULL_DETAIL {
+            points.push(catmull_point(v1, v2, v3, v4, step as f64 / CATMULL_DETAIL as f64));
+            points.push(catmull_point(v1, v2, v3, v4, (step + 1) as f64 / CATMULL_DETAIL as f64));
+        }
+    }
+
+    points
+}
+
+fn catmull_point(v1: Vec2, v2: Vec2, v3: Vec2, v4: Vec2, t: f64) -> Vec2 {
+    let (t2, t3) = (t * t, t * t * t);
+
+    Vec2::new(
+        0.5 * (2.0 * v2.x + (-v1.x + v3.x) * t + (2.0 * v1.x - 5.0 * v2.x + 4.0 * v3.x - v4.x) * t2
+            + (-v1.x + 3.0 * v2.x - 3.0 * v3.x + v4.x) * t3),
+        0.5 * (2.0 * v2.y + (-v1.y + v3.y) * t + (2.0 * v1.y - 5.0 * v2.y + 4.0 * v3.y - v4.y) * t2
+            + (-v1.y + 3.0 * v2.y - 3.0 * v3.y + v4.y) * t3),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!(actual.distance(expected) < 0.5, "{:?} is not near {:?}", actual, expected);
+    }
+
+    #[test]
+    fn cuts_and_extends_to_expected_length() {
+        let points = [Vec2::new(0.0, 0.0), Vec2::new(100.0, 0.0)];
+
+        let cut = SliderPath::new("L", &points, Some(50.0));
+        assert_eq!(cut.distance(), 50.0);
+        assert_near(cut.position_at(1.0), Vec2::new(50.0, 0.0));
+
+        let extended = SliderPath::new("L", &points, Some(150.0));
+        assert_eq!(extended.distance(), 150.0);
+        assert_near(extended.position_at(1.0), Vec2::new(150.0, 0.0));
+        assert_near(extended.position_at(0.5), Vec2::new(75.0, 0.0));
+    }
+
+    #[test]
+    fn follows_perfect_circles() {
+        // Half circle of radius 50 around (50, 0).
+        let points = [Vec2::new(0.0, 0.0), Vec2::new(50.0, 50.0), Vec2::new(100.0, 0.0)];
+        let path = SliderPath::new("P", &points, Some(50.0 * PI));
+
+        assert!((path.distance() - 50.0 * PI).abs() < 1e-6);
+        assert_near(path.position_at(0.5), Vec2::new(50.0, 50.0));
+        assert_near(path.position_at(1.0), Vec2::new(100.0, 0.0));
+    }
+
+    #[test]
+    fn splits_beziers_on_red_anchors() {
+        let points = [
+            Vec2::new(0.0, 0.0),
+            Vec2::new(100.0, 0.0),
+            Vec2::new(100.0, 0.0),
+            Vec2::new(100.0, 100.0),
+        ];
+        let path = SliderPath::new("B", &points, None);
+
+        assert!((path.distance() - 200.0).abs() < 1e-6);
+        assert_near(path.position_at(0.5), Vec2::new(100.0, 0.0));
+    }
+}
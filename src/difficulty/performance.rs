@@ -0,0 +1,297 @@
+use serde::{Deserialize, Serialize};
+
+use super::{mods::Mods, DifficultyAttributes, DIFFICULTY_MULTIPLIER};
+
+pub const PERFORMANCE_BASE_MULTIPLIER: f64 = 1.14;
+
+/// Accuracies listed in the pp table of `/api/v1/beatmaps/:id/attributes`.
+pub const PP_ACCURACIES: [f64; 6] = [95.0, 97.0, 98.0, 99.0, 99.5, 100.0];
+
+/// Judgement counts of a play.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score {
+    pub great: u32,
+    pub ok: u32,
+    pub meh: u32,
+    pub miss: u32,
+    pub max_combo: u32,
+}
+
+impl Score {
+    /// A full combo reaching `accuracy` (in percent) with as few 100s as possible and no 50s.
+    pub fn full_combo(attributes: &DifficultyAttributes, accuracy: f64) -> Score {
+        let total = attributes.circles + attributes.sliders + attributes.spinners;
+        let accuracy = (accuracy / 100.0).clamp(0.0, 1.0);
+        let ok = ((1.5 * total as f64 * (1.0 - accuracy)).round() as u32).min(total);
+
+        Score {
+            great: total - ok,
+            ok,
+            meh: 0,
+            miss: 0,
+            max_combo: attributes.max_combo,
+        }
+    }
+
+    fn total_hits(&self) -> u32 {
+        self.great + self.ok + self.meh + self.miss
+    }
+
+    fn accuracy(&self) -> f64 {
+        match self.total_hits() {
+            0 => 0.0,
+            total => (self.great * 6 + self.ok * 2 + self.meh) as f64 / (total * 6) as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PerformanceAttributes {
+    pub accuracy: f64,
+    pub pp: f64,
+    pub aim: f64,
+    pub speed: f64,
+    #[serde(rename = "accuracy_pp")]
+    pub accuracy_value: f64,
+    pub flashlight: f64,
+}
+
+struct Calculator<'a> {
+    attributes: &'a DifficultyAttributes,
+    mods: Mods,
+    score: Score,
+    accuracy: f64,
+    total_hits: f64,
+    effective_miss_count: f64,
+}
+
+fn skill_value(rating: f64) -> f64 {
+    (5.0 * (rating / DIFFICULTY_MULTIPLIER).max(1.0) - 4.0).powi(3) / 100_000.0
+}
+
+pub fn calculate_performance(attributes: &DifficultyAttributes, mods: Mods, score: Score) -> PerformanceAttributes {
+    let total_hits = score.total_hits() as f64;
+    let mut calculator = Calculator {
+        attributes,
+        mods,
+        score,
+        accuracy: score.accuracy(),
+        total_hits,
+        effective_miss_count: 0.0,
+    };
+    calculator.effective_miss_count = calculator.effective_miss_count();
+
+    let mut multiplier = PERFORMANCE_BASE_MULTIPLIER;
+
+    if mods.contains(Mods::NO_FAIL) {
+        multiplier *= (1.0 - 0.02 * calculator.effective_miss_count).max(0.9);
+    }
+    if mods.contains(Mods::SPUN_OUT) && total_hits > 0.0 {
+        multiplier *= 1.0 - (attributes.spinners as f64 / total_hits).powf(0.85);
+    }
+    if mods.contains(Mods::RELAX) {
+        // 100s and 50s on relax are mostly misaimed notes.
+        let od = attributes.overall_difficulty;
+        let ok_multiplier = if od > 0.0 { 1.0 - (od / 13.33).powf(1.8) } else { 1.0 }.max(0.0);
+        let meh_multiplier = if od > 0.0 { 1.0 - (od / 13.33).powi(5) } else { 1.0 }.max(0.0);
+
+        calculator.effective_miss_count = (calculator.effective_miss_count
+            + score.ok as f64 * ok_multiplier
+            + score.meh as f64 * meh_multiplier)
+            .min(total_hits);
+    }
+
+    let aim = calculator.aim_value();
+    let speed = calculator.speed_value();
+    let accuracy_value = calculator.accuracy_value();
+    let flashlight = calculator.flashlight_value();
+
+    let pp = (aim.powf(1.1) + speed.powf(1.1) + accuracy_value.powf(1.1) + flashlight.powf(1.1)).powf(1.0 / 1.1) * multiplier;
+
+    PerformanceAttributes {
+        accuracy: calculator.accuracy * 100.0,
+        pp,
+        aim,
+        speed,
+        accuracy_value,
+        flashlight,
+    }
+}
+
+impl Calculator<'_> {
+    /// Misses estimated from the combo, a broken combo on a map with sliders usually
+    /// means a dropped slider end even without a miss.
+    fn effective_miss_count(&self) -> f64 {
+        let (attributes, score) = (self.attributes, self.score);
+        let mut combo_based = 0.0;
+
+        if attributes.sliders > 0 {
+            let full_combo_threshold = attributes.max_combo as f64 - 0.1 * attributes.sliders as f64;
+            if (score.max_combo as f64) < full_combo_threshold {
+                combo_based = full_combo_threshold / (score.max_combo as f64).max(1.0);
+            }
+        }
+
+        combo_based = combo_based.min((score.ok + score.meh + score.miss) as f64);
+        (score.miss as f64).max(combo_based)
+    }
+
+    fn combo_scaling(&self) -> f64 {
+        match self.attributes.max_combo {
+            0 => 1.0,
+            max_combo => ((self.score.max_combo as f64).powf(0.8) / (max_combo as f64).powf(0.8)).min(1.0),
+        }
+    }
+
+    fn length_bonus(&self) -> f64 {
+        let long = match self.total_hits > 2000.0 {
+            true => (self.total_hits / 2000.0).log10() * 0.5,
+            false => 0.0,
+        };
+        0.95 + 0.4 * (self.total_hits / 2000.0).min(1.0) + long
+    }
+
+    fn miss_penalty(&self, exponent: f64) -> f64 {
+        0.97 * (1.0 - (self.effective_miss_count / self.total_hits).powf(0.775)).powf(exponent)
+    }
+
+    fn aim_value(&self) -> f64 {
+        let attributes = self.attributes;
+        let mut value = skill_value(attributes.aim);
+
+        let length_bonus = self.length_bonus();
+        value *= length_bonus;
+
+        if self.effective_miss_count > 0.0 {
+            value *= self.miss_penalty(self.effective_miss_count);
+        }
+        value *= self.combo_scaling();
+
+        let ar = attributes.approach_rate;
+        let mut approach_rate_factor = if ar > 10.33 {
+            0.3 * (ar - 10.33)
+        } else if ar < 8.0 {
+            0.05 * (8.0 - ar)
+        } else {
+            0.0
+        };
+        if self.mods.contains(Mods::RELAX) {
+            approach_rate_factor = 0.0;
+        }
+        value *= 1.0 + approach_rate_factor * length_bonus;
+
+        if self.mods.contains(Mods::HIDDEN) {
+            value *= 1.0 + 0.04 * (12.0 - ar);
+        }
+
+        // Sliders whose ends were likely dropped are worth less aim.
+        if attributes.sliders > 0 {
+            let difficult_sliders = attributes.sliders as f64 * 0.15;
+            let dropped_ends = ((self.score.ok + self.score.meh + self.score.miss) as f64)
+                .min(attributes.max_combo.saturating_sub(self.score.max_combo) as f64)
+                .clamp(0.0, difficult_sliders);
+            let nerf = (1.0 - attributes.slider_factor) * (1.0 - dropped_ends / difficult_sliders).powi(3)
+                + attributes.slider_factor;
+            value *= nerf;
+        }
+
+        value *= self.accuracy;
+        value * (0.98 + attributes.overall_difficulty.powi(2) / 2500.0)
+    }
+
+    fn speed_value(&self) -> f64 {
+        if self.mods.contains(Mods::RELAX) {
+            return 0.0;
+        }
+
+        let attributes = self.attributes;
+        let mut value = skill_value(attributes.speed);
+
+        let length_bonus = self.length_bonus();
+        value *= length_bonus;
+
+        if self.effective_miss_count > 0.0 {
+            value *= self.miss_penalty(self.effective_miss_count.powf(0.875));
+        }
+        value *= self.combo_scaling();
+
+        let ar = attributes.approach_rate;
+        if ar > 10.33 {
+            value *= 1.0 + 0.3 * (ar - 10.33) * length_bonus;
+        }
+        if self.mods.contains(Mods::HIDDEN) {
+            value *= 1.0 + 0.04 * (12.0 - ar);
+        }
+
+        // Accuracy on the notes that make up the speed rating.
+        let score = self.score;
+        let notes = attributes.speed_note_count;
+        let relevant_total_diff = self.total_hits - notes;
+        let great = (score.great as f64 - relevant_total_diff).max(0.0);
+        let ok = (score.ok as f64 - (relevant_total_diff - score.great as f64).max(0.0)).max(0.0);
+        let meh = (score.meh as f64 - (relevant_total_diff - score.great as f64 - score.ok as f64).max(0.0)).max(0.0);
+        let relevant_accuracy = match notes == 0.0 {
+            true => 0.0,
+            false => (great * 6.0 + ok * 2.0 + meh) / (notes * 6.0),
+        };
+
+        let od = attributes.overall_difficulty;
+        value *= (0.95 + od.powi(2) / 750.0) * ((self.accuracy + relevant_accuracy) / 2.0).powf((14.5 - od.max(8.0)) / 2.0);
+
+        let meh_allowance = self.total_hits / 500.0;
+        let excess_mehs = match (score.meh as f64) < meh_allowance {
+            true => 0.0,
+            false => score.meh as f64 - meh_allowance,
+        };
+        value * 0.99_f64.powf(excess_mehs)
+    }
+
+    fn accuracy_value(&self) -> f64 {
+        if self.mods.contains(Mods::RELAX) {
+            return 0.0;
+        }
+
+        // Only circles are judged on timing in stable.
+        let circles = self.attributes.circles as f64;
+        let better_accuracy = match circles > 0.0 {
+            true => ((self.score.great as f64 - (self.total_hits - circles)) * 6.0 + self.score.ok as f64 * 2.0 + self.score.meh as f64)
+                / (circles * 6.0),
+            false => 0.0,
+        }
+        .max(0.0);
+
+        let mut value = 1.52163_f64.powf(self.attributes.overall_difficulty) * better_accuracy.powi(24) * 2.83;
+        value *= (circles / 1000.0).powf(0.3).min(1.15);
+
+        if self.mods.contains(Mods::HIDDEN) {
+            value *= 1.08;
+        }
+        if self.mods.contains(Mods::FLASHLIGHT) {
+            value *= 1.02;
+        }
+
+        value
+    }
+
+    fn flashlight_value(&self) -> f64 {
+        if !self.mods.contains(Mods::FLASHLIGHT) {
+            return 0.0;
+        }
+
+        let mut value = self.attributes.flashlight.powi(2) * 25.0;
+
+        if self.effective_miss_count > 0.0 {
+            value *= self.miss_penalty(self.effective_miss_count.powf(0.875));
+        }
+        value *= self.combo_scaling();
+
+        let length = match self.total_hits > 200.0 {
+            true => 0.2 * ((self.total_hits - 200.0) / 200.0).min(1.0),
+            false => 0.0,
+        };
+        value *= 0.7 + 0.1 * (self.total_hits / 200.0).min(1.0) + length;
+
+        value *= 0.5 + self.accuracy / 2.0;
+        value * (0.98 + self.attributes.overall_difficulty.powi(2) / 2500.0)
+    }
+}
@@ -0,0 +1,634 @@
+use std::f64::consts::PI;
+
+use super::{
+    objects::{ObjectKind, OsuObject, Scaling, NORMALISED_RADIUS},
+    path::Vec2,
+};
+
+const MIN_DELTA_TIME: f64 = 25.0;
+const MAXIMUM_SLIDER_RADIUS: f64 = NORMALISED_RADIUS * 2.4;
+const ASSUMED_SLIDER_RADIUS: f64 = NORMALISED_RADIUS * 1.8;
+
+const SECTION_LENGTH: f64 = 400.0;
+const DECAY_WEIGHT: f64 = 0.9;
+const REDUCED_STRAIN_BASELINE: f64 = 0.75;
+pub const DEFAULT_DIFFICULTY_MULTIPLIER: f64 = 1.06;
+
+/// A hit object seen from the previous one, with times divided by the clock rate.
+pub struct DifficultyObject<'a> {
+    pub base: &'a OsuObject,
+    pub start_time: f64,
+    pub delta_time: f64,
+    pub strain_time: f64,
+    pub lazy_jump_distance: f64,
+    pub minimum_jump_distance: f64,
+    pub minimum_jump_time: f64,
+    pub travel_distance: f64,
+    pub travel_time: f64,
+    pub angle: Option<f64>,
+    /// Full width of the 300 window.
+    pub hit_window_great: f64,
+}
+
+fn stacked_position(object: &OsuObject, scaling: &Scaling) -> Vec2 {
+    object.position + scaling.stack_offset(object.stack_height)
+}
+
+fn stacked_end_position(object: &OsuObject, scaling: &Scaling) -> Vec2 {
+    object.end_position() + scaling.stack_offset(object.stack_height)
+}
+
+fn end_cursor_position(object: &OsuObject, scaling: &Scaling) -> Vec2 {
+    match &object.kind {
+        ObjectKind::Slider(slider) => stacked_position(object, scaling) + slider.lazy_end_offset,
+        _ => stacked_position(object, scaling),
+    }
+}
+
+/// One difficulty object per hit object after the first.
+pub fn difficulty_objects<'a>(
+    objects: &'a [OsuObject],
+    scaling: &Scaling,
+    clock_rate: f64,
+    great_window: f64,
+) -> Vec<DifficultyObject<'a>> {
+    let mut result = Vec::with_capacity(objects.len().saturating_sub(1));
+
+    for index in 1..objects.len() {
+        let (base, last) = (&objects[index], &objects[index - 1]);
+        let last_last = index.checked_sub(2).map(|index| &objects[index]);
+
+        let start_time = base.start_time / clock_rate;
+        let delta_time = (base.start_time - last.start_time) / clock_rate;
+        let strain_time = delta_time.max(MIN_DELTA_TIME);
+
+        let mut object = DifficultyObject {
+            base,
+            start_time,
+            delta_time,
+            strain_time,
+            lazy_jump_distance: 0.0,
+            minimum_jump_distance: 0.0,
+            minimum_jump_time: 0.0,
+            travel_distance: 0.0,
+            travel_time: 0.0,
+            angle: None,
+            hit_window_great: if base.is_spinner() { 0.0 } else { 2.0 * great_window / clock_rate },
+        };
+
+        if let ObjectKind::Slider(slider) = &base.kind {
+            object.travel_distance = slider.lazy_travel_distance;
+            object.travel_time = (slider.lazy_travel_time / clock_rate).max(MIN_DELTA_TIME);
+        }
+
+        if base.is_spinner() || last.is_spinner() {
+            result.push(object);
+            continue;
+        }
+
+        let mut scaling_factor = NORMALISED_RADIUS / scaling.radius;
+        if scaling.radius < 30.0 {
+            scaling_factor *= 1.0 + (30.0 - scaling.radius).min(5.0) / 50.0;
+        }
+
+        let position = stacked_position(base, scaling);
+        let last_cursor = end_cursor_position(last, scaling);
+
+        object.lazy_jump_distance = (position * scaling_factor - last_cursor * scaling_factor).length();
+        object.minimum_jump_time = strain_time;
+        object.minimum_jump_distance = object.lazy_jump_distance;
+
+        if let ObjectKind::Slider(last_slider) = &last.kind {
+            let last_travel_time = (last_slider.lazy_travel_time / clock_rate).max(MIN_DELTA_TIME);
+            object.minimum_jump_time = (strain_time - last_travel_time).max(MIN_DELTA_TIME);
+
+            let tail_jump_distance = (stacked_end_position(last, scaling) - position).length() * scaling_factor;
+            object.minimum_jump_distance = (object.lazy_jump_distance - (MAXIMUM_SLIDER_RADIUS - ASSUMED_SLIDER_RADIUS))
+                .min(tail_jump_distance - MAXIMUM_SLIDER_RADIUS)
+                .max(0.0);
+        }
+
+        if let Some(last_last) = last_last.filter(|object| !object.is_spinner()) {
+            let v1 = end_cursor_position(last_last, scaling) - stacked_position(last, scaling);
+            let v2 = position - last_cursor;
+            let dot = v1.dot(v2);
+            let det = v1.x * v2.y - v1.y * v2.x;
+            object.angle = Some(det.atan2(dot).abs());
+        }
+
+        result.push(object);
+    }
+
+    result
+}
+
+fn previous<'o, 'a>(objects: &'o [DifficultyObject<'a>], current: usize, back: usize) -> Option<&'o DifficultyObject<'a>> {
+    current.checked_sub(back + 1).map(|index| &objects[index])
+}
+
+fn lerp(start: f64, end: f64, amount: f64) -> f64 {
+    start + (end - start) * amount
+}
+
+fn strain_decay(base: f64, ms: f64) -> f64 {
+    base.powf(ms / 1000.0)
+}
+
+/// Highest strain of every 400ms section of the map, as in osu!'s `StrainSkill`.
+#[derive(Default)]
+struct Peaks {
+    section_end: f64,
+    current_peak: f64,
+    peaks: Vec<f64>,
+}
+
+trait StrainSkill {
+    fn peaks(&mut self) -> &mut Peaks;
+    fn strain_value_at(&mut self, objects: &[DifficultyObject], current: usize) -> f64;
+    fn initial_strain(&self, objects: &[DifficultyObject], time: f64, current: usize) -> f64;
+
+    fn process(&mut self, objects: &[DifficultyObject], current: usize) {
+        let start_time = objects[current].start_time;
+        if current == 0 {
+            self.peaks().section_end = (start_time / SECTION_LENGTH).ceil() * SECTION_LENGTH;
+        }
+
+        while start_time > self.peaks().section_end {
+            let section_end = self.peaks().section_end;
+            let initial = self.initial_strain(objects, section_end, current);
+
+            let peaks = self.peaks();
+            peaks.peaks.push(peaks.current_peak);
+            peaks.current_peak = initial;
+            peaks.section_end += SECTION_LENGTH;
+        }
+
+        let strain = self.strain_value_at(objects, current);
+        let peaks = self.peaks();
+        peaks.current_peak = peaks.current_peak.max(strain);
+    }
+
+    fn strain_peaks(&mut self) -> Vec<f64> {
+        let peaks = self.peaks();
+        let mut result = peaks.peaks.clone();
+        result.push(peaks.current_peak);
+        result
+    }
+}
+
+/// osu!'s `OsuStrainSkill`: the hardest sections are reduced to keep short spikes in check,
+/// then all sections are summed with a geometric weighting.
+fn reduced_difficulty_value(mut strains: Vec<f64>, reduced_section_count: usize, difficulty_multiplier: f64) -> f64 {
+    strains.retain(|strain| *strain > 0.0);
+    strains.sort_by(|a, b| b.total_cmp(a));
+
+    for (index, strain) in strains.iter_mut().take(reduced_section_count).enumerate() {
+        let scale = lerp(1.0, 10.0, (index as f64 / reduced_section_count as f64).clamp(0.0, 1.0)).log10();
+        *strain *= lerp(REDUCED_STRAIN_BASELINE, 1.0, scale);
+    }
+    strains.sort_by(|a, b| b.total_cmp(a));
+
+    let mut difficulty = 0.0;
+    let mut weight = 1.0;
+    for strain in strains {
+        difficulty += strain * weight;
+        weight *= DECAY_WEIGHT;
+    }
+
+    difficulty * difficulty_multiplier
+}
+
+fn wide_angle_bonus(angle: f64) -> f64 {
+    (0.75 * (angle.clamp(PI / 6.0, 5.0 * PI / 6.0) - PI / 6.0)).sin().powi(2)
+}
+
+fn acute_angle_bonus(angle: f64) -> f64 {
+    1.0 - wide_angle_bonus(angle)
+}
+
+fn evaluate_aim(objects: &[DifficultyObject], current: usize, with_sliders: bool) -> f64 {
+    let curr = &objects[current];
+    let (last, last_last) = match (previous(objects, current, 0), previous(objects, current, 1)) {
+        (Some(last), Some(last_last)) => (last, last_last),
+        _ => return 0.0,
+    };
+    if curr.base.is_spinner() || last.base.is_spinner() {
+        return 0.0;
+    }
+
+    let mut curr_velocity = curr.lazy_jump_distance / curr.strain_time;
+    if last.base.is_slider() && with_sliders {
+        let travel_velocity = last.travel_distance / last.travel_time;
+        let movement_velocity = curr.minimum_jump_distance / curr.minimum_jump_time;
+        curr_velocity = curr_velocity.max(movement_velocity + travel_velocity);
+    }
+
+    let mut prev_velocity = last.lazy_jump_distance / last.strain_time;
+    if last_last.base.is_slider() && with_sliders {
+        let travel_velocity = last_last.travel_distance / last_last.travel_time;
+        let movement_velocity = last.minimum_jump_distance / last.minimum_jump_time;
+        prev_velocity = prev_velocity.max(movement_velocity + travel_velocity);
+    }
+
+    let mut wide_bonus = 0.0;
+    let mut acute_bonus = 0.0;
+    let mut velocity_change_bonus = 0.0;
+    let mut aim_strain = curr_velocity;
+
+    // Only rhythmically consistent patterns get an angle bonus.
+    if curr.strain_time.max(last.strain_time) < 1.25 * curr.strain_time.min(last.strain_time) {
+        if let (Some(curr_angle), Some(last_angle), Some(last_last_angle)) = (curr.angle, last.angle, last_last.angle) {
+            let angle_bonus = curr_velocity.min(prev_velocity);
+
+            wide_bonus = wide_angle_bonus(curr_angle);
+            acute_bonus = acute_angle_bonus(curr_angle);
+
+            if curr.strain_time > 100.0 {
+                acute_bonus = 0.0;
+            } else {
+                acute_bonus *= acute_angle_bonus(last_angle)
+                    * angle_bonus.min(125.0 / curr.strain_time)
+                    * (PI / 2.0 * ((100.0 - curr.strain_time) / 25.0).min(1.0)).sin().powi(2)
+                    * (PI / 2.0 * (curr.lazy_jump_distance.clamp(50.0, 100.0) - 50.0) / 50.0).sin().powi(2);
+            }
+
+            wide_bonus *= angle_bonus * (1.0 - wide_bonus.min(wide_angle_bonus(last_angle).powi(3)));
+            acute_bonus *= 0.5 + 0.5 * (1.0 - acute_bonus.min(acute_angle_bonus(last_last_angle).powi(3)));
+        }
+    }
+
+    if prev_velocity.max(curr_velocity) != 0.0 {
+        let prev_velocity = (last.lazy_jump_distance + last_last.travel_distance) / last.strain_time;
+        let curr_velocity = (curr.lazy_jump_distance + last.travel_distance) / curr.strain_time;
+
+        let distance_ratio = (PI / 2.0 * (prev_velocity - curr_velocity).abs() / prev_velocity.max(curr_velocity))
+            .sin()
+            .powi(2);
+        let overlap_velocity_buff = (125.0 / curr.strain_time.min(last.strain_time)).min((prev_velocity - curr_velocity).abs());
+
+        velocity_change_bonus = overlap_velocity_buff * distance_ratio
+            * (curr.strain_time.min(last.strain_time) / curr.strain_time.max(last.strain_time)).powi(2);
+    }
+
+    let slider_bonus = match last.base.is_slider() {
+        true => last.travel_distance / last.travel_time,
+        false => 0.0,
+    };
+
+    aim_strain += (acute_bonus * 1.95).max(wide_bonus * 1.5 + velocity_change_bonus * 0.75);
+    if with_sliders {
+        aim_strain += slider_bonus * 1.35;
+    }
+
+    aim_strain
+}
+
+fn evaluate_speed(objects: &[DifficultyObject], current: usize) -> f64 {
+    const SINGLE_SPACING_THRESHOLD: f64 = 125.0;
+    const MIN_SPEED_BONUS: f64 = 75.0;
+    const SPEED_BALANCING_FACTOR: f64 = 40.0;
+
+    let curr = &objects[current];
+    if curr.base.is_spinner() {
+        return 0.0;
+    }
+
+    let prev = previous(objects, current, 0);
+    let next = objects.get(current + 1);
+
+    let mut strain_time = curr.strain_time;
+    let great_window = curr.hit_window_great;
+
+    // Streams faster than the hit window are capped, they can be played with less precise timing.
+    if let Some(prev) = prev {
+        if strain_time < great_window && prev.strain_time > strain_time {
+            strain_time = lerp(prev.strain_time, strain_time, strain_time / great_window);
+        }
+    }
+    strain_time /= ((strain_time / great_window) / 0.93).clamp(0.92, 1.0);
+
+    let mut doubletapness = 1.0;
+    if let Some(next) = next {
+        let curr_delta = curr.delta_time.max(1.0);
+        let next_delta = next.delta_time.max(1.0);
+        let speed_ratio = curr_delta / curr_delta.max((next_delta - curr_delta).abs());
+        let window_ratio = (curr_delta / great_window).min(1.0).powi(2);
+        doubletapness = speed_ratio.powf(1.0 - window_ratio);
+    }
+
+    let mut speed_bonus = 1.0;
+    if strain_time < MIN_SPEED_BONUS {
+        speed_bonus += 0.75 * ((MIN_SPEED_BONUS - strain_time) / SPEED_BALANCING_FACTOR).powi(2);
+    }
+
+    let travel_distance = prev.map_or(0.0, |prev| prev.travel_distance);
+    let distance = SINGLE_SPACING_THRESHOLD.min(travel_distance + curr.minimum_jump_distance);
+
+    (speed_bonus + speed_bonus * (distance / SINGLE_SPACING_THRESHOLD).powf(3.5)) * doubletapness / strain_time
+}
+
+fn evaluate_rhythm(objects: &[DifficultyObject], current: usize) -> f64 {
+    const HISTORY_TIME_MAX: f64 = 5000.0;
+    const RHYTHM_MULTIPLIER: f64 = 0.75;
+
+    let curr = &objects[current];
+    if curr.base.is_spinner() {
+        return 0.0;
+    }
+
+    let mut previous_island_size = 0;
+    let mut rhythm_complexity_sum = 0.0;
+    let mut island_size = 1;
+    let mut start_ratio = 0.0;
+    let mut first_delta_switch = false;
+
+    let historical_note_count = current.min(32);
+    let mut rhythm_start = 0;
+    while rhythm_start + 2 < historical_note_count
+        && curr.start_time - objects[current - rhythm_start - 1].start_time < HISTORY_TIME_MAX
+    {
+        rhythm_start += 1;
+    }
+
+    for i in (1..=rhythm_start).rev() {
+        let curr_object = &objects[current - i];
+        let prev_object = &objects[current - i - 1];
+        let last_object = &objects[current - i - 2];
+
+        let decay = (HISTORY_TIME_MAX - (curr.start_time - curr_object.start_time)) / HISTORY_TIME_MAX;
+        let decay = ((historical_note_count - i) as f64 / historical_note_count as f64).min(decay);
+
+        let curr_delta = curr_object.strain_time;
+        let prev_delta = prev_object.strain_time;
+        let last_delta = last_object.strain_time;
+
+        let curr_ratio = 1.0 + 6.0 * 0.5_f64.min((PI / (prev_delta.min(curr_delta) / prev_delta.max(curr_delta))).sin().powi(2));
+
+        let window_penalty = (((prev_delta - curr_delta).abs() - curr_object.hit_window_great * 0.3).max(0.0)
+            / (curr_object.hit_window_great * 0.3))
+            .min(1.0);
+        let mut effective_ratio = window_penalty * curr_ratio;
+
+        if first_delta_switch {
+            if !(prev_delta > 1.25 * curr_delta || prev_delta * 1.25 < curr_delta) {
+                if island_size < 7 {
+                    island_size += 1;
+                }
+            } else {
+                if curr_object.base.is_slider() {
+                    effective_ratio *= 0.125;
+                }
+                if prev_object.base.is_slider() {
+                    effective_ratio *= 0.25;
+                }
+                if previous_island_size == island_size {
+                    effective_ratio *= 0.25;
+                }
+                if previous_island_size % 2 == island_size % 2 {
+                    effective_ratio *= 0.5;
+                }
+                if last_delta > prev_delta + 10.0 && prev_delta > curr_delta + 10.0 {
+                    effective_ratio *= 0.125;
+                }
+
+                rhythm_complexity_sum += (effective_ratio * start_ratio).sqrt()
+                    * decay
+                    * (4.0 + island_size as f64).sqrt()
+                    / 2.0
+                    * (4.0 + previous_island_size as f64).sqrt()
+                    / 2.0;
+
+                start_ratio = effective_ratio;
+                previous_island_size = island_size;
+
+                if prev_delta * 1.25 < curr_delta {
+                    first_delta_switch = false;
+                }
+                island_size = 1;
+            }
+        } else if prev_delta > 1.25 * curr_delta {
+            first_delta_switch = true;
+            start_ratio = effective_ratio;
+            island_size = 1;
+        }
+    }
+
+    (4.0 + rhythm_complexity_sum * RHYTHM_MULTIPLIER).sqrt() / 2.0
+}
+
+/// How visible `object` is at `time` (both unscaled), 0 after it should have been hit.
+fn opacity_at(object: &OsuObject, scaling: &Scaling, time: f64, hidden: bool) -> f64 {
+    if time > object.start_time {
+        return 0.0;
+    }
+
+    let fade_in_start = object.start_time - scaling.preempt;
+    let fade_in = ((time - fade_in_start) / scaling.fade_in).clamp(0.0, 1.0);
+
+    if !hidden {
+        return fade_in;
+    }
+
+    let fade_out_start = object.start_time - scaling.preempt + scaling.fade_in;
+    let fade_out_duration = scaling.preempt * 0.3;
+    fade_in.min(1.0 - ((time - fade_out_start) / fade_out_duration).clamp(0.0, 1.0))
+}
+
+fn evaluate_flashlight(objects: &[DifficultyObject], current: usize, scaling: &Scaling, hidden: bool) -> f64 {
+    const MAX_OPACITY_BONUS: f64 = 0.4;
+    const HIDDEN_BONUS: f64 = 0.2;
+    const MIN_VELOCITY: f64 = 0.5;
+    const SLIDER_MULTIPLIER: f64 = 1.3;
+    const MIN_ANGLE_MULTIPLIER: f64 = 0.2;
+
+    let curr = &objects[current];
+    if curr.base.is_spinner() {
+        return 0.0;
+    }
+
+    let scaling_factor = 52.0 / scaling.radius;
+    let position = stacked_position(curr.base, scaling);
+
+    let mut small_distance_nerf = 1.0;
+    let mut cumulative_strain_time = 0.0;
+    let mut result = 0.0;
+    let mut last = curr;
+    let mut angle_repeat_count = 0.0;
+
+    for i in 0..current.min(10) {
+        let object = &objects[current - i - 1];
+
+        if !object.base.is_spinner() {
+            let jump_distance = (position - stacked_end_position(object.base, scaling)).length();
+            cumulative_strain_time += last.strain_time;
+
+            if i == 0 {
+                small_distance_nerf = (jump_distance / 75.0).min(1.0);
+            }
+
+            let stack_nerf = ((object.lazy_jump_distance / scaling_factor) / 25.0).min(1.0);
+            let opacity_bonus = 1.0 + MAX_OPACITY_BONUS * (1.0 - opacity_at(curr.base, scaling, object.base.start_time, hidden));
+
+            result += stack_nerf * opacity_bonus * scaling_factor * jump_distance / cumulative_strain_time;
+
+            if let (Some(angle), Some(curr_angle)) = (object.angle, curr.angle) {
+                if (angle - curr_angle).abs() < 0.02 {
+                    angle_repeat_count += (1.0 - 0.1 * i as f64).max(0.0);
+                }
+            }
+        }
+
+        last = object;
+    }
+
+    result = (small_distance_nerf * result).powi(2);
+    if hidden {
+        result *= 1.0 + HIDDEN_BONUS;
+    }
+    result *= MIN_ANGLE_MULTIPLIER + (1.0 - MIN_ANGLE_MULTIPLIER) / (angle_repeat_count + 1.0);
+
+    if let ObjectKind::Slider(slider) = &curr.base.kind {
+        let pixel_travel_distance = slider.lazy_travel_distance / scaling_factor;
+        let mut slider_bonus = (pixel_travel_distance / curr.travel_time - MIN_VELOCITY).max(0.0).sqrt() * pixel_travel_distance;
+        if slider.repeats > 0 {
+            slider_bonus /= (slider.repeats + 1) as f64;
+        }
+        result += slider_bonus * SLIDER_MULTIPLIER;
+    }
+
+    result
+}
+
+pub struct Aim {
+    with_sliders: bool,
+    current_strain: f64,
+    peaks: Peaks,
+}
+
+impl Aim {
+    pub fn new(with_sliders: bool) -> Aim {
+        Aim {
+            with_sliders,
+            current_strain: 0.0,
+            peaks: Peaks::default(),
+        }
+    }
+
+    pub fn difficulty_value(&mut self) -> f64 {
+        reduced_difficulty_value(self.strain_peaks(), 10, DEFAULT_DIFFICULTY_MULTIPLIER)
+    }
+}
+
+impl StrainSkill for Aim {
+    fn peaks(&mut self) -> &mut Peaks {
+        &mut self.peaks
+    }
+
+    fn strain_value_at(&mut self, objects: &[DifficultyObject], current: usize) -> f64 {
+        self.current_strain *= strain_decay(0.15, objects[current].delta_time);
+        self.current_strain += evaluate_aim(objects, current, self.with_sliders) * 23.55;
+        self.current_strain
+    }
+
+    fn initial_strain(&self, objects: &[DifficultyObject], time: f64, current: usize) -> f64 {
+        let previous_start = previous(objects, current, 0).map_or(0.0, |object| object.start_time);
+        self.current_strain * strain_decay(0.15, time - previous_start)
+    }
+}
+
+#[derive(Default)]
+pub struct Speed {
+    current_strain: f64,
+    current_rhythm: f64,
+    object_strains: Vec<f64>,
+    peaks: Peaks,
+}
+
+impl Speed {
+    pub fn difficulty_value(&mut self) -> f64 {
+        reduced_difficulty_value(self.strain_peaks(), 5, 1.04)
+    }
+
+    /// Number of notes weighted by how close their strain is to the hardest one.
+    pub fn relevant_note_count(&self) -> f64 {
+        let max_strain = self.object_strains.iter().copied().fold(0.0, f64::max);
+        if max_strain == 0.0 {
+            return 0.0;
+        }
+
+        self.object_strains
+            .iter()
+            .map(|strain| 1.0 / (1.0 + (-(strain / max_strain * 12.0 - 6.0)).exp()))
+            .sum()
+    }
+}
+
+impl StrainSkill for Speed {
+    fn peaks(&mut self) -> &mut Peaks {
+        &mut self.peaks
+    }
+
+    fn strain_value_at(&mut self, objects: &[DifficultyObject], current: usize) -> f64 {
+        self.current_strain *= strain_decay(0.3, objects[current].strain_time);
+        self.current_strain += evaluate_speed(objects, current) * 1375.0;
+        self.current_rhythm = evaluate_rhythm(objects, current);
+
+        let total_strain = self.current_strain * self.current_rhythm;
+        self.object_strains.push(total_strain);
+        total_strain
+    }
+
+    fn initial_strain(&self, objects: &[DifficultyObject], time: f64, current: usize) -> f64 {
+        let previous_start = previous(objects, current, 0).map_or(0.0, |object| object.start_time);
+        self.current_strain * self.current_rhythm * strain_decay(0.3, time - previous_start)
+    }
+}
+
+pub struct Flashlight {
+    scaling: Scaling,
+    hidden: bool,
+    current_strain: f64,
+    peaks: Peaks,
+}
+
+impl Flashlight {
+    pub fn new(scaling: Scaling, hidden: bool) -> Flashlight {
+        Flashlight {
+            scaling,
+            hidden,
+            current_strain: 0.0,
+            peaks: Peaks::default(),
+        }
+    }
+
+    pub fn difficulty_value(&mut self) -> f64 {
+        self.strain_peaks().iter().sum::<f64>() * DEFAULT_DIFFICULTY_MULTIPLIER
+    }
+}
+
+impl StrainSkill for Flashlight {
+    fn peaks(&mut self) -> &mut Peaks {
+        &mut self.peaks
+    }
+
+    fn strain_value_at(&mut self, objects: &[DifficultyObject], current: usize) -> f64 {
+        self.current_strain *= strain_decay(0.15, objects[current].delta_time);
+        self.current_strain += evaluate_flashlight(objects, current, &self.scaling, self.hidden) * 0.052;
+        self.current_strain
+    }
+
+    fn initial_strain(&self, objects: &[DifficultyObject], time: f64, current: usize) -> f64 {
+        let previous_start = previous(objects, current, 0).map_or(0.0, |object| object.start_time);
+        self.current_strain * strain_decay(0.15, time - previous_start)
+    }
+}
+
+/// Runs every object through the skills in order.
+pub fn process(objects: &[DifficultyObject], aim: &mut Aim, aim_no_sliders: &mut Aim, speed: &mut Speed, flashlight: &mut Flashlight) {
+    for current in 0..objects.len() {
+        aim.process(objects, current);
+        aim_no_sliders.process(objects, current);
+        speed.process(objects, current);
+        flashlight.process(objects, current);
+    }
+}
@@ -3,6 +3,12 @@ mod osu;
 mod crawler;
 mod api;
 mod ops;
+mod storage;
+mod archive;
+mod difficulty;
+mod media;
+#[cfg(test)]
+mod testing;
 
 use std::{time::Instant, fs::copy, sync::Arc};
 
@@ -12,94 +18,9 @@ use meilisearch_sdk::client::Client;
 use tracing::{info, error, level_filters::LevelFilter};
 use tracing_subscriber::util::SubscriberInitExt;
 
-use crate::{config::{Configuration, CONFIG_VERSION, Config}, crawler::Context, osu::client::log_in_using_credentials};
+use crate::{config::{config_path, load_stored, store, Configuration, CONFIG_VERSION, Config}, crawler::Context, ops::indexes::ensure_indexes, osu::client::log_in_using_credentials};
 use crate::osu::client::{OsuClient, OsuApi};
-
-async fn ensure_filters(client: &Client, index: impl ToString, filters: &[&str]) {
-    let filter = client.get_index(index.to_string()).await;
-
-    match filter {
-        Ok(filter) => {
-            let mut filters_to_add = Vec::new();
-            let filter_names = filter.get_filterable_attributes().await.unwrap();
-            for &filter_name in filters {
-                if !filter_names.contains(&filter_name.to_string()) {
-                    filters_to_add.push(filter_name);
-                }
-            }
-
-            info!("Filterable atrributes of {}: {:#?}", index.to_string(), filter_names);
-
-            if !filters_to_add.is_empty() {
-                info!("Updating filters");
-                let update_filter_task = filter.set_filterable_attributes(filters).await;
-                match update_filter_task {
-                    Err(err) => {
-                        error!("Failed to run update task, {}", err)
-                    },
-                    Ok(task) => {
-                        info!("Task has been enqueued, id: {}. awaiting", task.task_uid);
-                        match task.wait_for_completion(&client, None, None).await {
-                            Err(err) => {
-                                error!("Failed to run update task, {}", err)
-                            },
-                            Ok(_) => {
-                                info!("Task has been completed");
-                            }
-                        }
-                    }
-                }
-            }
-        },
-        Err(_) => {
-            
-        }
-    };
-}
-
-
-async fn ensure_sort(client: &Client, index: impl ToString, sort: &[&str]) {
-    let filter = client.get_index(index.to_string()).await;
-
-    match filter {
-        Ok(filter) => {
-            let mut filters_to_add = Vec::new();
-            let filter_names = filter.get_sortable_attributes().await.unwrap();
-            for &filter_name in sort {
-                if !filter_names.contains(&filter_name.to_string()) {
-                    filters_to_add.push(filter_name);
-                }
-            }
-
-            info!("Sortable atrributes of {}: {:#?}", index.to_string(), filter_names);
-
-            if !filters_to_add.is_empty() {
-                info!("Updating sortable attributes");
-                let update_filter_task = filter.set_sortable_attributes(sort).await;
-                match update_filter_task {
-                    Err(err) => {
-                        error!("Failed to run update task, {}", err)
-                    },
-                    Ok(task) => {
-                        info!("Task has been enqueued, id: {}. awaiting", task.task_uid);
-                        match task.wait_for_completion(&client, None, None).await {
-                            Err(err) => {
-                                error!("Failed to run update task, {}", err)
-                            },
-                            Ok(_) => {
-                                info!("Task has been completed");
-                            }
-                        }
-                    }
-                }
-            }
-        },
-        Err(_) => {
-            
-        }
-    };
-}
-
+use crate::storage::Storage;
 
 #[tokio::main]
 async fn main() {
@@ -110,10 +31,10 @@ async fn main() {
     .with_thread_names(false)
     .finish().init();
 
-    let cfg_path = confy::get_configuration_file_path("mirria", None).unwrap();
+    let cfg_path = config_path().unwrap();
     info!("Configuration file path: {}", cfg_path.display());
 
-    let cfg: Result<Configuration, ConfyError> = confy::load("mirria", None);
+    let cfg: Result<Configuration, ConfyError> = load_stored();
     
     if cfg.is_err() {
         let err = cfg.unwrap_err();
@@ -122,7 +43,7 @@ async fn main() {
                 let config_file = format!("config.bak.{}", Instant::now().elapsed().as_secs());
 
                 copy(cfg_path, "config.old.yml").expect("Error while copying configuration file");
-                let result = confy::store("mirria", None, Configuration::default());
+                let result = store(Configuration::default());
                 if result.is_err() {
                     error!("Error while storing configuration");
                     error!("{:#?}", result.unwrap_err());
@@ -213,18 +134,24 @@ async fn main() {
 
     let meiliclient = meiliclient.unwrap();
 
-    ensure_filters(&meiliclient, "beatmapset", &["beatmaps.id", "id", "title", "title_unicode", "beatmaps.checksum", "beatmaps.mode", "status"]).await;
-    ensure_filters(&meiliclient, "downloads", &["id"]).await;
-    ensure_sort(&meiliclient, "beatmapset", &["id", "title", "title_unicode", "last_updated", "ranked_date", "submitted_date", "play_count"]).await;
-    
-
+    ensure_indexes(&meiliclient, &configuration.meilisearch.index_prefix).await;
 
     info!("Meiliclient is up and running");
 
+    let storage = match Storage::from_config(&configuration) {
+        Ok(storage) => storage,
+        Err(err) => {
+            error!("Error while creating storage");
+            error!("{:#?}", err);
+            return;
+        }
+    };
+
     let context = Context {
         config: Arc::new(configuration.clone()),
         meili_client: Arc::new(meiliclient),
-        osu: osu_client.unwrap()
+        osu: osu_client.unwrap(),
+        storage
     };
 
     let configuration_env: Config = Config::parse();
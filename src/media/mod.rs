@@ -0,0 +1,275 @@
+use std::{fmt, io::Cursor};
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageError};
+
+/// Length of audio previews, same as osu!'s.
+pub const PREVIEW_DURATION_MS: f64 = 10_000.0;
+/// osu! starts previews of maps without a `PreviewTime` at 40% of the song.
+const DEFAULT_PREVIEW_POSITION: f64 = 0.4;
+
+#[derive(Debug)]
+pub enum MediaError {
+    UnsupportedAudio,
+    Image(ImageError),
+}
+
+impl std::error::Error for MediaError {}
+
+impl fmt::Display for MediaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MediaError::UnsupportedAudio => write!(f, "Audio is not an mp3 file"),
+            MediaError::Image(err) => write!(f, "Invalid image: {}", err),
+        }
+    }
+}
+
+impl From<ImageError> for MediaError {
+    fn from(err: ImageError) -> Self {
+        MediaError::Image(err)
+    }
+}
+
+/// Fixed thumbnail sizes of backgrounds, cropped to fill.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThumbnailSize {
+    Large,
+    Medium,
+    Small,
+}
+
+impl ThumbnailSize {
+    pub fn parse(value: &str) -> Option<ThumbnailSize> {
+        match value {
+            "large" => Some(ThumbnailSize::Large),
+            "medium" => Some(ThumbnailSize::Medium),
+            "small" => Some(ThumbnailSize::Small),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ThumbnailSize::Large => "large",
+            ThumbnailSize::Medium => "medium",
+            ThumbnailSize::Small => "small",
+        }
+    }
+
+    pub fn dimensions(self) -> (u32, u32) {
+        match self {
+            ThumbnailSize::Large => (1280, 720),
+            ThumbnailSize::Medium => (640, 360),
+            ThumbnailSize::Small => (160, 90),
+        }
+    }
+}
+
+/// Scales and crops `source` to exactly `size`, encoded as JPEG.
+pub fn thumbnail(source: &[u8], size: ThumbnailSize) -> Result<Vec<u8>, MediaError> {
+    let (width, height) = size.dimensions();
+    let image = image::load_from_memory(source)?.resize_to_fill(width, height, FilterType::Triangle).to_rgb8();
+
+    let mut output = Cursor::new(Vec::new());
+    JpegEncoder::new_with_quality(&mut output, 85).encode_image(&image)?;
+    Ok(output.into_inner())
+}
+
+/// `image/*` content type of an encoded image.
+pub fn image_content_type(data: &[u8]) -> &'static str {
+    match image::guess_format(data) {
+        Ok(image::ImageFormat::Png) => "image/png",
+        Ok(image::ImageFormat::Jpeg) => "image/jpeg",
+        Ok(format) => format.to_mime_type(),
+        Err(_) => "application/octet-stream",
+    }
+}
+
+struct Frame {
+    offset: usize,
+    length: usize,
+    /// Milliseconds.
+    duration: f64,
+}
+
+const BITRATES_V1: [[u32; 16]; 3] = [
+    [0, 32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448, 0],
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384, 0],
+    [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 0],
+];
+const BITRATES_V2: [[u32; 16]; 2] = [
+    [0, 32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256, 0],
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160, 0],
+];
+const SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+
+/// Length and duration of the MPEG audio frame starting with `header`, if it is one.
+fn parse_frame_header(header: &[u8]) -> Option<(usize, f64)> {
+    if header.len() < 4 || header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+        return None;
+    }
+
+    let version = (header[1] >> 3) & 0b11;
+    let layer = (header[1] >> 1) & 0b11;
+    let bitrate_index = (header[2] >> 4) as usize;
+    let sample_rate_index = ((header[2] >> 2) & 0b11) as usize;
+    let padding = ((header[2] >> 1) & 1) as usize;
+
+    if version == 0b01 || layer == 0 || sample_rate_index == 3 {
+        return None;
+    }
+
+    // Layer bits are 3 for layer I, 2 for II and 1 for III.
+    let layer = 4 - layer as usize;
+    let mpeg1 = version == 0b11;
+
+    let bitrate = match mpeg1 {
+        true => BITRATES_V1[layer - 1][bitrate_index],
+        false => BITRATES_V2[if layer == 1 { 0 } else { 1 }][bitrate_index],
+    } as usize
+        * 1000;
+    if bitrate == 0 {
+        return None;
+    }
+
+    let sample_rate = SAMPLE_RATES[sample_rate_index]
+        >> match version {
+            0b11 => 0,
+            0b10 => 1,
+            _ => 2,
+        };
+    let sample_rate = sample_rate as usize;
+
+    let (samples, length) = match layer {
+        1 => (384, (12 * bitrate / sample_rate + padding) * 4),
+        2 => (1152, 144 * bitrate / sample_rate + padding),
+        _ if mpeg1 => (1152, 144 * bitrate / sample_rate + padding),
+        _ => (576, 72 * bitrate / sample_rate + padding),
+    };
+
+    Some((length, samples as f64 * 1000.0 / sample_rate as f64))
+}
+
+/// Size of a leading ID3v2 tag.
+fn id3_length(data: &[u8]) -> usize {
+    if data.len() < 10 || &data[..3] != b"ID3" {
+        return 0;
+    }
+
+    let size = data[6..10].iter().fold(0usize, |size, byte| (size << 7) | (*byte & 0x7F) as usize);
+    let footer = if data[5] & 0x10 != 0 { 10 } else { 0 };
+    10 + size + footer
+}
+
+fn mp3_frames(data: &[u8]) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    let mut offset = id3_length(data);
+
+    while offset + 4 <= data.len() {
+        match parse_frame_header(&data[offset..]) {
+            Some((length, duration)) if length > 4 && offset + length <= data.len() => {
+                frames.push(Frame { offset, length, duration });
+                offset += length;
+            }
+            // Garbage between frames or a trailing tag, resynchronise.
+            _ => offset += 1,
+        }
+    }
+
+    // A leading Xing/Info frame describes the whole file and would be wrong for a clip.
+    if let Some(first) = frames.first() {
+        let content = &data[first.offset..first.offset + first.length];
+        if content.windows(4).take(64).any(|window| window == b"Xing" || window == b"Info") {
+            frames.remove(0);
+        }
+    }
+
+    frames
+}
+
+/// The frames of the mp3 `data` covering `PREVIEW_DURATION_MS` from `preview_time`,
+/// or from 40% into the song when it is negative. Frames are copied without re-encoding.
+pub fn mp3_preview(data: &[u8], preview_time: f64) -> Result<Vec<u8>, MediaError> {
+    let frames = mp3_frames(data);
+    if frames.is_empty() {
+        return Err(MediaError::UnsupportedAudio);
+    }
+
+    let total: f64 = frames.iter().map(|frame| frame.duration).sum();
+    let mut start = match preview_time < 0.0 {
+        true => total * DEFAULT_PREVIEW_POSITION,
+        false => preview_time,
+    };
+    // Previews past the end start early enough to still be a full clip where possible.
+    if start + PREVIEW_DURATION_MS > total {
+        start = (total - PREVIEW_DURATION_MS).max(0.0);
+    }
+
+    let mut clip = Vec::new();
+    let mut time = 0.0;
+    for frame in frames {
+        if time >= start + PREVIEW_DURATION_MS {
+            break;
+        }
+        if time + frame.duration > start {
+            clip.extend_from_slice(&data[frame.offset..frame.offset + frame.length]);
+        }
+        time += frame.duration;
+    }
+
+    Ok(clip)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, ImageOutputFormat, RgbImage};
+
+    use super::*;
+
+    /// MPEG-1 layer III, 128kbps, 44.1kHz: 417 bytes and ~26.12ms per frame.
+    fn mp3(frames: usize) -> Vec<u8> {
+        let mut data = b"ID3\x04\x00\x00\x00\x00\x00\x05tag!!".to_vec();
+        for index in 0..frames {
+            let mut frame = vec![index as u8; 417];
+            frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+            data.extend(frame);
+        }
+        data
+    }
+
+    #[test]
+    fn cuts_preview_clips_on_frame_boundaries() {
+        let frame_duration = 1152.0 * 1000.0 / 44100.0;
+        let source = mp3(1000);
+
+        let clip = mp3_preview(&source, 5000.0).unwrap();
+        assert_eq!(clip.len() % 417, 0);
+        assert!((clip.len() / 417) as f64 * frame_duration >= PREVIEW_DURATION_MS);
+        // The clip starts at the frame playing at 5 seconds.
+        assert_eq!(clip[4], (5000.0 / frame_duration) as u8);
+
+        // Without a preview time the clip starts at 40% of the song, frame 400.8 here.
+        let clip = mp3_preview(&mp3(1002), -1.0).unwrap();
+        assert_eq!(clip[4], (400 % 256) as u8);
+
+        // Short songs are returned whole.
+        assert_eq!(mp3_preview(&mp3(10), 0.0).unwrap().len(), 4170);
+        assert!(matches!(mp3_preview(b"OggS not an mp3", 0.0), Err(MediaError::UnsupportedAudio)));
+    }
+
+    #[test]
+    fn crops_thumbnails_to_fixed_sizes() {
+        let mut source = Cursor::new(Vec::new());
+        image::DynamicImage::ImageRgb8(RgbImage::new(300, 100))
+            .write_to(&mut source, ImageOutputFormat::Png)
+            .unwrap();
+        let source = source.into_inner();
+        assert_eq!(image_content_type(&source), "image/png");
+
+        let small = thumbnail(&source, ThumbnailSize::Small).unwrap();
+        assert_eq!(image_content_type(&small), "image/jpeg");
+        assert_eq!(image::load_from_memory(&small).unwrap().dimensions(), (160, 90));
+
+        assert!(thumbnail(b"not an image", ThumbnailSize::Small).is_err());
+    }
+}
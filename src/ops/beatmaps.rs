@@ -7,6 +7,8 @@ use crate::{
     osu::types::{Beatmap, Beatmapset},
 };
 
+use super::filter::Filter;
+
 #[derive(Debug)]
 pub enum DatabaseError {
     RecordNotFound,
@@ -26,10 +28,9 @@ impl fmt::Display for DatabaseError {
 
 pub async fn get_beatmap_by_id(ctx: Context, id: i64) -> Result<Beatmap, DatabaseError> {
     let response = ctx
-        .meili_client
         .index("beatmapset")
         .search()
-        .with_filter(format!("beatmaps.id = {}", id).as_str())
+        .with_filter(&Filter::eq("beatmaps.id", id).to_string())
         .execute::<Beatmapset>()
         .await;
 
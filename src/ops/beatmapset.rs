@@ -1,16 +1,57 @@
+use std::{collections::BTreeMap, fmt};
+
+use chrono::DateTime;
+use meilisearch_sdk::{errors::Error, search::Selectors, task_info::TaskInfo};
+use serde_derive::Serialize;
 use tracing::error;
 
-use crate::{crawler::Context, osu::types::Beatmapset};
+use crate::{crawler::Context, osu::types::{Beatmap, Beatmapset}};
+
+use super::{beatmaps::DatabaseError, cursor::PageCursor, filter::{Filter, Operator}};
+
 
-use super::beatmaps::DatabaseError;
+/// Unix timestamp of one of osu!'s dates, 0 for missing ones like the ranked date of unranked sets.
+pub fn timestamp(date: Option<&str>) -> i64 {
+    date.and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+        .map_or(0, |date| date.timestamp())
+}
+
+/// A beatmapset as it is indexed. Meilisearch only compares numbers in filters,
+/// so the dates are stored as timestamps as well for the cursors of date sorts. Sets indexed
+/// before they were stored sort last in date orders until a crawl pass indexes them again.
+#[derive(Serialize)]
+struct BeatmapsetDocument<'a> {
+    #[serde(flatten)]
+    beatmapset: &'a Beatmapset,
+    last_updated_at: i64,
+    ranked_at: i64,
+    submitted_at: i64,
+}
+
+impl<'a> BeatmapsetDocument<'a> {
+    fn new(beatmapset: &'a Beatmapset) -> BeatmapsetDocument<'a> {
+        BeatmapsetDocument {
+            beatmapset,
+            last_updated_at: timestamp(Some(&beatmapset.last_updated)),
+            ranked_at: timestamp(beatmapset.ranked_date.as_deref()),
+            submitted_at: timestamp(Some(&beatmapset.submitted_date)),
+        }
+    }
+}
+
+/// Adds or replaces `beatmapsets` in the index, every write of a beatmapset goes through here.
+pub async fn add_beatmapsets(ctx: &Context, beatmapsets: &[Beatmapset]) -> Result<TaskInfo, Error> {
+    let documents: Vec<BeatmapsetDocument> = beatmapsets.iter().map(BeatmapsetDocument::new).collect();
+
+    ctx.index("beatmapset").add_documents(&documents, Some("id")).await
+}
 
 
 pub async fn get_beatmapset_by_hash(ctx: Context, checksum: impl ToString) -> Result<Beatmapset, DatabaseError> {
     let response = ctx
-        .meili_client
         .index("beatmapset")
         .search()
-        .with_filter(format!("beatmaps.checksum = {}", checksum.to_string()).as_str())
+        .with_filter(&Filter::eq("beatmaps.checksum", checksum.to_string()).to_string())
         .execute::<Beatmapset>()
         .await;
 
@@ -20,7 +61,7 @@ pub async fn get_beatmapset_by_hash(ctx: Context, checksum: impl ToString) -> Re
 
     let response = response.unwrap();
 
-    if response.hits.len() == 0 {
+    if response.hits.is_empty() {
         return Err(DatabaseError::RecordNotFound);
     }
 
@@ -32,10 +73,9 @@ pub async fn get_beatmapset_by_hash(ctx: Context, checksum: impl ToString) -> Re
 
 pub async fn get_beatmapset_by_id(ctx: Context, id: i64) -> Result<Beatmapset, DatabaseError> {
     let response = ctx
-        .meili_client
         .index("beatmapset")
         .search()
-        .with_filter(format!("id = {}", id).as_str())
+        .with_filter(&Filter::eq("id", id).to_string())
         .execute::<Beatmapset>()
         .await;
 
@@ -45,7 +85,7 @@ pub async fn get_beatmapset_by_id(ctx: Context, id: i64) -> Result<Beatmapset, D
 
     let response = response.unwrap();
 
-    if response.hits.len() == 0 {
+    if response.hits.is_empty() {
         return Err(DatabaseError::RecordNotFound);
     }
 
@@ -57,23 +97,46 @@ pub async fn get_beatmapset_by_id(ctx: Context, id: i64) -> Result<Beatmapset, D
 
 pub async fn get_beatmapset_by_beatmap_id(ctx: Context, id: i64) -> Result<Beatmapset, DatabaseError> {
     let response = ctx
-        .meili_client
         .index("beatmapset")
         .search()
-        .with_filter(format!("beatmaps.id = {}", id).as_str())
+        .with_filter(&Filter::eq("beatmaps.id", id).to_string())
         .execute::<Beatmapset>()
         .await;
 
-    if response.is_err() {
-    
-        let err = response.unwrap_err();
-        error!("{:#?}", err);
-        return Err(DatabaseError::InternalDatabaseError);
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            error!("{:#?}", err);
+            return Err(DatabaseError::InternalDatabaseError);
+        }
+    };
+
+    if response.hits.is_empty() {
+        return Err(DatabaseError::RecordNotFound);
     }
 
-    let response = response.unwrap();
+    let beatmapset = &response.hits.first().unwrap().result;
 
-    if response.hits.len() == 0 {
+    Ok(beatmapset.clone())
+}
+pub async fn get_latest_updated_beatmapset(ctx: Context) -> Result<Beatmapset, DatabaseError> {
+    let response = ctx
+        .index("beatmapset")
+        .search()
+        .with_sort(&["last_updated:desc"])
+        .with_limit(1)
+        .execute::<Beatmapset>()
+        .await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            error!("{:#?}", err);
+            return Err(DatabaseError::InternalDatabaseError);
+        }
+    };
+
+    if response.hits.is_empty() {
         return Err(DatabaseError::RecordNotFound);
     }
 
@@ -81,3 +144,603 @@ pub async fn get_beatmapset_by_beatmap_id(ctx: Context, id: i64) -> Result<Beatm
 
     Ok(beatmapset.clone())
 }
+
+
+pub async fn get_beatmapsets_after_id(ctx: Context, id: i64, limit: usize) -> Result<Vec<Beatmapset>, DatabaseError> {
+    let response = ctx
+        .index("beatmapset")
+        .search()
+        .with_filter(&Filter::gt("id", id).to_string())
+        .with_sort(&["id:asc"])
+        .with_limit(limit)
+        .execute::<Beatmapset>()
+        .await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            error!("{:#?}", err);
+            return Err(DatabaseError::InternalDatabaseError);
+        }
+    };
+
+    Ok(response.hits.into_iter().map(|hit| hit.result).collect())
+}
+
+
+pub async fn get_beatmapsets_by_ids(ctx: Context, ids: &[i64]) -> Result<Vec<Beatmapset>, DatabaseError> {
+    let response = ctx
+        .index("beatmapset")
+        .search()
+        .with_filter(&Filter::is_in("id", ids.iter().copied()).to_string())
+        .with_limit(ids.len())
+        .execute::<Beatmapset>()
+        .await;
+
+    let response = match response {
+        Ok(response) => response,
+        Err(err) => {
+            error!("{:#?}", err);
+            return Err(DatabaseError::InternalDatabaseError);
+        }
+    };
+
+    Ok(response.hits.into_iter().map(|hit| hit.result).collect())
+}
+
+
+/// Per-difficulty attributes that searches can be narrowed down by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DifficultyAttribute {
+    Stars,
+    ApproachRate,
+    OverallDifficulty,
+    CircleSize,
+    DrainRate,
+    Bpm,
+    /// Total length in seconds.
+    Length,
+    /// Drain length in seconds.
+    DrainLength,
+    MaxCombo,
+}
+
+impl DifficultyAttribute {
+    /// Name used in search parameters.
+    pub fn name(self) -> &'static str {
+        match self {
+            DifficultyAttribute::Stars => "stars",
+            DifficultyAttribute::ApproachRate => "ar",
+            DifficultyAttribute::OverallDifficulty => "od",
+            DifficultyAttribute::CircleSize => "cs",
+            DifficultyAttribute::DrainRate => "hp",
+            DifficultyAttribute::Bpm => "bpm",
+            DifficultyAttribute::Length => "length",
+            DifficultyAttribute::DrainLength => "drain",
+            DifficultyAttribute::MaxCombo => "combo",
+        }
+    }
+
+    /// Filterable attribute of the beatmapset index.
+    pub fn field(self) -> &'static str {
+        match self {
+            DifficultyAttribute::Stars => "beatmaps.difficulty_rating",
+            DifficultyAttribute::ApproachRate => "beatmaps.ar",
+            DifficultyAttribute::OverallDifficulty => "beatmaps.accuracy",
+            DifficultyAttribute::CircleSize => "beatmaps.cs",
+            DifficultyAttribute::DrainRate => "beatmaps.drain",
+            DifficultyAttribute::Bpm => "beatmaps.bpm",
+            DifficultyAttribute::Length => "beatmaps.total_length",
+            DifficultyAttribute::DrainLength => "beatmaps.hit_length",
+            DifficultyAttribute::MaxCombo => "beatmaps.max_combo",
+        }
+    }
+
+    fn value(self, beatmap: &Beatmap) -> Option<f64> {
+        match self {
+            DifficultyAttribute::Stars => Some(beatmap.stars),
+            DifficultyAttribute::ApproachRate => Some(beatmap.ar),
+            DifficultyAttribute::OverallDifficulty => Some(beatmap.od),
+            DifficultyAttribute::CircleSize => Some(beatmap.cs),
+            DifficultyAttribute::DrainRate => Some(beatmap.hp),
+            DifficultyAttribute::Bpm => Some(beatmap.bpm),
+            DifficultyAttribute::Length => Some(beatmap.seconds_total as f64),
+            DifficultyAttribute::DrainLength => Some(beatmap.seconds_drain as f64),
+            DifficultyAttribute::MaxCombo => beatmap.max_combo.map(|combo| combo as f64),
+        }
+    }
+}
+
+/// Bounds on a difficulty attribute, both must be finite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifficultyRange {
+    pub attribute: DifficultyAttribute,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// Excludes the bounds themselves.
+    pub exclusive: bool,
+}
+
+impl DifficultyRange {
+    fn filters(&self) -> Vec<Filter> {
+        let field = self.attribute.field();
+        let (greater, less) = match self.exclusive {
+            true => (Operator::Greater, Operator::Less),
+            false => (Operator::GreaterOrEqual, Operator::LessOrEqual),
+        };
+
+        let mut filters = Vec::new();
+        if let Some(min) = self.min {
+            filters.push(Filter::compare(field, greater, min));
+        }
+        if let Some(max) = self.max {
+            filters.push(Filter::compare(field, less, max));
+        }
+        filters
+    }
+
+    fn matches(&self, beatmap: &Beatmap) -> bool {
+        match self.attribute.value(beatmap) {
+            Some(value) if self.exclusive => self.min.is_none_or(|min| value > min) && self.max.is_none_or(|max| value < max),
+            Some(value) => self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max),
+            None => false,
+        }
+    }
+}
+
+/// Orders of osu!'s beatmap listing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Id,
+    Title,
+    Artist,
+    /// Hardest difficulty when descending, easiest when ascending.
+    Difficulty,
+    Ranked,
+    Updated,
+    Submitted,
+    Plays,
+    Favourites,
+    Rating,
+    /// Meilisearch's ranking of the text query.
+    Relevance,
+}
+
+impl SortField {
+    pub const ALL: [SortField; 11] = [
+        SortField::Id,
+        SortField::Title,
+        SortField::Artist,
+        SortField::Difficulty,
+        SortField::Ranked,
+        SortField::Updated,
+        SortField::Submitted,
+        SortField::Plays,
+        SortField::Favourites,
+        SortField::Rating,
+        SortField::Relevance,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SortField::Id => "id",
+            SortField::Title => "title",
+            SortField::Artist => "artist",
+            SortField::Difficulty => "difficulty",
+            SortField::Ranked => "ranked",
+            SortField::Updated => "updated",
+            SortField::Submitted => "submitted",
+            SortField::Plays => "plays",
+            SortField::Favourites => "favourites",
+            SortField::Rating => "rating",
+            SortField::Relevance => "relevance",
+        }
+    }
+
+    /// Sortable attribute of the beatmapset index.
+    pub fn attribute(self) -> Option<&'static str> {
+        match self {
+            SortField::Id => Some("id"),
+            SortField::Title => Some("title"),
+            SortField::Artist => Some("artist"),
+            SortField::Difficulty => Some("beatmaps.difficulty_rating"),
+            SortField::Ranked => Some("ranked_at"),
+            SortField::Updated => Some("last_updated_at"),
+            SortField::Submitted => Some("submitted_at"),
+            SortField::Plays => Some("play_count"),
+            SortField::Favourites => Some("favourite_count"),
+            SortField::Rating => Some("rating"),
+            SortField::Relevance => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BeatmapsetSort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl BeatmapsetSort {
+    pub fn new(field: SortField, descending: bool) -> BeatmapsetSort {
+        BeatmapsetSort { field, descending }
+    }
+
+    /// `<field>_asc` or `<field>_desc`, relevance only sorts descending.
+    pub fn parse(value: &str) -> Option<BeatmapsetSort> {
+        let (name, descending) = match value.rsplit_once('_') {
+            Some((name, "asc")) => (name, false),
+            Some((name, "desc")) => (name, true),
+            _ => return None,
+        };
+
+        let field = SortField::ALL.into_iter().find(|field| field.name() == name)?;
+        if field == SortField::Relevance && !descending {
+            return None;
+        }
+
+        Some(BeatmapsetSort::new(field, descending))
+    }
+
+    /// Meilisearch sort rules, ties are broken by id so pages don't overlap.
+    fn rules(&self) -> Vec<String> {
+        let direction = if self.descending { "desc" } else { "asc" };
+
+        match self.field.attribute() {
+            Some("id") => vec![format!("id:{}", direction)],
+            Some(attribute) => vec![format!("{}:{}", attribute, direction), format!("id:{}", direction)],
+            None => Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for BeatmapsetSort {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}_{}", self.field.name(), if self.descending { "desc" } else { "asc" })
+    }
+}
+
+/// Meilisearch's default `maxTotalHits`, hits past it can't be paged to.
+const MAX_TOTAL_HITS: usize = 1000;
+const SCAN_BATCH_SIZE: usize = 200;
+
+/// Attributes counted when a search asks for facets, with the name they are returned under.
+const FACET_ATTRIBUTES: [&str; 5] = ["status", "beatmaps.mode", "genre.name", "language.name", "video"];
+
+fn facet_name(attribute: &str) -> &str {
+    match attribute {
+        "beatmaps.mode" => "mode",
+        "genre.name" => "genre",
+        "language.name" => "language",
+        attribute => attribute,
+    }
+}
+
+/// Matching sets per value of every facet.
+pub type Facets = BTreeMap<String, BTreeMap<String, usize>>;
+
+fn count_facets(facets: &mut Facets, beatmapset: &Beatmapset) {
+    let mut count = |name: &str, value: &str| {
+        *facets.entry(name.to_string()).or_default().entry(value.to_string()).or_default() += 1;
+    };
+
+    count("status", &beatmapset.status);
+    let mut modes: Vec<&str> = beatmapset.beatmaps.iter().map(|beatmap| beatmap.mode.as_str()).collect();
+    modes.sort();
+    modes.dedup();
+    for mode in modes {
+        count("mode", mode);
+    }
+    if let Some(genre) = &beatmapset.genre {
+        count("genre", &genre.name);
+    }
+    if let Some(language) = &beatmapset.language {
+        count("language", &language.name);
+    }
+    count("video", &beatmapset.video.to_string());
+}
+
+/// Full-text search over beatmapsets that aren't deleted, `statuses` and `modes` are or-ed.
+/// With `ranges`, only sets with a difficulty of one of `modes` inside all of them are returned.
+#[derive(Debug, Clone)]
+pub struct BeatmapsetSearch {
+    pub query: String,
+    pub statuses: Vec<String>,
+    pub modes: Vec<String>,
+    pub ranges: Vec<DifficultyRange>,
+    /// Further conditions on the beatmapset itself.
+    pub conditions: Vec<Filter>,
+    pub sort: BeatmapsetSort,
+    pub offset: usize,
+    pub limit: usize,
+    pub facets: bool,
+    /// Continues after a previous page, the cursor has to be of `sort`.
+    pub cursor: Option<PageCursor>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub beatmapsets: Vec<Beatmapset>,
+    /// Meilisearch's estimate, or the exact count within `maxTotalHits` with `ranges`.
+    pub total: usize,
+    pub facets: Option<Facets>,
+    /// Set when the page is full and `sort` supports cursors.
+    pub next: Option<PageCursor>,
+}
+
+impl BeatmapsetSearch {
+    fn filter(&self) -> Filter {
+        let mut filters = vec![
+            Filter::is_in("status", &self.statuses),
+            Filter::is_in("beatmaps.mode", &self.modes),
+            Filter::is_null("deleted_at"),
+        ];
+        filters.extend(self.ranges.iter().flat_map(DifficultyRange::filters));
+        filters.extend(self.conditions.iter().cloned());
+        filters.extend(self.cursor.as_ref().map(PageCursor::filter));
+
+        Filter::and(filters)
+    }
+
+    fn matches(&self, beatmapset: &Beatmapset) -> bool {
+        beatmapset.beatmaps.iter().any(|beatmap| {
+            beatmap.deleted_at.is_none()
+                && self.modes.contains(&beatmap.mode)
+                && self.ranges.iter().all(|range| range.matches(beatmap))
+        })
+    }
+}
+
+async fn execute_search(
+    ctx: &Context,
+    search: &BeatmapsetSearch,
+    filter: &str,
+    offset: usize,
+    limit: usize,
+    facets: bool,
+) -> Result<SearchResults, DatabaseError> {
+    let index = ctx.index("beatmapset");
+    let rules = search.sort.rules();
+    let rules: Vec<&str> = rules.iter().map(String::as_str).collect();
+
+    let mut query = index.search();
+    query.with_query(&search.query).with_filter(filter).with_offset(offset).with_limit(limit);
+    if !rules.is_empty() {
+        query.with_sort(&rules);
+    }
+    if facets {
+        query.with_facets(Selectors::Some(&FACET_ATTRIBUTES));
+    }
+
+    let response = match query.execute::<Beatmapset>().await {
+        Ok(response) => response,
+        Err(err) => {
+            error!("{}", err);
+            return Err(DatabaseError::InternalDatabaseError);
+        }
+    };
+
+    let facets = response.facet_distribution.map(|distribution| {
+        distribution
+            .into_iter()
+            .map(|(attribute, values)| (facet_name(&attribute).to_string(), values.into_iter().collect()))
+            .collect()
+    });
+
+    Ok(SearchResults {
+        total: response.estimated_total_hits.unwrap_or(response.hits.len()),
+        beatmapsets: response.hits.into_iter().map(|hit| hit.result).collect(),
+        facets,
+        next: None,
+    })
+}
+
+pub async fn search_beatmapsets(ctx: Context, search: &BeatmapsetSearch) -> Result<SearchResults, DatabaseError> {
+    let filter = search.filter().to_string();
+
+    let mut results = match search.ranges.is_empty() {
+        true => execute_search(&ctx, search, &filter, search.offset, search.limit, search.facets).await?,
+        false => scan_search(&ctx, search, &filter).await?,
+    };
+
+    if search.limit > 0 && results.beatmapsets.len() == search.limit {
+        results.next = results.beatmapsets.last().and_then(|last| PageCursor::after(search.sort, last));
+    }
+
+    Ok(results)
+}
+
+async fn scan_search(ctx: &Context, search: &BeatmapsetSearch, filter: &str) -> Result<SearchResults, DatabaseError> {
+
+    // Meilisearch matches every condition against any of the difficulties, so sets where they are
+    // only met by different ones are dropped here. Pages, the total and facets are counted after that,
+    // which takes scanning every hit Meilisearch can return.
+    let mut results = SearchResults {
+        facets: search.facets.then(Facets::new),
+        ..Default::default()
+    };
+    let mut scanned = 0;
+
+    while scanned < MAX_TOTAL_HITS {
+        let batch = execute_search(ctx, search, filter, scanned, SCAN_BATCH_SIZE, false).await?.beatmapsets;
+        let exhausted = batch.len() < SCAN_BATCH_SIZE;
+        scanned += SCAN_BATCH_SIZE;
+
+        for beatmapset in batch.into_iter().filter(|beatmapset| search.matches(beatmapset)) {
+            if let Some(facets) = &mut results.facets {
+                count_facets(facets, &beatmapset);
+            }
+            if results.total >= search.offset && results.beatmapsets.len() < search.limit {
+                results.beatmapsets.push(beatmapset);
+            }
+            results.total += 1;
+        }
+
+        if exhausted {
+            break;
+        }
+    }
+
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        osu::types::{Genre, Language},
+        testing::{context, fixture_beatmapset, generated_beatmapsets, index_beatmapsets, FakeOsu},
+    };
+
+    use super::*;
+
+    fn search(ranges: Vec<DifficultyRange>) -> BeatmapsetSearch {
+        BeatmapsetSearch {
+            query: String::new(),
+            statuses: vec!["ranked".to_string()],
+            modes: vec!["osu".to_string()],
+            ranges,
+            conditions: Vec::new(),
+            sort: BeatmapsetSort::new(SortField::Updated, true),
+            offset: 0,
+            limit: 50,
+            facets: false,
+            cursor: None,
+        }
+    }
+
+    fn range(attribute: DifficultyAttribute, min: Option<f64>, max: Option<f64>) -> DifficultyRange {
+        DifficultyRange { attribute, min, max, exclusive: false }
+    }
+
+    #[test]
+    fn requires_one_difficulty_to_match_every_range() {
+        // Normal is 2.1 stars with AR6, Hard 4.3 stars with AR9.
+        let beatmapset = fixture_beatmapset();
+
+        let hard = search(vec![
+            range(DifficultyAttribute::Stars, Some(4.0), None),
+            range(DifficultyAttribute::ApproachRate, Some(9.0), Some(9.0)),
+        ]);
+        assert!(hard.matches(&beatmapset));
+        assert_eq!(
+            hard.filter().to_string(),
+            "(status IN [\"ranked\"]) AND (beatmaps.mode IN [\"osu\"]) AND (deleted_at IS NULL) \
+            AND (beatmaps.difficulty_rating >= 4) AND (beatmaps.ar >= 9) AND (beatmaps.ar <= 9)"
+        );
+
+        // Each range is met by a difficulty, but not by the same one.
+        let mixed = search(vec![
+            range(DifficultyAttribute::Stars, Some(4.0), None),
+            range(DifficultyAttribute::ApproachRate, None, Some(6.0)),
+        ]);
+        assert!(!mixed.matches(&beatmapset));
+
+        let mut taiko = search(vec![range(DifficultyAttribute::Stars, None, Some(3.0))]);
+        assert!(taiko.matches(&beatmapset));
+        taiko.modes = vec!["taiko".to_string()];
+        assert!(!taiko.matches(&beatmapset));
+
+        let above_hard = search(vec![DifficultyRange { exclusive: true, ..range(DifficultyAttribute::Stars, Some(4.3), None) }]);
+        assert!(!above_hard.matches(&beatmapset));
+        assert!(above_hard.filter().to_string().ends_with("(beatmaps.difficulty_rating > 4.3)"));
+
+        // The fixture has no max combo, so it can't be in any combo range.
+        assert!(!search(vec![range(DifficultyAttribute::MaxCombo, Some(0.0), None)]).matches(&beatmapset));
+    }
+
+    #[test]
+    fn counts_facets() {
+        let mut beatmapset = fixture_beatmapset();
+        let mut facets = Facets::new();
+        count_facets(&mut facets, &beatmapset);
+
+        beatmapset.genre = Some(Genre { id: 3, name: "Anime".to_string() });
+        beatmapset.language = Some(Language { id: 3, name: "Japanese".to_string() });
+        count_facets(&mut facets, &beatmapset);
+
+        beatmapset.status = "loved".to_string();
+        beatmapset.genre = Some(Genre { id: 2, name: "Video Game".to_string() });
+        count_facets(&mut facets, &beatmapset);
+
+        assert_eq!(facets["status"], BTreeMap::from([("loved".to_string(), 1), ("ranked".to_string(), 2)]));
+        // Sets are counted once per mode, not per difficulty.
+        assert_eq!(facets["mode"], BTreeMap::from([("osu".to_string(), 3)]));
+        // Sets without a genre or language aren't counted under either.
+        assert_eq!(facets["genre"], BTreeMap::from([("Anime".to_string(), 1), ("Video Game".to_string(), 1)]));
+        assert_eq!(facets["language"], BTreeMap::from([("Japanese".to_string(), 2)]));
+        assert_eq!(facets["video"], BTreeMap::from([("false".to_string(), 3)]));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Meilisearch"]
+    async fn pages_through_cursor_sorts() {
+        let fake = FakeOsu::start().await;
+        let ctx = context(&fake, "cursor_pages").await;
+
+        let mut beatmapsets = generated_beatmapsets(1, 5);
+        for (beatmapset, value) in beatmapsets.iter_mut().zip([30, 10, 30, 20, 10]) {
+            beatmapset.playcount = value;
+            beatmapset.favourite_count = value;
+            beatmapset.rating = value as f64 / 4.0;
+            let date = format!("2024-01-01T00:{:02}:00Z", value);
+            beatmapset.last_updated = date.clone();
+            beatmapset.ranked_date = Some(date.clone());
+            beatmapset.submitted_date = date;
+        }
+        index_beatmapsets(&ctx, &beatmapsets).await;
+
+        let fields = [
+            SortField::Plays,
+            SortField::Favourites,
+            SortField::Rating,
+            SortField::Updated,
+            SortField::Ranked,
+            SortField::Submitted,
+        ];
+        for field in fields {
+            let mut page = BeatmapsetSearch { sort: BeatmapsetSort::new(field, true), limit: 2, ..search(Vec::new()) };
+            let mut ids = Vec::new();
+
+            loop {
+                let results = search_beatmapsets(ctx.clone(), &page).await.unwrap();
+                ids.extend(results.beatmapsets.iter().map(|beatmapset| beatmapset.mapset_id));
+                match results.next {
+                    Some(next) => page.cursor = Some(next),
+                    None => break,
+                }
+            }
+
+            // Ties keep the id order of the sort.
+            assert_eq!(ids, [3, 1, 4, 5, 2], "{}", field.name());
+        }
+    }
+
+    #[test]
+    fn indexes_dates_as_timestamps() {
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.ranked_date = None;
+        beatmapset.submitted_date = "not a date".to_string();
+        let document = serde_json::to_value(BeatmapsetDocument::new(&beatmapset)).unwrap();
+
+        assert_eq!(document["id"], 1);
+        assert_eq!(document["last_updated"], "2024-01-01T00:00:00Z");
+        assert_eq!(document["last_updated_at"], 1704067200);
+        assert_eq!(document["ranked_at"], 0);
+        assert_eq!(document["submitted_at"], 0);
+    }
+
+    #[test]
+    fn parses_sorts() {
+        let plays = BeatmapsetSort::parse("plays_desc").unwrap();
+        assert_eq!(plays, BeatmapsetSort::new(SortField::Plays, true));
+        assert_eq!(plays.rules(), ["play_count:desc", "id:desc"]);
+        assert_eq!(BeatmapsetSort::parse("difficulty_asc").unwrap().rules(), ["beatmaps.difficulty_rating:asc", "id:asc"]);
+        assert!(BeatmapsetSort::parse("relevance_desc").unwrap().rules().is_empty());
+        assert_eq!(BeatmapsetSort::parse("id_asc").unwrap().rules(), ["id:asc"]);
+        assert_eq!(plays.to_string(), "plays_desc");
+
+        assert_eq!(BeatmapsetSort::parse("relevance_asc"), None);
+        assert_eq!(BeatmapsetSort::parse("plays"), None);
+        assert_eq!(BeatmapsetSort::parse("length_desc"), None);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::osu::types::Beatmapset;
+
+use super::{
+    beatmapset::{timestamp, BeatmapsetSort, SortField},
+    filter::{Filter, FilterValue, Operator},
+};
+
+/// Position after a beatmapset in a sorted listing, handed out as an opaque string.
+/// Meilisearch only compares numbers in filters, so there are cursors for the id, date, plays,
+/// favourites and rating orders, not for text, difficulty or relevance. Ties are broken by id,
+/// like the sort itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageCursor {
+    pub sort: BeatmapsetSort,
+    pub value: FilterValue,
+    pub id: i64,
+}
+
+fn sort_value(field: SortField, beatmapset: &Beatmapset) -> Option<FilterValue> {
+    match field {
+        SortField::Id => Some(beatmapset.mapset_id.into()),
+        SortField::Updated => Some(timestamp(Some(&beatmapset.last_updated)).into()),
+        SortField::Ranked => Some(timestamp(beatmapset.ranked_date.as_deref()).into()),
+        SortField::Submitted => Some(timestamp(Some(&beatmapset.submitted_date)).into()),
+        SortField::Plays => Some(beatmapset.playcount.into()),
+        SortField::Favourites => Some(beatmapset.favourite_count.into()),
+        SortField::Rating => Some(beatmapset.rating.into()),
+        _ => None,
+    }
+}
+
+impl PageCursor {
+    pub fn supports(sort: BeatmapsetSort) -> bool {
+        matches!(
+            sort.field,
+            SortField::Id
+                | SortField::Updated
+                | SortField::Ranked
+                | SortField::Submitted
+                | SortField::Plays
+                | SortField::Favourites
+                | SortField::Rating
+        )
+    }
+
+    /// Cursor of the page following `beatmapset`, if `sort` supports cursors.
+    pub fn after(sort: BeatmapsetSort, beatmapset: &Beatmapset) -> Option<PageCursor> {
+        Some(PageCursor {
+            sort,
+            value: sort_value(sort.field, beatmapset)?,
+            id: beatmapset.mapset_id,
+        })
+    }
+
+    pub fn encode(&self) -> String {
+        hex::encode(format!("{}:{}:{}", self.sort, self.value, self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<PageCursor> {
+        let decoded = String::from_utf8(hex::decode(cursor).ok()?).ok()?;
+        let mut parts = decoded.splitn(3, ':');
+
+        let sort = BeatmapsetSort::parse(parts.next()?).filter(|sort| PageCursor::supports(*sort))?;
+        let value = parts.next()?;
+        let value = match value.parse::<i64>() {
+            Ok(value) => FilterValue::Integer(value),
+            Err(_) => FilterValue::Float(value.parse::<f64>().ok().filter(|value| value.is_finite())?),
+        };
+        let id = parts.next()?.parse().ok()?;
+
+        Some(PageCursor { sort, value, id })
+    }
+
+    /// Beatmapsets past the cursor in its order.
+    pub fn filter(&self) -> Filter {
+        let past = match self.sort.descending {
+            true => Operator::Less,
+            false => Operator::Greater,
+        };
+
+        match self.sort.field.attribute() {
+            Some(attribute) if attribute != "id" => Filter::or(vec![
+                Filter::compare(attribute, past, self.value.clone()),
+                Filter::and(vec![Filter::eq(attribute, self.value.clone()), Filter::compare("id", past, self.id)]),
+            ]),
+            _ => Filter::compare("id", past, self.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ops::indexes::BEATMAPSET_FILTERS, testing::fixture_beatmapset};
+
+    use super::*;
+
+    #[test]
+    fn round_trips_and_filters() {
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.playcount = 1500;
+
+        let plays = PageCursor::after(BeatmapsetSort::new(SortField::Plays, true), &beatmapset).unwrap();
+        assert_eq!(PageCursor::decode(&plays.encode()), Some(plays.clone()));
+        assert_eq!(plays.filter().to_string(), "(play_count < 1500) OR ((play_count = 1500) AND (id < 1))");
+
+        let ids = PageCursor::after(BeatmapsetSort::new(SortField::Id, false), &beatmapset).unwrap();
+        assert_eq!(ids.filter().to_string(), "id > 1");
+
+        let updated = PageCursor::after(BeatmapsetSort::new(SortField::Updated, true), &beatmapset).unwrap();
+        assert_eq!(updated.value, FilterValue::Integer(1704067200));
+        assert_eq!(
+            updated.filter().to_string(),
+            "(last_updated_at < 1704067200) OR ((last_updated_at = 1704067200) AND (id < 1))"
+        );
+
+        beatmapset.ranked_date = None;
+        let ranked = PageCursor::after(BeatmapsetSort::new(SortField::Ranked, false), &beatmapset).unwrap();
+        assert_eq!(PageCursor::decode(&ranked.encode()).unwrap().value, FilterValue::Integer(0));
+
+        beatmapset.rating = 9.25;
+        let rating = PageCursor::after(BeatmapsetSort::new(SortField::Rating, true), &beatmapset).unwrap();
+        assert_eq!(PageCursor::decode(&rating.encode()).unwrap().value, FilterValue::Float(9.25));
+    }
+
+    #[test]
+    fn filters_only_on_filterable_attributes() {
+        for field in SortField::ALL {
+            let sort = BeatmapsetSort::new(field, true);
+            if !PageCursor::supports(sort) {
+                continue;
+            }
+
+            let attribute = field.attribute().unwrap();
+            assert!(BEATMAPSET_FILTERS.contains(&attribute), "{} is not filterable", attribute);
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_and_tampered_cursors() {
+        for field in [SortField::Title, SortField::Artist, SortField::Difficulty, SortField::Relevance] {
+            assert_eq!(PageCursor::after(BeatmapsetSort::new(field, true), &fixture_beatmapset()), None);
+        }
+
+        let forged = |content: &str| PageCursor::decode(&hex::encode(content));
+        assert!(forged("plays_desc:10:1").is_some());
+        assert_eq!(forged("title_asc:10:1"), None);
+        assert_eq!(forged("plays_desc:10 OR id > 0:1"), None);
+        assert_eq!(forged("plays_desc:NaN:1"), None);
+        assert_eq!(forged("plays_desc:10"), None);
+        assert_eq!(PageCursor::decode("not hex"), None);
+    }
+}
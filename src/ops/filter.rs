@@ -0,0 +1,224 @@
+use std::fmt;
+
+/// Right hand side of a filter condition. Text is always quoted, numbers are written as is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterValue {
+    Integer(i64),
+    /// Must be finite, Meilisearch has no representation for NaN or infinity.
+    Float(f64),
+    Text(String),
+}
+
+impl From<i64> for FilterValue {
+    fn from(value: i64) -> Self {
+        FilterValue::Integer(value)
+    }
+}
+
+impl From<f64> for FilterValue {
+    fn from(value: f64) -> Self {
+        FilterValue::Float(value)
+    }
+}
+
+impl From<&str> for FilterValue {
+    fn from(value: &str) -> Self {
+        FilterValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for FilterValue {
+    fn from(value: String) -> Self {
+        FilterValue::Text(value)
+    }
+}
+
+impl From<&String> for FilterValue {
+    fn from(value: &String) -> Self {
+        FilterValue::Text(value.clone())
+    }
+}
+
+impl fmt::Display for FilterValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilterValue::Integer(value) => write!(f, "{}", value),
+            FilterValue::Float(value) => write!(f, "{}", value),
+            // Meilisearch skips the character after every backslash while looking for the closing quote,
+            // so both have to be escaped. Only `\"` is unescaped again, values with backslashes won't match.
+            FilterValue::Text(value) => write!(f, "\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operator = match self {
+            Operator::Equal => "=",
+            Operator::NotEqual => "!=",
+            Operator::Greater => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::Less => "<",
+            Operator::LessOrEqual => "<=",
+        };
+        write!(f, "{}", operator)
+    }
+}
+
+/// A Meilisearch filter expression. Attribute names are fixed at compile time and values are
+/// escaped when rendered, so nothing taken from a request can change the shape of the filter.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Compare(&'static str, Operator, FilterValue),
+    In(&'static str, Vec<FilterValue>),
+    IsNull(&'static str),
+    /// Renders to an empty filter, matching everything, when there are no conditions.
+    And(Vec<Filter>),
+    /// Needs at least one condition.
+    Or(Vec<Filter>),
+}
+
+impl Filter {
+    pub fn compare(attribute: &'static str, operator: Operator, value: impl Into<FilterValue>) -> Filter {
+        Filter::Compare(attribute, operator, value.into())
+    }
+
+    pub fn eq(attribute: &'static str, value: impl Into<FilterValue>) -> Filter {
+        Filter::compare(attribute, Operator::Equal, value)
+    }
+
+    pub fn gt(attribute: &'static str, value: impl Into<FilterValue>) -> Filter {
+        Filter::compare(attribute, Operator::Greater, value)
+    }
+
+    pub fn is_in<V: Into<FilterValue>>(attribute: &'static str, values: impl IntoIterator<Item = V>) -> Filter {
+        Filter::In(attribute, values.into_iter().map(Into::into).collect())
+    }
+
+    pub fn is_null(attribute: &'static str) -> Filter {
+        Filter::IsNull(attribute)
+    }
+
+    pub fn and(filters: Vec<Filter>) -> Filter {
+        Filter::And(filters)
+    }
+
+    pub fn or(filters: Vec<Filter>) -> Filter {
+        Filter::Or(filters)
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::Compare(attribute, operator, value) => write!(f, "{} {} {}", attribute, operator, value),
+            Filter::In(attribute, values) => {
+                let values = values.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+                write!(f, "{} IN [{}]", attribute, values.join(", "))
+            }
+            Filter::IsNull(attribute) => write!(f, "{} IS NULL", attribute),
+            Filter::And(filters) | Filter::Or(filters) if filters.len() == 1 => write!(f, "{}", filters[0]),
+            Filter::And(filters) => {
+                let filters = filters.iter().map(|filter| format!("({})", filter)).collect::<Vec<String>>();
+                write!(f, "{}", filters.join(" AND "))
+            }
+            Filter::Or(filters) => {
+                let filters = filters.iter().map(|filter| format!("({})", filter)).collect::<Vec<String>>();
+                write!(f, "{}", filters.join(" OR "))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Splits a rendered filter the way Meilisearch's parser reads quoted values:
+    /// the character after a backslash is skipped and only `\"` is unescaped.
+    fn tokens(filter: &str) -> (Vec<String>, String) {
+        let mut values = Vec::new();
+        let mut outside = String::new();
+        let mut chars = filter.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '"' {
+                outside.push(c);
+                continue;
+            }
+
+            let mut value = String::new();
+            loop {
+                match chars.next().expect("unterminated quoted value") {
+                    '"' => break,
+                    '\\' => {
+                        value.push('\\');
+                        value.push(chars.next().expect("dangling escape"));
+                    }
+                    c => value.push(c),
+                }
+            }
+            values.push(value.replace("\\\"", "\""));
+            outside.push_str("<value>");
+        }
+
+        (values, outside)
+    }
+
+    #[test]
+    fn renders_filters() {
+        let filter = Filter::and(vec![
+            Filter::is_in("status", ["ranked", "loved"]),
+            Filter::eq("beatmaps.id", 75),
+            Filter::gt("beatmaps.difficulty_rating", 5.5),
+            Filter::is_null("deleted_at"),
+        ]);
+
+        assert_eq!(
+            filter.to_string(),
+            "(status IN [\"ranked\", \"loved\"]) AND (beatmaps.id = 75) AND (beatmaps.difficulty_rating > 5.5) AND (deleted_at IS NULL)"
+        );
+        assert_eq!(Filter::and(vec![Filter::eq("id", 1)]).to_string(), "id = 1");
+        assert_eq!(Filter::and(vec![]).to_string(), "");
+
+        let nested = Filter::or(vec![
+            Filter::gt("play_count", 10),
+            Filter::and(vec![Filter::eq("play_count", 10), Filter::gt("id", 5)]),
+        ]);
+        assert_eq!(nested.to_string(), "(play_count > 10) OR ((play_count = 10) AND (id > 5))");
+    }
+
+    #[test]
+    fn keeps_hostile_values_inside_quotes() {
+        let hostile = [
+            "abc OR id > 0",
+            "\" OR id > 0 OR status = \"",
+            "x\\",
+            "x\\\" OR id > 0",
+            "] OR (deleted_at IS NOT NULL",
+            "\\\\\"\"",
+        ];
+
+        for value in hostile {
+            let filter = Filter::and(vec![Filter::eq("beatmaps.checksum", value), Filter::is_in("status", [value, value])]);
+            let (values, outside) = tokens(&filter.to_string());
+
+            assert_eq!(values.len(), 3, "{}", filter);
+            assert_eq!(outside, "(beatmaps.checksum = <value>) AND (status IN [<value>, <value>])");
+            // Values without backslashes come back unchanged.
+            if !value.contains('\\') {
+                assert!(values.iter().all(|parsed| parsed == value));
+            }
+        }
+    }
+}
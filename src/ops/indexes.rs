@@ -0,0 +1,120 @@
+use meilisearch_sdk::client::Client;
+use tracing::{error, info};
+
+/// Includes the attributes of every `PageCursor` order, its filters compare them.
+pub const BEATMAPSET_FILTERS: [&str; 28] = [
+    "beatmaps.id", "id", "title", "title_unicode", "beatmaps.checksum", "beatmaps.mode", "status", "deleted_at", "creator", "artist",
+    "genre.name", "language.name", "video", "play_count", "favourite_count", "rating",
+    "last_updated_at", "ranked_at", "submitted_at",
+    "beatmaps.difficulty_rating", "beatmaps.ar", "beatmaps.accuracy", "beatmaps.cs", "beatmaps.drain", "beatmaps.bpm",
+    "beatmaps.total_length", "beatmaps.hit_length", "beatmaps.max_combo",
+];
+
+/// `last_updated` is the watermark of the recent crawl, the date sorts use the timestamps.
+pub const BEATMAPSET_SORTS: [&str; 12] = [
+    "id", "title", "title_unicode", "artist", "last_updated", "last_updated_at", "ranked_at", "submitted_at", "play_count",
+    "favourite_count", "rating", "beatmaps.difficulty_rating",
+];
+
+pub const DOWNLOAD_FILTERS: [&str; 1] = ["id"];
+
+async fn ensure_filters(client: &Client, index: impl ToString, filters: &[&str]) {
+    if let Ok(filter) = client.get_index(index.to_string()).await {
+        let mut filters_to_add = Vec::new();
+        let filter_names = filter.get_filterable_attributes().await.unwrap();
+        for &filter_name in filters {
+            if !filter_names.contains(&filter_name.to_string()) {
+                filters_to_add.push(filter_name);
+            }
+        }
+
+        info!("Filterable atrributes of {}: {:#?}", index.to_string(), filter_names);
+
+        if !filters_to_add.is_empty() {
+            info!("Updating filters");
+            let update_filter_task = filter.set_filterable_attributes(filters).await;
+            match update_filter_task {
+                Err(err) => {
+                    error!("Failed to run update task, {}", err)
+                },
+                Ok(task) => {
+                    info!("Task has been enqueued, id: {}. awaiting", task.task_uid);
+                    match task.wait_for_completion(client, None, None).await {
+                        Err(err) => {
+                            error!("Failed to run update task, {}", err)
+                        },
+                        Ok(_) => {
+                            info!("Task has been completed");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+async fn ensure_sort(client: &Client, index: impl ToString, sort: &[&str]) {
+    if let Ok(filter) = client.get_index(index.to_string()).await {
+        let mut filters_to_add = Vec::new();
+        let filter_names = filter.get_sortable_attributes().await.unwrap();
+        for &filter_name in sort {
+            if !filter_names.contains(&filter_name.to_string()) {
+                filters_to_add.push(filter_name);
+            }
+        }
+
+        info!("Sortable atrributes of {}: {:#?}", index.to_string(), filter_names);
+
+        if !filters_to_add.is_empty() {
+            info!("Updating sortable attributes");
+            let update_filter_task = filter.set_sortable_attributes(sort).await;
+            match update_filter_task {
+                Err(err) => {
+                    error!("Failed to run update task, {}", err)
+                },
+                Ok(task) => {
+                    info!("Task has been enqueued, id: {}. awaiting", task.task_uid);
+                    match task.wait_for_completion(client, None, None).await {
+                        Err(err) => {
+                            error!("Failed to run update task, {}", err)
+                        },
+                        Ok(_) => {
+                            info!("Task has been completed");
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Fresh installs have no indexes until the first documents arrive, which would leave them without settings.
+async fn ensure_index(client: &Client, uid: &str) {
+    if client.get_index(uid).await.is_ok() {
+        return;
+    }
+
+    info!("Creating index {}", uid);
+    match client.create_index(uid, Some("id")).await {
+        Ok(task) => {
+            if let Err(err) = task.wait_for_completion(client, None, None).await {
+                error!("Failed to create index {}: {}", uid, err);
+            }
+        }
+        Err(err) => error!("Failed to create index {}: {}", uid, err),
+    }
+}
+
+/// Creates the indexes behind `prefix` and registers their filterable and sortable attributes.
+pub async fn ensure_indexes(client: &Client, prefix: &str) {
+    let beatmapset = format!("{}beatmapset", prefix);
+    let downloads = format!("{}downloads", prefix);
+
+    ensure_index(client, &beatmapset).await;
+    ensure_index(client, &downloads).await;
+
+    ensure_filters(client, &beatmapset, &BEATMAPSET_FILTERS).await;
+    ensure_filters(client, &downloads, &DOWNLOAD_FILTERS).await;
+    ensure_sort(client, &beatmapset, &BEATMAPSET_SORTS).await;
+}
@@ -4,9 +4,17 @@ use serde_derive::{Deserialize, Serialize};
 
 pub mod beatmaps;
 pub mod beatmapset;
+pub mod cursor;
+pub mod filter;
+pub mod indexes;
 
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct DownloadIndex {
     pub id: i64,
-    pub date: i64
+    pub date: i64,
+    /// Why the last download from osu! was rejected, cleared once one succeeds.
+    #[serde(default)]
+    pub failure: Option<String>,
+    #[serde(default)]
+    pub failed_at: Option<i64>,
 }
\ No newline at end of file
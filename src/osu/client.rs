@@ -1,25 +1,70 @@
 use std::{
-    borrow::BorrowMut,
-    fs::write,
-    io::{Error, ErrorKind},
+    fmt,
     path::Path,
+    sync::Arc,
+    time::Duration,
 };
 
 
 use chrono::{Local};
+use axum_prometheus::metrics::counter;
 use confy::ConfyError;
-use reqwest::StatusCode;
+use futures_util::StreamExt;
+use rand::Rng;
+use reqwest::{header::RETRY_AFTER, RequestBuilder, Response, StatusCode};
 use serde_derive::Deserialize;
-use tokio::fs::File;
-use tracing::{error, info};
+use tokio::{fs, io::AsyncWriteExt, time};
+use tracing::{error, info, warn};
 
 
-use crate::config::Configuration;
+use crate::{
+    archive::{verify_archive, ArchiveError},
+    config::{load_stored, update_stored, Configuration, Http},
+    storage::{archive_key, derived_keys, staging_path, BeatmapStorage, Storage, StorageError},
+};
+
+use super::{ratelimit::TokenBucket, types::{Beatmap, Beatmaps, Beatmapset, SearchResponse}};
+
+/// How many times an archive failing verification is downloaded again before giving up.
+const DOWNLOAD_ATTEMPTS: u32 = 3;
 
-use super::types::SearchResponse;
+#[derive(Debug)]
+pub enum OsuError {
+    Request(reqwest::Error),
+    Status(StatusCode),
+    NotFound,
+    InvalidResponse(String),
+    Config(ConfyError),
+    Storage(StorageError),
+    InvalidArchive(String),
+}
+
+impl std::error::Error for OsuError {}
+
+impl fmt::Display for OsuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OsuError::Request(err) => write!(f, "Request to osu! failed: {}", err),
+            OsuError::Status(status) => write!(f, "osu! responded with status {}", status.as_u16()),
+            OsuError::NotFound => write!(f, "Not found on osu!."),
+            OsuError::InvalidResponse(reason) => write!(f, "Invalid response from osu!: {}", reason),
+            OsuError::Config(err) => write!(f, "Failed to access configuration: {}", err),
+            OsuError::Storage(err) => write!(f, "Failed to store beatmap: {}", err),
+            OsuError::InvalidArchive(reason) => write!(f, "osu! sent an invalid archive: {}", reason),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OsuClient {
+    http: reqwest::Client,
+    download_http: reqwest::Client,
+    http_config: Http,
+    base_url: String,
+    api_limiter: Arc<TokenBucket>,
+    download_limiter: Arc<TokenBucket>,
+    reconcile_limiter: Arc<TokenBucket>,
+    asset_limiter: Arc<TokenBucket>,
     access_token: String,
     refresh_token: String,
     token_expires_at: i64,
@@ -44,88 +89,272 @@ pub trait OsuApi {
         config: Configuration,
         access_token: String,
         refresh_token: String,
-    ) -> Result<OsuClient, Error>;
-    async fn refresh_token(&mut self, config: Configuration) -> Result<bool, Error>;
+    ) -> Result<OsuClient, OsuError>;
+    async fn refresh_token(&mut self, config: Configuration) -> Result<bool, OsuError>;
     async fn search_beatmapsets(
         &mut self,
         nsfw: bool,
         sort: String,
         status: String,
         cursor_string: Option<String>
-    ) -> Option<SearchResponse>;
+    ) -> Result<SearchResponse, OsuError>;
 
     async fn download_if_not_exists(
         &mut self,
         id: i64,
-        path_to_beatmaps: String,
-        force: bool
-    ) -> Result<Vec<u8>, Error>;
-    async fn fetch_user(&self) -> Result<UserResponse, Error>;
+        storage: &Storage,
+        force: bool,
+        checksums: &[String]
+    ) -> Result<(), OsuError>;
+    async fn fetch_beatmapset(&mut self, id: i64) -> Result<Beatmapset, OsuError>;
+    async fn lookup_beatmaps(&mut self, ids: &[i64]) -> Result<Vec<Beatmap>, OsuError>;
+    async fn fetch_user(&self) -> Result<UserResponse, OsuError>;
+    async fn fetch_asset(&self, url: &str) -> Result<Vec<u8>, OsuError>;
 
     async fn refresh_token_if_required(&mut self) -> bool;
 
     fn load_config(self) -> Result<Configuration, ConfyError>;
 }
 
+/// Client for archive downloads, which are bound by `Http.read_timeout` instead of a total timeout.
+fn build_download_client(config: &Http) -> Result<reqwest::Client, OsuError> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()
+        .map_err(OsuError::Request)
+}
+
+pub fn build_http_client(config: &Http) -> Result<reqwest::Client, OsuError> {
+    reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(config.connect_timeout))
+        .timeout(Duration::from_secs(config.timeout))
+        .pool_idle_timeout(Duration::from_secs(90))
+        .build()
+        .map_err(OsuError::Request)
+}
+
+fn backoff_delay(config: &Http, attempt: u32) -> Duration {
+    let exponential = config.backoff_base_ms.saturating_mul(1 << attempt.min(16));
+    let capped = exponential.min(config.backoff_max_ms);
+    let jitter = rand::thread_rng().gen_range(0..=capped / 2);
+
+    Duration::from_millis(capped / 2 + jitter)
+}
+
+/// Falls back to a minute when osu! does not say how long to back off for.
+fn retry_after(response: &Response) -> Duration {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or(Duration::from_secs(60))
+}
+
+/// Sends the request built by `build` once `limiter` allows it, retrying connection errors,
+/// timeouts and 5xx responses with exponential backoff and 429s after `Retry-After`.
+/// Any other response is handed back to the caller.
+async fn send_with_retry(config: &Http, limiter: &TokenBucket, build: impl Fn() -> RequestBuilder) -> Result<Response, OsuError> {
+    let mut attempt = 0;
+
+    loop {
+        limiter.acquire().await;
+        let response = build().send().await;
+
+        let reason = match response {
+            Ok(response) if response.status() == StatusCode::TOO_MANY_REQUESTS && attempt < config.max_retries => {
+                limiter.pause_for(retry_after(&response)).await;
+                attempt += 1;
+                continue;
+            }
+            Ok(response) if response.status().is_server_error() && attempt < config.max_retries => {
+                format!("status {}", response.status().as_u16())
+            }
+            Ok(response) => return Ok(response),
+            Err(err) if (err.is_connect() || err.is_timeout() || err.is_request()) && attempt < config.max_retries => {
+                err.to_string()
+            }
+            Err(err) => return Err(OsuError::Request(err)),
+        };
+
+        let delay = backoff_delay(config, attempt);
+        attempt += 1;
+        warn!("Request to osu! failed ({}), retry {}/{} in {:?}", reason, attempt, config.max_retries, delay);
+        time::sleep(delay).await;
+    }
+}
+
 pub async fn log_in_using_credentials(
     config: Configuration
-) -> Result<TokenResponse, Error> {
-    let client = reqwest::Client::new();
+) -> Result<TokenResponse, OsuError> {
+    let client = build_http_client(&config.http)?;
     let params = [
         ("grant_type", "password"),
-        ("client_id", "5"),
-        ("client_secret", "FGc9GAtyHzeQDshWP5Ah7dega8hJACAJpQtw6OXk"),
+        ("client_id", &config.osu_client_id),
+        ("client_secret", &config.osu_client_secret),
         ("username", &config.osu_username),
-        ("password", &config.osu_password), 
+        ("password", &config.osu_password),
         ("scope", "*"),
     ];
 
-    let response = client
-        .post("https://osu.ppy.sh/oauth/token")
-        .header("Accept", "application/json")
-        .form(&params)
-        .send()
-        .await
-        .unwrap();
+    let limiter = TokenBucket::new("api", &config.rate_limits.api);
+    let response = send_with_retry(&config.http, &limiter, || {
+        client
+            .post(osu_url(&config.osu_base_url, "/oauth/token"))
+            .header("Accept", "application/json")
+            .form(&params)
+    })
+    .await?;
 
     if response.status() != StatusCode::OK {
+        let status = response.status();
+        error!("Error to create token, response: {}", response.text().await.unwrap_or_default());
+        return Err(OsuError::Status(status))
+    }
 
-        return Err(Error::new(ErrorKind::Other, format!("Error to create token, response: {}", response.text().await.unwrap())))
+    let resp = response
+        .json::<TokenResponse>()
+        .await
+        .map_err(|err| OsuError::InvalidResponse(err.to_string()))?;
+
+    update_stored(|stored| {
+        stored.osu_access_token = resp.access_token.clone();
+        stored.osu_refresh_token = resp.refresh_token.clone();
+        stored.osu_token_expires_at = Local::now().timestamp() + resp.expires_in;
+    }).map_err(OsuError::Config)?;
+
+    Ok(resp)
+}
+
+/// Streams the body of `response` into `path`, failing when the body ends before the
+/// announced `Content-Length` so a cut connection never passes for a complete archive.
+async fn write_to_file(response: Response, path: &Path, read_timeout: Duration) -> Result<u64, OsuError> {
+    let expected = response.content_length();
+    let mut file = fs::File::create(path).await.map_err(|err| OsuError::Storage(StorageError::Io(err)))?;
+    let mut body = response.bytes_stream();
+    let mut written = 0u64;
+
+    loop {
+        let chunk = match time::timeout(read_timeout, body.next()).await {
+            Ok(Some(chunk)) => chunk.map_err(OsuError::Request)?,
+            Ok(None) => break,
+            Err(_) => return Err(OsuError::InvalidResponse(format!("archive stalled after {} bytes", written))),
+        };
+        file.write_all(&chunk).await.map_err(|err| OsuError::Storage(StorageError::Io(err)))?;
+        written += chunk.len() as u64;
     }
 
-    let resp = response.json::<TokenResponse>().await.unwrap();
+    file.flush().await.map_err(|err| OsuError::Storage(StorageError::Io(err)))?;
+
+    match expected {
+        Some(expected) if expected != written => {
+            counter!("mirria_archive_rejected_total", "reason" => "truncated").increment(1);
+            Err(OsuError::InvalidResponse(format!("archive is {} bytes, expected {}", written, expected)))
+        }
+        _ => Ok(written),
+    }
+}
 
-    let mut new_config = config.clone().borrow_mut().to_owned();
-    new_config.osu_access_token = resp.access_token.clone();
-    new_config.osu_refresh_token = resp.refresh_token.clone();
-    new_config.osu_token_expires_at = Local::now().timestamp() + resp.expires_in;
+fn osu_url(base_url: &str, path: &str) -> String {
+    format!("{}{}", base_url.trim_end_matches('/'), path)
+}
 
-    confy::store("mirria", None, new_config).expect("Error while saving config.");
+impl OsuClient {
+    /// Downloads the archive of `id` into `part` and checks it against `checksums`.
+    async fn download_archive(&self, id: i64, part: &Path, checksums: &[String]) -> Result<(), OsuError> {
+        let response = self
+            .send(&self.download_limiter, || {
+                self.download_http
+                    .get(self.url(&format!("/api/v2/beatmapsets/{}/download", id)))
+                    .bearer_auth(&self.access_token)
+            })
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(OsuError::NotFound);
+        }
 
-    Ok(resp)
+        if !response.status().is_success() {
+            let status = response.status();
+            error!("Invalid status: {}", status.as_u16());
+            return Err(OsuError::Status(status));
+        }
+
+        write_to_file(response, part, Duration::from_secs(self.http_config.read_timeout)).await?;
+
+        let (part, checksums) = (part.to_path_buf(), checksums.to_vec());
+        tokio::task::spawn_blocking(move || -> Result<(), ArchiveError> {
+            verify_archive(std::fs::File::open(part)?, &checksums)
+        })
+        .await
+        .map_err(|err| OsuError::InvalidArchive(err.to_string()))?
+        .map_err(|err| {
+            counter!("mirria_archive_rejected_total", "reason" => err.reason()).increment(1);
+            OsuError::InvalidArchive(err.to_string())
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        osu_url(&self.base_url, path)
+    }
+
+    async fn send(&self, limiter: &TokenBucket, build: impl Fn() -> RequestBuilder) -> Result<Response, OsuError> {
+        send_with_retry(&self.http_config, limiter, build).await
+    }
+
+    /// Picks up tokens refreshed by another task or process before making a request.
+    async fn reload_tokens_if_required(&mut self) -> Result<(), OsuError> {
+        if self.refresh_token_if_required().await {
+            let config = self.clone().load_config().map_err(|error| {
+                error!("Error while reloading config: {}", error);
+                OsuError::Config(error)
+            })?;
+
+            self.access_token = config.osu_access_token;
+            self.refresh_token = config.osu_refresh_token;
+            self.token_expires_at = config.osu_token_expires_at;
+        }
+
+        Ok(())
+    }
 }
 
 impl OsuApi for OsuClient {
-    async fn fetch_user(&self) -> Result<UserResponse, Error> {
-        let client = reqwest::Client::new();
+    /// Public assets (covers, thumbnails) are fetched without the API token.
+    async fn fetch_asset(&self, url: &str) -> Result<Vec<u8>, OsuError> {
+        let response = self.send(&self.asset_limiter, || self.http.get(url)).await?;
 
-        let response = client
-            .get("https://osu.ppy.sh/api/v2/me")
-            .header("Accept", "application/json")
-            .bearer_auth(self.clone().access_token)
-            .send()
-            .await;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(OsuError::NotFound);
+        }
 
-        if let Err(_err) = response {
-            return Err(Error::new(ErrorKind::Other, "Error while fetching current user."))
+        if !response.status().is_success() {
+            return Err(OsuError::Status(response.status()));
         }
 
-        let response = response.unwrap();
+        response.bytes().await.map(|bytes| bytes.to_vec()).map_err(OsuError::Request)
+    }
+
+    async fn fetch_user(&self) -> Result<UserResponse, OsuError> {
+        let response = self
+            .send(&self.api_limiter, || {
+                self.http
+                    .get(self.url("/api/v2/me"))
+                    .header("Accept", "application/json")
+                    .bearer_auth(&self.access_token)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(OsuError::Status(response.status()))
+        }
 
         let user = response.json::<UserResponse>().await;
 
         if user.is_err() {
-            return Err(Error::new(ErrorKind::Other, "Looks like osu! servers down, or did html instead of json."))
+            return Err(OsuError::InvalidResponse("Looks like osu! servers down, or did html instead of json.".to_string()))
         }
 
         let user = user.unwrap();
@@ -136,62 +365,65 @@ impl OsuApi for OsuClient {
         config: Configuration,
         access_token: String,
         refresh_token: String,
-    ) -> Result<OsuClient, Error> {
-        
+    ) -> Result<OsuClient, OsuError> {
+
 
         //Validating tokens
         let client = OsuClient {
-            access_token: String::from(access_token),
-            refresh_token: String::from(refresh_token),
+            http: build_http_client(&config.http)?,
+            download_http: build_download_client(&config.http)?,
+            http_config: config.http.clone(),
+            base_url: config.osu_base_url.clone(),
+            api_limiter: Arc::new(TokenBucket::new("api", &config.rate_limits.api)),
+            download_limiter: Arc::new(TokenBucket::new("download", &config.rate_limits.download)),
+            reconcile_limiter: Arc::new(TokenBucket::new("reconcile", &config.rate_limits.reconcile)),
+            asset_limiter: Arc::new(TokenBucket::new("assets", &config.rate_limits.assets)),
+            access_token,
+            refresh_token,
             token_expires_at: config.osu_token_expires_at,
         };
 
 
-        let user = client.fetch_user().await;
-
-        if user.is_err() {
-            return Err(Error::new(ErrorKind::Other, "Failed to fetch user"));
-        }
-
-        let user = user.unwrap();
+        let user = client.fetch_user().await?;
 
         info!("Logged in as {}!", user.username);
 
         Ok(client)
     }
 
-    async fn refresh_token(&mut self, config: Configuration) -> Result<bool, Error> {
- let client = reqwest::Client::new();
-
+    async fn refresh_token(&mut self, config: Configuration) -> Result<bool, OsuError> {
         let form = [
             ("grant_type", "refresh_token"),
             ("refresh_token", &config.osu_refresh_token),
-            ("client_id", "5"),
-            ("client_secret", "FGc9GAtyHzeQDshWP5Ah7dega8hJACAJpQtw6OXk"),
+            ("client_id", &config.osu_client_id),
+            ("client_secret", &config.osu_client_secret),
             ("scope", "*"),
         ];
 
 
-        let response = client
-            .post("https://osu.ppy.sh/oauth/token")
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .header("Accept", "application/json")
-            .form(&form)
-            .send()
-            .await
-            .unwrap();
+        let response = self
+            .send(&self.api_limiter, || {
+                self.http
+                    .post(self.url("/oauth/token"))
+                    .header("Content-Type", "application/x-www-form-urlencoded")
+                    .header("Accept", "application/json")
+                    .form(&form)
+            })
+            .await?;
 
         if !response.status().is_success() {
-            return Err(Error::new(ErrorKind::Other, "Failed to refresh token"));
+            return Err(OsuError::Status(response.status()));
         }
-        let resp = response.json::<TokenResponse>().await.unwrap();
-
-        let mut new_config = config.clone().borrow_mut().to_owned();
-        new_config.osu_access_token = resp.access_token.clone();
-        new_config.osu_refresh_token = resp.refresh_token.clone();
-        new_config.osu_token_expires_at = Local::now().timestamp() + resp.expires_in;
+        let resp = response
+            .json::<TokenResponse>()
+            .await
+            .map_err(|err| OsuError::InvalidResponse(err.to_string()))?;
 
-        confy::store("mirria", None, new_config).expect("Error while saving config.");
+        update_stored(|stored| {
+            stored.osu_access_token = resp.access_token.clone();
+            stored.osu_refresh_token = resp.refresh_token.clone();
+            stored.osu_token_expires_at = Local::now().timestamp() + resp.expires_in;
+        }).map_err(OsuError::Config)?;
 
         self.access_token = resp.access_token;
         self.refresh_token = resp.refresh_token;
@@ -202,7 +434,7 @@ impl OsuApi for OsuClient {
         Ok(true)
     }
 
-    
+
 
     async fn search_beatmapsets(
         &mut self,
@@ -210,115 +442,167 @@ impl OsuApi for OsuClient {
         sort: String,
         status: String,
         cursor_string: Option<String>
-    ) -> Option<SearchResponse> {
-        if self.clone().refresh_token_if_required().await {
-            let config = self.clone().load_config();
-            if let Err(error) = config {
-                error!("Error while reloading config: {}", error);
-                return None
-            }
+    ) -> Result<SearchResponse, OsuError> {
+        self.reload_tokens_if_required().await?;
+
+        let query = [
+            ("nsfw", nsfw.to_string()),
+            ("sort", sort),
+            ("s", status),
+            ("cursor_string", cursor_string.unwrap_or_default()),
+        ];
 
-            let config = config.unwrap();
+        let response = self
+            .send(&self.api_limiter, || {
+                self.http
+                    .get(self.url("/api/v2/beatmapsets/search"))
+                    .query(&query)
+                    .bearer_auth(&self.access_token)
+            })
+            .await?;
 
-            self.access_token = config.osu_access_token;
-            self.refresh_token = config.osu_refresh_token;
-            self.token_expires_at = config.osu_token_expires_at;
+        if !response.status().is_success() {
+            return Err(OsuError::Status(response.status()));
         }
-        
-        let client = reqwest::Client::new();
-
-        let response = client
-            .get("https://osu.ppy.sh/api/v2/beatmapsets/search")
-            .query(&[
-                ("nsfw", nsfw.to_string()),
-                ("sort", sort),
-                ("s", status),
-                ("cursor_string", cursor_string.unwrap_or(String::new())),
-            ])
-            .bearer_auth(self.clone().access_token)
-            .send()
-            .await
-            .unwrap();
 
-        // let serialization_response = response.json::<SearchResponse>().await;
-        let text = response.text().await.unwrap();
+        let text = response.text().await.map_err(OsuError::Request)?;
         let jd: &mut serde_json::Deserializer<serde_json::de::StrRead<'_>> =
             &mut serde_json::Deserializer::from_str(text.as_str());
 
         let result: Result<SearchResponse, _> = serde_path_to_error::deserialize(jd);
-        match result {
-            Ok(v) => return Some(v),
-            Err(err) => {
-                let path = err.path().to_string();
-                error!("Failed to parse json, here path: {} ({})", path, err);
-                return None;
-            }
-        }
-        // Some(serialization_response.unwrap())
+        result.map_err(|err| {
+            let path = err.path().to_string();
+            error!("Failed to parse json, here path: {} ({})", path, err);
+            OsuError::InvalidResponse(format!("{} at {}", err, path))
+        })
     }
     async fn download_if_not_exists(
         &mut self,
         id: i64,
-        path_to_beatmaps: String,
-        force: bool
-    ) -> Result<Vec<u8>, Error> {
-        if self.refresh_token_if_required().await {
-            let config = self.clone().load_config();
-            if let Err(error) = config {
-                error!("Error while reloading config: {}", error);
-                return Err(Error::new(ErrorKind::Other, "Failed to refresh token"));
-            }
+        storage: &Storage,
+        force: bool,
+        checksums: &[String]
+    ) -> Result<(), OsuError> {
+        self.reload_tokens_if_required().await?;
 
-            let config = config.unwrap();
+        let key = archive_key(id);
 
-            self.access_token = config.osu_access_token;
-            self.refresh_token = config.osu_refresh_token;
-            self.token_expires_at = config.osu_token_expires_at;
+        if !force && storage.exists(&key).await.map_err(OsuError::Storage)? {
+            return Ok(())
         }
-        let data_folder = Path::new(path_to_beatmaps.as_str());
-        let path_to_save = data_folder.join(format!("{}.osz", id));
 
-        let file = File::open(path_to_save.clone()).await;
+        //Sets which are not indexed yet are checked against what osu! knows about them
+        let checksums = match checksums.is_empty() {
+            true => self
+                .fetch_beatmapset(id)
+                .await?
+                .beatmaps
+                .into_iter()
+                .filter_map(|beatmap| beatmap.checksum)
+                .collect(),
+            false => checksums.to_vec(),
+        };
 
-        if file.is_ok() && !force {
-            return Ok(Vec::new())
+        fs::create_dir_all(storage.staging_dir()).await.map_err(|err| OsuError::Storage(StorageError::Io(err)))?;
+        let mut reason = String::new();
+
+        for attempt in 1..=DOWNLOAD_ATTEMPTS {
+            let part = staging_path(storage, &key);
+
+            match self.download_archive(id, &part, &checksums).await {
+                Ok(()) => {
+                    //Only complete and verified archives make it into the storage
+                    storage.put_file(&key, &part).await.map_err(|err| {
+                        error!("Failed to save beatmap: {:#?}", err);
+                        OsuError::Storage(err)
+                    })?;
+
+                    //Variants, previews and covers cached from the previous version are stale now
+                    for derived in derived_keys(id) {
+                        storage.delete(&derived).await.map_err(OsuError::Storage)?;
+                    }
+                    return Ok(());
+                }
+                Err(OsuError::InvalidArchive(err)) | Err(OsuError::InvalidResponse(err)) => {
+                    warn!("Rejected archive of {} (attempt {}/{}): {}", id, attempt, DOWNLOAD_ATTEMPTS, err);
+                    let _ = fs::remove_file(&part).await;
+                    reason = err;
+                }
+                Err(err) => {
+                    error!("Failed to download beatmap {}: {}", id, err);
+                    let _ = fs::remove_file(&part).await;
+                    return Err(err);
+                }
+            }
         }
-   
-        let client = reqwest::Client::new();
-
-        let response = client
-            .get(format!(
-                "https://osu.ppy.sh/api/v2/beatmapsets/{}/download",
-                id
-            ))
-            .bearer_auth(self.clone().access_token)
-            .send()
-            .await
-            .unwrap();
 
-        info!("got response");
+        Err(OsuError::InvalidArchive(reason))
+    }
+
+    async fn fetch_beatmapset(&mut self, id: i64) -> Result<Beatmapset, OsuError> {
+        self.reload_tokens_if_required().await?;
+
+        let response = self
+            .send(&self.api_limiter, || {
+                self.http
+                    .get(self.url(&format!("/api/v2/beatmapsets/{}", id)))
+                    .header("Accept", "application/json")
+                    .bearer_auth(&self.access_token)
+            })
+            .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(OsuError::NotFound);
+        }
 
         if !response.status().is_success() {
-            let status = response.status().as_u16();
-            error!("Invalid status: {}", status);
-            return Err(Error::new(
-                ErrorKind::Other,
-                "Error while downloading file.",
-            ));
+            return Err(OsuError::Status(response.status()));
         }
 
-        let bytes = response.bytes().await.unwrap();
-        //Saving it to data folder
+        let beatmapset = response.json::<Beatmapset>().await;
 
-        let result = write(path_to_save, bytes.clone());
-        if result.is_err() {
-            error!("Failed to save beatmap: {:#?}", result.unwrap_err());
+        if let Err(err) = beatmapset {
+            return Err(OsuError::InvalidResponse(format!("Failed to parse beatmapset: {}", err)));
         }
-        Ok(bytes.to_vec())
+
+        Ok(beatmapset.unwrap())
+    }
+
+    /// Up to 50 beatmaps per request, ones which don't exist anymore are left out of the response.
+    /// Only the reconciliation sweep uses it, so it draws on its own budget.
+    async fn lookup_beatmaps(&mut self, ids: &[i64]) -> Result<Vec<Beatmap>, OsuError> {
+        self.reload_tokens_if_required().await?;
+
+        let query: Vec<(&str, i64)> = ids.iter().map(|&id| ("ids[]", id)).collect();
+        let response = self
+            .send(&self.reconcile_limiter, || {
+                self.http
+                    .get(self.url("/api/v2/beatmaps"))
+                    .query(&query)
+                    .header("Accept", "application/json")
+                    .bearer_auth(&self.access_token)
+            })
+            .await?;
+
+        if !response.status().is_success() {
+            return Err(OsuError::Status(response.status()));
+        }
+
+        response
+            .json::<Beatmaps>()
+            .await
+            .map(|response| response.beatmaps)
+            .map_err(|err| OsuError::InvalidResponse(format!("Failed to parse beatmaps: {}", err)))
     }
 
     async fn refresh_token_if_required(&mut self) -> bool {
-        let config = self.clone().load_config().expect("Failed to load config");
+        let config = match self.clone().load_config() {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Failed to load config: {}", err);
+                return false;
+            }
+        };
 
         if config.osu_access_token != self.access_token {
             return true;
@@ -341,8 +625,179 @@ impl OsuApi for OsuClient {
     }
 
     fn load_config(self) -> Result<Configuration, ConfyError> {
-        let configuration: Result<Configuration, ConfyError> = confy::load("mirria", None);
+        let configuration: Result<Configuration, ConfyError> = load_stored();
 
         configuration
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::{
+        storage::local::LocalStorage,
+        testing::{fixture_archive, fixture_beatmapset, fixture_cover, generated_beatmapsets, temp_dir, FakeOsu},
+    };
+
+    use super::*;
+
+    #[tokio::test]
+    async fn fetches_current_user() {
+        let fake = FakeOsu::start().await;
+        let client = fake.client().await;
+
+        let user = client.fetch_user().await.unwrap();
+
+        assert_eq!(user.username, "mirria");
+    }
+
+    #[tokio::test]
+    async fn pages_through_search_with_cursor() {
+        let fake = FakeOsu::with_beatmapsets(generated_beatmapsets(1000, 120), HashMap::new()).await;
+        let mut client = fake.client().await;
+
+        let mut cursor = None;
+        let mut ids = Vec::new();
+        loop {
+            let page = client
+                .search_beatmapsets(true, "updated_asc".to_string(), "any".to_string(), cursor)
+                .await
+                .unwrap();
+
+            ids.extend(page.beatmapsets.iter().map(|set| set.mapset_id));
+            cursor = page.cursor_string;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(ids, (1000..1120).collect::<Vec<i64>>());
+        assert_eq!(fake.requests("/api/v2/beatmapsets/search"), 3);
+    }
+
+    #[tokio::test]
+    async fn downloads_archive_once() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+        let folder = temp_dir("downloads_archive_once");
+        let storage = Storage::Local(LocalStorage::new(&folder));
+
+        client.download_if_not_exists(1, &storage, false, &[]).await.unwrap();
+        assert_eq!(std::fs::read(folder.join("1.osz")).unwrap(), fixture_archive());
+        assert_eq!(std::fs::read_dir(storage.staging_dir()).unwrap().count(), 0);
+
+        client.download_if_not_exists(1, &storage, false, &[]).await.unwrap();
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1/download"), 1);
+    }
+
+    #[tokio::test]
+    async fn replacing_archive_drops_derived_files() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+        let storage = Storage::Local(LocalStorage::new(temp_dir("replacing_archive_drops_derived_files")));
+
+        client.download_if_not_exists(1, &storage, false, &[]).await.unwrap();
+        for key in derived_keys(1) {
+            storage.put(&key, b"stale".to_vec()).await.unwrap();
+        }
+
+        client.download_if_not_exists(1, &storage, true, &[]).await.unwrap();
+        for key in derived_keys(1) {
+            assert!(!storage.exists(&key).await.unwrap(), "{} was kept", key);
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_assets() {
+        let fake = FakeOsu::start().await;
+        let client = fake.client().await;
+
+        let cover = client.fetch_asset(&format!("{}/assets/beatmaps/1/covers/card.jpg", fake.base_url)).await.unwrap();
+        assert_eq!(cover, fixture_cover("card.jpg"));
+
+        assert!(matches!(
+            client.fetch_asset(&format!("{}/assets/beatmaps/2/covers/card.jpg", fake.base_url)).await,
+            Err(OsuError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_archives() {
+        let beatmapset = fixture_beatmapset();
+        let checksums: Vec<String> = beatmapset.beatmaps.iter().filter_map(|beatmap| beatmap.checksum.clone()).collect();
+        let archives = HashMap::from([(1, b"<html>Service Unavailable</html>".to_vec())]);
+        let fake = FakeOsu::with_beatmapsets(vec![beatmapset], archives).await;
+        let mut client = fake.client().await;
+        let folder = temp_dir("rejects_invalid_archives");
+        let storage = Storage::Local(LocalStorage::new(&folder));
+
+        let result = client.download_if_not_exists(1, &storage, true, &checksums).await;
+
+        assert!(matches!(result, Err(OsuError::InvalidArchive(_))));
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1/download"), DOWNLOAD_ATTEMPTS as usize);
+        assert!(!folder.join("1.osz").exists());
+        assert_eq!(std::fs::read_dir(storage.staging_dir()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn rejects_archives_of_other_beatmapsets() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+        let storage = Storage::Local(LocalStorage::new(temp_dir("rejects_archives_of_other_beatmapsets")));
+
+        let result = client.download_if_not_exists(1, &storage, true, &["0".repeat(32)]).await;
+
+        assert!(matches!(result, Err(OsuError::InvalidArchive(_))));
+    }
+
+    #[tokio::test]
+    async fn missing_beatmapset_is_not_found() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+
+        assert!(matches!(client.fetch_beatmapset(404).await, Err(OsuError::NotFound)));
+        assert!(matches!(
+            client.download_if_not_exists(404, &Storage::Local(LocalStorage::new(temp_dir("missing"))), true, &[]).await,
+            Err(OsuError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn looks_up_beatmaps_in_batches() {
+        let fake = FakeOsu::with_beatmapsets(generated_beatmapsets(1000, 3), HashMap::new()).await;
+        let mut client = fake.client().await;
+
+        let beatmaps = client.lookup_beatmaps(&[100_000, 100_100, 404]).await.unwrap();
+
+        let sets: Vec<i64> = beatmaps.iter().map(|beatmap| beatmap.mapset_id).collect();
+        assert_eq!(sets, [1000, 1001]);
+        assert_eq!(fake.requests("/api/v2/beatmaps"), 1);
+    }
+
+    #[tokio::test]
+    async fn retries_server_errors_and_rate_limits() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+
+        fake.fail_next(2);
+        let beatmapset = client.fetch_beatmapset(1).await.unwrap();
+        assert_eq!(beatmapset.title, "Fixture Song");
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1"), 3);
+
+        fake.rate_limit_next(1);
+        client.fetch_beatmapset(1).await.unwrap();
+        assert_eq!(fake.requests("/api/v2/beatmapsets/1"), 5);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_retries() {
+        let fake = FakeOsu::start().await;
+        let mut client = fake.client().await;
+
+        fake.fail_next(100);
+        let result = client.fetch_beatmapset(1).await;
+
+        assert!(matches!(result, Err(OsuError::Status(StatusCode::INTERNAL_SERVER_ERROR))));
+    }
+}
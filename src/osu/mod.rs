@@ -1,2 +1,4 @@
 pub mod client;
-pub mod types;
\ No newline at end of file
+pub mod types;
+pub mod ratelimit;
+pub mod parser;
@@ -0,0 +1,508 @@
+//! Parser for `.osu` difficulty files, format versions 3 to 14.
+//!
+//! Older versions leave out fields which were added later (`ApproachRate` before v8,
+//! most timing point columns before v5, ...), those get the defaults osu! itself uses.
+
+use std::fmt;
+
+use serde_derive::Serialize;
+
+#[derive(Debug, PartialEq)]
+pub enum ParseError {
+    MissingHeader,
+    InvalidLine { line: usize, reason: String },
+}
+
+impl std::error::Error for ParseError {}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::MissingHeader => write!(f, "Missing \"osu file format\" header."),
+            ParseError::InvalidLine { line, reason } => write!(f, "Invalid line {}: {}", line, reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct General {
+    pub audio_filename: String,
+    pub audio_lead_in: i32,
+    pub preview_time: i32,
+    pub countdown: i32,
+    pub sample_set: String,
+    pub stack_leniency: f64,
+    pub mode: u8,
+    pub letterbox_in_breaks: bool,
+    pub widescreen_storyboard: bool,
+}
+
+impl Default for General {
+    fn default() -> Self {
+        General {
+            audio_filename: String::new(),
+            audio_lead_in: 0,
+            preview_time: -1,
+            countdown: 1,
+            sample_set: "Normal".to_string(),
+            stack_leniency: 0.7,
+            mode: 0,
+            letterbox_in_breaks: false,
+            widescreen_storyboard: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    pub title: String,
+    pub title_unicode: String,
+    pub artist: String,
+    pub artist_unicode: String,
+    pub creator: String,
+    pub version: String,
+    pub source: String,
+    pub tags: Vec<String>,
+    pub beatmap_id: Option<i64>,
+    pub beatmapset_id: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Difficulty {
+    pub hp_drain_rate: f64,
+    pub circle_size: f64,
+    pub overall_difficulty: f64,
+    pub approach_rate: f64,
+    pub slider_multiplier: f64,
+    pub slider_tick_rate: f64,
+}
+
+impl Default for Difficulty {
+    fn default() -> Self {
+        Difficulty {
+            hp_drain_rate: 5.0,
+            circle_size: 5.0,
+            overall_difficulty: 5.0,
+            approach_rate: 5.0,
+            slider_multiplier: 1.4,
+            slider_tick_rate: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Video {
+    pub file: String,
+    pub start_time: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Break {
+    pub start_time: i32,
+    pub end_time: i32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Events {
+    pub background: Option<String>,
+    pub video: Option<Video>,
+    pub breaks: Vec<Break>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimingPoint {
+    pub time: f64,
+    /// Milliseconds per beat for uninherited points, a negative inverse slider
+    /// velocity percentage (`-100` is 1x) for inherited ones.
+    pub beat_length: f64,
+    pub meter: i32,
+    pub sample_set: i32,
+    pub sample_index: i32,
+    pub volume: i32,
+    pub uninherited: bool,
+    pub kiai: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum HitObjectKind {
+    Circle,
+    Slider {
+        curve_type: String,
+        curve_points: Vec<(f64, f64)>,
+        slides: i32,
+        length: f64,
+    },
+    Spinner {
+        end_time: f64,
+    },
+    /// osu!mania long note.
+    Hold {
+        end_time: f64,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HitObject {
+    pub x: f64,
+    pub y: f64,
+    pub time: f64,
+    pub new_combo: bool,
+    pub hit_sound: i32,
+    #[serde(flatten)]
+    pub kind: HitObjectKind,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct OsuFile {
+    pub format_version: i32,
+    pub general: General,
+    pub metadata: Metadata,
+    pub difficulty: Difficulty,
+    pub events: Events,
+    pub timing_points: Vec<TimingPoint>,
+    pub hit_objects: Vec<HitObject>,
+}
+
+fn invalid(line: usize, reason: impl ToString) -> ParseError {
+    ParseError::InvalidLine { line, reason: reason.to_string() }
+}
+
+fn number<T: std::str::FromStr>(value: &str, line: usize, what: &str) -> Result<T, ParseError> {
+    value
+        .trim()
+        .parse::<T>()
+        .map_err(|_| invalid(line, format!("{} is not a number: {:?}", what, value)))
+}
+
+/// Like `number` for optional trailing columns, missing or empty ones fall back to `default`.
+fn column<T: std::str::FromStr>(fields: &[&str], index: usize, default: T, line: usize, what: &str) -> Result<T, ParseError> {
+    match fields.get(index).map(|value| value.trim()) {
+        None | Some("") => Ok(default),
+        Some(value) => number(value, line, what),
+    }
+}
+
+fn flag(value: &str) -> bool {
+    value.trim() == "1"
+}
+
+fn unquote(value: &str) -> String {
+    value.trim().trim_matches('"').to_string()
+}
+
+impl OsuFile {
+    pub fn parse(content: &str) -> Result<OsuFile, ParseError> {
+        let mut lines = content.lines().enumerate();
+        let mut file = OsuFile::default();
+
+        let header = lines
+            .by_ref()
+            .map(|(_, line)| line.trim_start_matches('\u{feff}').trim())
+            .find(|line| !line.is_empty())
+            .ok_or(ParseError::MissingHeader)?;
+        file.format_version = header
+            .strip_prefix("osu file format v")
+            .and_then(|version| version.trim().parse::<i32>().ok())
+            .ok_or(ParseError::MissingHeader)?;
+
+        let mut section = String::new();
+        let mut approach_rate = None;
+
+        for (index, raw) in lines {
+            let line_number = index + 1;
+            let line = raw.trim();
+
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].to_string();
+                continue;
+            }
+
+            match section.as_str() {
+                "General" | "Metadata" | "Difficulty" => {
+                    let (key, value) = match line.split_once(':') {
+                        Some((key, value)) => (key.trim(), value.trim()),
+                        None => continue,
+                    };
+
+                    match section.as_str() {
+                        "General" => file.parse_general(key, value, line_number)?,
+                        "Metadata" => file.parse_metadata(key, value, line_number)?,
+                        _ => {
+                            if key == "ApproachRate" {
+                                approach_rate = Some(number(value, line_number, key)?);
+                            } else {
+                                file.parse_difficulty(key, value, line_number)?;
+                            }
+                        }
+                    }
+                }
+                "Events" => file.parse_event(line, line_number)?,
+                "TimingPoints" => file.timing_points.push(parse_timing_point(line, line_number)?),
+                "HitObjects" => file.hit_objects.push(parse_hit_object(line, line_number)?),
+                _ => {}
+            }
+        }
+
+        // Before v8 the approach rate was always the overall difficulty.
+        file.difficulty.approach_rate = approach_rate.unwrap_or(file.difficulty.overall_difficulty);
+
+        Ok(file)
+    }
+
+    fn parse_general(&mut self, key: &str, value: &str, line: usize) -> Result<(), ParseError> {
+        let general = &mut self.general;
+
+        match key {
+            "AudioFilename" => general.audio_filename = value.to_string(),
+            "AudioLeadIn" => general.audio_lead_in = number(value, line, key)?,
+            "PreviewTime" => general.preview_time = number(value, line, key)?,
+            "Countdown" => general.countdown = number(value, line, key)?,
+            "SampleSet" => general.sample_set = value.to_string(),
+            "StackLeniency" => general.stack_leniency = number(value, line, key)?,
+            "Mode" => general.mode = number(value, line, key)?,
+            "LetterboxInBreaks" => general.letterbox_in_breaks = flag(value),
+            "WidescreenStoryboard" => general.widescreen_storyboard = flag(value),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn parse_metadata(&mut self, key: &str, value: &str, line: usize) -> Result<(), ParseError> {
+        let metadata = &mut self.metadata;
+
+        match key {
+            "Title" => metadata.title = value.to_string(),
+            "TitleUnicode" => metadata.title_unicode = value.to_string(),
+            "Artist" => metadata.artist = value.to_string(),
+            "ArtistUnicode" => metadata.artist_unicode = value.to_string(),
+            "Creator" => metadata.creator = value.to_string(),
+            "Version" => metadata.version = value.to_string(),
+            "Source" => metadata.source = value.to_string(),
+            "Tags" => metadata.tags = value.split_whitespace().map(|tag| tag.to_string()).collect(),
+            "BeatmapID" => metadata.beatmap_id = Some(number(value, line, key)?),
+            "BeatmapSetID" => metadata.beatmapset_id = Some(number(value, line, key)?),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn parse_difficulty(&mut self, key: &str, value: &str, line: usize) -> Result<(), ParseError> {
+        let difficulty = &mut self.difficulty;
+
+        match key {
+            "HPDrainRate" => difficulty.hp_drain_rate = number(value, line, key)?,
+            "CircleSize" => difficulty.circle_size = number(value, line, key)?,
+            "OverallDifficulty" => difficulty.overall_difficulty = number(value, line, key)?,
+            "SliderMultiplier" => difficulty.slider_multiplier = number(value, line, key)?,
+            "SliderTickRate" => difficulty.slider_tick_rate = number(value, line, key)?,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn parse_event(&mut self, line: &str, line_number: usize) -> Result<(), ParseError> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() < 3 {
+            return Ok(());
+        }
+
+        match fields[0].trim() {
+            "0" => self.events.background = Some(unquote(fields[2])),
+            "1" | "Video" => {
+                self.events.video = Some(Video {
+                    file: unquote(fields[2]),
+                    start_time: number(fields[1], line_number, "video start time")?,
+                })
+            }
+            "2" | "Break" => self.events.breaks.push(Break {
+                start_time: number(fields[1], line_number, "break start time")?,
+                end_time: number(fields[2], line_number, "break end time")?,
+            }),
+            // Storyboard commands are not part of the difficulty.
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_timing_point(line: &str, line_number: usize) -> Result<TimingPoint, ParseError> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() < 2 {
+        return Err(invalid(line_number, "timing point needs a time and a beat length"));
+    }
+
+    let beat_length: f64 = number(fields[1], line_number, "beat length")?;
+    let effects: i32 = column(&fields, 7, 0, line_number, "effects")?;
+
+    Ok(TimingPoint {
+        time: number(fields[0], line_number, "time")?,
+        beat_length,
+        meter: column(&fields, 2, 4, line_number, "meter")?,
+        sample_set: column(&fields, 3, 0, line_number, "sample set")?,
+        sample_index: column(&fields, 4, 0, line_number, "sample index")?,
+        volume: column(&fields, 5, 100, line_number, "volume")?,
+        // Versions without the column mark inherited points by a negative beat length only.
+        uninherited: match fields.get(6).map(|value| value.trim()) {
+            None | Some("") => beat_length >= 0.0,
+            Some(value) => flag(value),
+        },
+        kiai: effects & 1 == 1,
+    })
+}
+
+fn parse_hit_object(line: &str, line_number: usize) -> Result<HitObject, ParseError> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() < 5 {
+        return Err(invalid(line_number, "hit object needs at least five fields"));
+    }
+
+    let kind_flags: i32 = number(fields[3], line_number, "type")?;
+    let time: f64 = number(fields[2], line_number, "time")?;
+
+    let kind = if kind_flags & 1 != 0 {
+        HitObjectKind::Circle
+    } else if kind_flags & 2 != 0 {
+        parse_slider(&fields, line_number)?
+    } else if kind_flags & 8 != 0 {
+        HitObjectKind::Spinner {
+            end_time: column(&fields, 5, time, line_number, "spinner end time")?,
+        }
+    } else if kind_flags & 128 != 0 {
+        // The end time shares its column with the hit sample: `end:normal:addition:...`.
+        let end_time = fields.get(5).and_then(|value| value.split(':').next()).unwrap_or_default();
+        HitObjectKind::Hold {
+            end_time: number(end_time, line_number, "hold end time")?,
+        }
+    } else {
+        return Err(invalid(line_number, format!("unknown hit object type {}", kind_flags)));
+    };
+
+    Ok(HitObject {
+        x: number(fields[0], line_number, "x")?,
+        y: number(fields[1], line_number, "y")?,
+        time,
+        new_combo: kind_flags & 4 != 0,
+        hit_sound: number(fields[4], line_number, "hit sound")?,
+        kind,
+    })
+}
+
+fn parse_slider(fields: &[&str], line_number: usize) -> Result<HitObjectKind, ParseError> {
+    let path = fields
+        .get(5)
+        .ok_or_else(|| invalid(line_number, "slider without a curve"))?;
+    let mut segments = path.split('|');
+
+    let curve_type = segments.next().unwrap_or_default().trim().to_string();
+    let curve_points = segments
+        .map(|point| {
+            let (x, y) = point
+                .split_once(':')
+                .ok_or_else(|| invalid(line_number, format!("invalid curve point {:?}", point)))?;
+            Ok((number(x, line_number, "curve x")?, number(y, line_number, "curve y")?))
+        })
+        .collect::<Result<Vec<(f64, f64)>, ParseError>>()?;
+
+    Ok(HitObjectKind::Slider {
+        curve_type,
+        curve_points,
+        slides: column(fields, 6, 1, line_number, "slides")?,
+        length: column(fields, 7, 0.0, line_number, "length")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixture_difficulty;
+
+    use super::*;
+
+    fn count(file: &OsuFile, predicate: impl Fn(&HitObjectKind) -> bool) -> usize {
+        file.hit_objects.iter().filter(|object| predicate(&object.kind)).count()
+    }
+
+    #[test]
+    fn parses_v14_fixture() {
+        let file = OsuFile::parse(&String::from_utf8_lossy(&fixture_difficulty("Normal"))).unwrap();
+
+        assert_eq!(file.format_version, 14);
+        assert_eq!(file.general.audio_filename, "audio.mp3");
+        assert_eq!(file.general.preview_time, 12000);
+        assert_eq!(file.metadata.version, "Normal");
+        assert_eq!(file.metadata.tags, vec!["fixture", "test"]);
+        assert_eq!(file.metadata.beatmap_id, Some(11));
+        assert_eq!(file.difficulty.approach_rate, 6.0);
+        assert_eq!(file.events.background.as_deref(), Some("bg.jpg"));
+        assert_eq!(file.events.breaks, vec![Break { start_time: 30000, end_time: 36000 }]);
+
+        assert_eq!(file.timing_points.len(), 4);
+        assert!(file.timing_points[0].uninherited);
+        assert!(!file.timing_points[1].uninherited);
+        assert!(file.timing_points[2].kiai);
+
+        assert_eq!(count(&file, |kind| matches!(kind, HitObjectKind::Circle)), 53);
+        assert_eq!(count(&file, |kind| matches!(kind, HitObjectKind::Slider { .. })), 13);
+        assert_eq!(count(&file, |kind| matches!(kind, HitObjectKind::Spinner { .. })), 1);
+        assert!(file.hit_objects[0].new_combo);
+    }
+
+    #[test]
+    fn fills_defaults_for_old_versions() {
+        let content = "\u{feff}osu file format v3\r\n\r\n[General]\r\nAudioFilename: old.mp3\r\n\r\n\
+            [Metadata]\r\nTitle:Old\r\n\r\n[Difficulty]\r\nHPDrainRate:6\r\nCircleSize:3\r\nOverallDifficulty:7\r\n\
+            SliderMultiplier: 1\r\n\r\n[Events]\r\n0,0,old.jpg\r\nVideo,500,\"intro.avi\"\r\n\r\n\
+            [TimingPoints]\r\n120,400\r\n800,-50\r\n\r\n[HitObjects]\r\n64,80,120,1,0\r\n\
+            100,100,520,2,0,B|200:200|300:100,2,140\r\n256,192,1000,12,0,3000\r\n";
+
+        let file = OsuFile::parse(content).unwrap();
+
+        assert_eq!(file.format_version, 3);
+        assert_eq!(file.general.audio_filename, "old.mp3");
+        assert_eq!(file.general.mode, 0);
+        assert_eq!(file.difficulty.approach_rate, 7.0);
+        assert_eq!(file.difficulty.slider_tick_rate, 1.0);
+        assert_eq!(file.events.background.as_deref(), Some("old.jpg"));
+        assert_eq!(file.events.video, Some(Video { file: "intro.avi".to_string(), start_time: 500 }));
+        assert_eq!(
+            file.timing_points.iter().map(|point| (point.uninherited, point.meter, point.volume)).collect::<Vec<_>>(),
+            vec![(true, 4, 100), (false, 4, 100)]
+        );
+        assert_eq!(
+            file.hit_objects[1].kind,
+            HitObjectKind::Slider {
+                curve_type: "B".to_string(),
+                curve_points: vec![(200.0, 200.0), (300.0, 100.0)],
+                slides: 2,
+                length: 140.0,
+            }
+        );
+        assert_eq!(file.hit_objects[2].kind, HitObjectKind::Spinner { end_time: 3000.0 });
+    }
+
+    #[test]
+    fn parses_mania_holds() {
+        let content = "osu file format v12\n[General]\nMode: 3\n[HitObjects]\n64,192,1000,128,0,1500:0:0:0:0:\n";
+        let file = OsuFile::parse(content).unwrap();
+
+        assert_eq!(file.general.mode, 3);
+        assert_eq!(file.hit_objects[0].kind, HitObjectKind::Hold { end_time: 1500.0 });
+    }
+
+    #[test]
+    fn reports_broken_lines() {
+        assert_eq!(OsuFile::parse("[General]\nMode: 0\n"), Err(ParseError::MissingHeader));
+        assert!(matches!(
+            OsuFile::parse("osu file format v14\n\n[HitObjects]\n64,x,1000,1,0\n"),
+            Err(ParseError::InvalidLine { line: 4, .. })
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{Duration, Instant};
+
+use axum_prometheus::metrics::{counter, histogram};
+use tokio::{sync::Mutex, time};
+use tracing::warn;
+
+use crate::config::RateLimit;
+
+#[derive(Debug)]
+struct BucketState {
+    tokens: f64,
+    last_refill: Instant,
+    paused_until: Option<Instant>,
+}
+
+/// Token bucket shared by every clone of `OsuClient`, refilled continuously at
+/// `per_minute` tokens per minute and holding at most `burst` tokens.
+#[derive(Debug)]
+pub struct TokenBucket {
+    name: &'static str,
+    capacity: f64,
+    refill_per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+impl TokenBucket {
+    pub fn new(name: &'static str, limit: &RateLimit) -> Self {
+        let capacity = limit.burst.max(1) as f64;
+
+        Self {
+            name,
+            capacity,
+            refill_per_sec: limit.per_minute.max(1) as f64 / 60.0,
+            state: Mutex::new(BucketState {
+                tokens: capacity,
+                last_refill: Instant::now(),
+                paused_until: None,
+            }),
+        }
+    }
+
+    /// Waits until a token is available and takes it, returning the time spent waiting.
+    pub async fn acquire(&self) -> Duration {
+        let started = Instant::now();
+
+        loop {
+            let wait = {
+                let mut state = self.state.lock().await;
+                let now = Instant::now();
+
+                let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+                state.tokens = (state.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+                state.last_refill = now;
+
+                match state.paused_until {
+                    Some(until) if until > now => until - now,
+                    _ if state.tokens >= 1.0 => {
+                        state.tokens -= 1.0;
+                        break;
+                    }
+                    _ => Duration::from_secs_f64((1.0 - state.tokens) / self.refill_per_sec),
+                }
+            };
+
+            time::sleep(wait).await;
+        }
+
+        let waited = started.elapsed();
+        histogram!("mirria_osu_ratelimit_wait_seconds", "bucket" => self.name).record(waited.as_secs_f64());
+
+        waited
+    }
+
+    /// Stops handing out tokens for `duration`, used when osu! answers with 429.
+    pub async fn pause_for(&self, duration: Duration) {
+        let mut state = self.state.lock().await;
+        let until = Instant::now() + duration;
+
+        warn!("osu! rate limited the {} bucket, pausing for {:?}", self.name, duration);
+        counter!("mirria_osu_ratelimited_total", "bucket" => self.name).increment(1);
+
+        if state.paused_until.is_none_or(|paused| paused < until) {
+            state.paused_until = Some(until);
+        }
+        state.tokens = 0.0;
+    }
+}
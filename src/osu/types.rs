@@ -99,9 +99,9 @@ pub struct Beatmapset {
     #[serde(rename = "nominations_summary")]
     pub nominations_summary: Value,
 
-    pub availability: Value, // Using Value to remain generic
+    pub availability: Availability,
 
-    pub covers: Value, // Using Value to remain generic
+    pub covers: Covers,
 
     #[serde(rename = "track_id")]
     pub track_id: Option<i64>,
@@ -121,6 +121,29 @@ pub struct Beatmapset {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub ratings: Option<Vec<i64>>,
+
+    /// Average user rating, sets indexed before it was stored have 0.
+    #[serde(default)]
+    pub rating: f64,
+
+    /// Only part of full beatmapset lookups, the crawler fills them in for search results.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub genre: Option<Genre>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Genre {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Language {
+    pub id: i64,
+    pub name: String,
 }
 
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -140,6 +163,42 @@ pub struct Covers {
     pub slimcover_2x: String,
 }
 
+impl Beatmapset {
+    /// Changes whenever osu! updates the covers: the cache busting query of their urls,
+    /// or `last_updated` for urls without one.
+    pub fn covers_version(&self) -> String {
+        let version = match self.covers.cover.split_once('?') {
+            Some((_, query)) if !query.is_empty() => query,
+            _ => &self.last_updated,
+        };
+
+        version.chars().filter(|c| c.is_ascii_alphanumeric()).collect()
+    }
+}
+
+impl Covers {
+    /// Cover kinds as named by osu!, which are also their keys in `covers`.
+    pub const KINDS: [&'static str; 8] = [
+        "cover", "cover@2x", "card", "card@2x", "list", "list@2x", "slimcover", "slimcover@2x",
+    ];
+
+    pub fn url(&self, kind: &str) -> Option<&str> {
+        let url = match kind {
+            "cover" => &self.cover,
+            "cover@2x" => &self.cover_2x,
+            "card" => &self.card,
+            "card@2x" => &self.card_2x,
+            "list" => &self.list,
+            "list@2x" => &self.list_2x,
+            "slimcover" => &self.slimcover,
+            "slimcover@2x" => &self.slimcover_2x,
+            _ => return None,
+        };
+
+        Some(url.as_str())
+    }
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct NominationsSummary {
@@ -223,6 +282,12 @@ pub struct Beatmap {
     pub max_combo: Option<i64>,
 }
 
+/// Response of `/api/v2/beatmaps`.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Beatmaps {
+    pub beatmaps: Vec<Beatmap>,
+}
+
 #[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Search {
@@ -236,3 +301,18 @@ pub struct Cursor {
     pub last_update: i64,
     pub id: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::fixture_beatmapset;
+
+    #[test]
+    fn covers_version_follows_cache_busting_query() {
+        let mut beatmapset = fixture_beatmapset();
+        beatmapset.last_updated = "2024-01-01T00:00:00+00:00".to_string();
+        assert_eq!(beatmapset.covers_version(), "20240101T0000000000");
+
+        beatmapset.covers.cover = "https://assets.ppy.sh/beatmaps/1/covers/cover.jpg?1700000000".to_string();
+        assert_eq!(beatmapset.covers_version(), "1700000000");
+    }
+}
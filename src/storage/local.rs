@@ -0,0 +1,95 @@
+use std::{io::{ErrorKind, SeekFrom}, path::{Path, PathBuf}};
+
+use futures_util::StreamExt;
+use tokio::{fs, io::{AsyncReadExt, AsyncSeekExt}};
+use tokio_util::io::ReaderStream;
+
+use super::{BeatmapStorage, ObjectStream, StorageError};
+
+fn map_io(err: std::io::Error) -> StorageError {
+    match err.kind() {
+        ErrorKind::NotFound => StorageError::NotFound,
+        _ => StorageError::Io(err),
+    }
+}
+
+/// Keeps objects as plain files below the beatmaps folder.
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> LocalStorage {
+        LocalStorage { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl BeatmapStorage for LocalStorage {
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        fs::try_exists(self.path(key)).await.map_err(StorageError::Io)
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        fs::read(self.path(key)).await.map_err(map_io)
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ObjectStream, StorageError> {
+        let file = fs::File::open(self.path(key)).await.map_err(map_io)?;
+        let length = file.metadata().await.map_err(StorageError::Io)?.len();
+
+        Ok(ObjectStream {
+            length: Some(length),
+            body: ReaderStream::new(file).boxed(),
+        })
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<ObjectStream, StorageError> {
+        let mut file = fs::File::open(self.path(key)).await.map_err(map_io)?;
+        file.seek(SeekFrom::Start(start)).await.map_err(StorageError::Io)?;
+        let length = end + 1 - start;
+
+        Ok(ObjectStream {
+            length: Some(length),
+            body: ReaderStream::new(file.take(length)).boxed(),
+        })
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, StorageError> {
+        Ok(fs::metadata(self.path(key)).await.map_err(map_io)?.len())
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
+        }
+
+        fs::write(path, data).await.map_err(StorageError::Io)
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        let destination = self.path(key);
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent).await.map_err(StorageError::Io)?;
+        }
+
+        fs::rename(path, destination).await.map_err(StorageError::Io)
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match fs::remove_file(self.path(key)).await {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(StorageError::Io(err)),
+        }
+    }
+
+    fn staging_dir(&self) -> PathBuf {
+        self.root.join(".staging")
+    }
+}
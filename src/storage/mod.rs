@@ -0,0 +1,248 @@
+pub mod local;
+pub mod s3;
+
+use std::{fmt, path::{Path, PathBuf}, time::Duration};
+
+use bytes::Bytes;
+use futures_util::{stream::{self, BoxStream}, Stream, StreamExt};
+use tokio::{fs, io::AsyncWriteExt, time};
+
+use crate::config::Configuration;
+
+use self::{local::LocalStorage, s3::S3Storage};
+
+#[derive(Debug)]
+pub enum StorageError {
+    NotFound,
+    Io(std::io::Error),
+    Http(reqwest::Error),
+    Status(u16),
+    InvalidConfiguration(String),
+}
+
+impl std::error::Error for StorageError {}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::NotFound => write!(f, "Object not found."),
+            StorageError::Io(err) => write!(f, "Storage io error: {}", err),
+            StorageError::Http(err) => write!(f, "Storage request failed: {}", err),
+            StorageError::Status(status) => write!(f, "Storage responded with status {}", status),
+            StorageError::InvalidConfiguration(reason) => write!(f, "Invalid storage configuration: {}", reason),
+        }
+    }
+}
+
+/// Ends `body` with a `TimedOut` error once it goes `idle` without yielding anything.
+pub fn with_read_timeout<S>(body: S, idle: Duration) -> BoxStream<'static, Result<Bytes, std::io::Error>>
+where
+    S: Stream<Item = Result<Bytes, std::io::Error>> + Send + 'static,
+{
+    stream::unfold(Some(body.boxed()), move |body| async move {
+        let mut body = body?;
+
+        match time::timeout(idle, body.next()).await {
+            Ok(Some(chunk)) => Some((chunk, Some(body))),
+            Ok(None) => None,
+            Err(_) => Some((Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "body stalled")), None)),
+        }
+    })
+    .boxed()
+}
+
+pub struct ObjectStream {
+    /// Unknown when the backend doesn't say, the body is then sent chunked.
+    pub length: Option<u64>,
+    pub body: BoxStream<'static, Result<Bytes, std::io::Error>>,
+}
+
+/// Where `.osz` archives (and anything derived from them) are kept, keys are relative paths like `1.osz`.
+pub trait BeatmapStorage {
+    async fn exists(&self, key: &str) -> Result<bool, StorageError>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError>;
+    async fn get_stream(&self, key: &str) -> Result<ObjectStream, StorageError>;
+    /// Streams bytes `start..=end` of the object.
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<ObjectStream, StorageError>;
+    async fn size(&self, key: &str) -> Result<u64, StorageError>;
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError>;
+    /// Moves a complete file from `staging_dir` into the storage under `key`.
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError>;
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+    /// Directory for partial files, for the local backend it lives on the same
+    /// filesystem so `put_file` is an atomic rename.
+    fn staging_dir(&self) -> PathBuf;
+}
+
+pub fn archive_key(id: i64) -> String {
+    format!("{}.osz", id)
+}
+
+/// The archive without its video, derived from `archive_key(id)`.
+pub fn novideo_archive_key(id: i64) -> String {
+    format!("novideo/{}.osz", id)
+}
+
+/// A single difficulty extracted from its archive, addressed by its MD5.
+pub fn difficulty_key(checksum: &str) -> String {
+    format!("osu/{}.osu", checksum.to_lowercase())
+}
+
+/// Marks a difficulty which wasn't in a freshly downloaded archive of its set, holds the unix time of the miss.
+pub fn missing_difficulty_key(checksum: &str) -> String {
+    format!("osu/missing/{}", checksum.to_lowercase())
+}
+
+/// A cover of a beatmapset as proxied from osu!, `kind` is one of `Covers::KINDS`. `version` is
+/// `Beatmapset::covers_version`, so updated covers never hit the copy cached before.
+pub fn cover_key(id: i64, version: &str, kind: &str) -> String {
+    format!("covers/{}/{}/{}.jpg", id, version, kind)
+}
+
+/// Audio preview clip of a beatmapset.
+pub fn preview_key(id: i64) -> String {
+    format!("previews/{}.mp3", id)
+}
+
+/// What is cached from the archive of a beatmapset, stale once the archive is replaced.
+pub fn derived_keys(id: i64) -> Vec<String> {
+    vec![novideo_archive_key(id), preview_key(id)]
+}
+
+/// Background of a difficulty, `variant` is `full` for the original image or a thumbnail size.
+pub fn background_key(checksum: &str, variant: &str) -> String {
+    format!("backgrounds/{}/{}", checksum.to_lowercase(), variant)
+}
+
+/// Calculated difficulty and pp of a difficulty for one mod combination, the version
+/// prefix changes whenever the calculation does.
+pub fn attributes_key(checksum: &str, mods: u32) -> String {
+    format!("attributes/v1/{}/{}.json", checksum.to_lowercase(), mods)
+}
+
+/// A fresh file name in the staging directory, `name` only helps telling them apart.
+pub fn staging_path(storage: &impl BeatmapStorage, name: &str) -> PathBuf {
+    storage
+        .staging_dir()
+        .join(format!("{}.{:016x}.part", name, rand::random::<u64>()))
+}
+
+/// Copies `key` into a staging file for code which needs a seekable file, the caller removes it.
+pub async fn copy_to_staging(storage: &impl BeatmapStorage, key: &str) -> Result<PathBuf, StorageError> {
+    let mut object = storage.get_stream(key).await?;
+
+    fs::create_dir_all(storage.staging_dir()).await.map_err(StorageError::Io)?;
+    let path = staging_path(storage, key.rsplit('/').next().unwrap_or(key));
+
+    let copied = async {
+        let mut file = fs::File::create(&path).await?;
+        while let Some(chunk) = object.body.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await
+    }
+    .await;
+
+    if let Err(err) = copied {
+        let _ = fs::remove_file(&path).await;
+        return Err(StorageError::Io(err));
+    }
+
+    Ok(path)
+}
+
+#[derive(Debug, Clone)]
+pub enum Storage {
+    Local(LocalStorage),
+    S3(S3Storage),
+}
+
+impl Storage {
+    pub fn from_config(config: &Configuration) -> Result<Storage, StorageError> {
+        match config.storage.as_str() {
+            "local" => Ok(Storage::Local(LocalStorage::new(&config.beatmaps_folder))),
+            "s3" => Ok(Storage::S3(S3Storage::new(&config.s3, &config.http)?)),
+            other => Err(StorageError::InvalidConfiguration(format!("unknown storage backend {}", other))),
+        }
+    }
+}
+
+impl BeatmapStorage for Storage {
+    async fn exists(&self, key: &str) -> Result<bool, StorageError> {
+        match self {
+            Storage::Local(storage) => storage.exists(key).await,
+            Storage::S3(storage) => storage.exists(key).await,
+        }
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        match self {
+            Storage::Local(storage) => storage.get(key).await,
+            Storage::S3(storage) => storage.get(key).await,
+        }
+    }
+
+    async fn get_stream(&self, key: &str) -> Result<ObjectStream, StorageError> {
+        match self {
+            Storage::Local(storage) => storage.get_stream(key).await,
+            Storage::S3(storage) => storage.get_stream(key).await,
+        }
+    }
+
+    async fn get_range(&self, key: &str, start: u64, end: u64) -> Result<ObjectStream, StorageError> {
+        match self {
+            Storage::Local(storage) => storage.get_range(key, start, end).await,
+            Storage::S3(storage) => storage.get_range(key, start, end).await,
+        }
+    }
+
+    async fn size(&self, key: &str) -> Result<u64, StorageError> {
+        match self {
+            Storage::Local(storage) => storage.size(key).await,
+            Storage::S3(storage) => storage.size(key).await,
+        }
+    }
+
+    async fn put(&self, key: &str, data: Vec<u8>) -> Result<(), StorageError> {
+        match self {
+            Storage::Local(storage) => storage.put(key, data).await,
+            Storage::S3(storage) => storage.put(key, data).await,
+        }
+    }
+
+    async fn put_file(&self, key: &str, path: &Path) -> Result<(), StorageError> {
+        match self {
+            Storage::Local(storage) => storage.put_file(key, path).await,
+            Storage::S3(storage) => storage.put_file(key, path).await,
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        match self {
+            Storage::Local(storage) => storage.delete(key).await,
+            Storage::S3(storage) => storage.delete(key).await,
+        }
+    }
+
+    fn staging_dir(&self) -> PathBuf {
+        match self {
+            Storage::Local(storage) => storage.staging_dir(),
+            Storage::S3(storage) => storage.staging_dir(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn read_timeout_ends_stalled_bodies() {
+        let stalled = stream::iter([Ok(Bytes::from_static(b"partial"))]).chain(stream::pending());
+        let mut body = with_read_timeout(stalled, Duration::from_millis(10));
+
+        assert_eq!(body.next().await.unwrap().unwrap(), Bytes::from_static(b"partial"));
+        assert_eq!(body.next().await.unwrap().unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+        assert!(body.next().await.is_none());
+    }
+}
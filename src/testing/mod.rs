@@ -0,0 +1,468 @@
+//! Test support: a fake osu! server serving fixtures from `src/testing/fixtures`,
+//! plus helpers for building clients and contexts pointed at it.
+
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Once,
+    },
+};
+
+use axum::{
+    extract::{Path, Query, State},
+    http::{header::AUTHORIZATION, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use meilisearch_sdk::client::Client;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::{
+    config::{store, Configuration, Http, RateLimit, RateLimits},
+    crawler::Context,
+    ops::{beatmapset::add_beatmapsets, indexes::ensure_indexes},
+    osu::{
+        client::{OsuApi, OsuClient},
+        types::{Beatmap, Beatmaps, Beatmapset, Genre, Language, Search, SearchResponse},
+    },
+    storage::{local::LocalStorage, Storage},
+};
+
+pub const ACCESS_TOKEN: &str = "fake-access-token";
+pub const REFRESH_TOKEN: &str = "fake-refresh-token";
+
+/// Same page size as osu!, the crawler relies on it to detect the end of a search.
+const PAGE_SIZE: usize = 50;
+
+pub fn fixture_beatmapset() -> Beatmapset {
+    serde_json::from_str(include_str!("fixtures/beatmapset.json")).unwrap()
+}
+
+pub fn fixture_archive() -> Vec<u8> {
+    include_bytes!("fixtures/1.osz").to_vec()
+}
+
+/// One of the `.osu` files inside the fixture archive, by difficulty name.
+pub fn fixture_difficulty(version: &str) -> Vec<u8> {
+    match version {
+        "Normal" => include_bytes!("fixtures/Fixture Artist - Fixture Song (mirria) [Normal].osu").to_vec(),
+        "Hard" => include_bytes!("fixtures/Fixture Artist - Fixture Song (mirria) [Hard].osu").to_vec(),
+        other => panic!("no fixture difficulty {}", other),
+    }
+}
+
+/// A zip holding `files`, by name.
+pub fn build_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    for (name, content) in files {
+        writer.start_file(*name, zip::write::FileOptions::default()).unwrap();
+        std::io::Write::write_all(&mut writer, content).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+/// Copies of the fixture set with ids starting at `first_id`, each updated a minute after the previous one.
+pub fn generated_beatmapsets(first_id: i64, count: usize) -> Vec<Beatmapset> {
+    let template = fixture_beatmapset();
+
+    (0..count as i64)
+        .map(|offset| {
+            let mut beatmapset = template.clone();
+            let id = first_id + offset;
+            let updated = chrono::DateTime::parse_from_rfc3339(&template.last_updated).unwrap()
+                + chrono::Duration::minutes(offset);
+
+            beatmapset.mapset_id = id;
+            beatmapset.last_updated = updated.to_rfc3339();
+            beatmapset.genre = Some(Genre { id: 2, name: "Video Game".to_string() });
+            beatmapset.language = Some(Language { id: 5, name: "Instrumental".to_string() });
+            for (index, beatmap) in beatmapset.beatmaps.iter_mut().enumerate() {
+                beatmap.mapset_id = id;
+                beatmap.map_id = id * 100 + index as i64;
+            }
+            beatmapset
+        })
+        .collect()
+}
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let path = std::env::temp_dir()
+        .join(format!("mirria-test-{}", std::process::id()))
+        .join(name);
+    std::fs::create_dir_all(&path).unwrap();
+    path
+}
+
+static STORED_CONFIG: Once = Once::new();
+
+/// Points the stored configuration at a scratch file holding the fake tokens,
+/// so token refreshes and cursor saves never touch the real one.
+pub fn init_stored_config() {
+    STORED_CONFIG.call_once(|| {
+        let path = temp_dir("config").join("mirria.yml");
+        std::env::set_var("MIRRIA_CONFIG_PATH", &path);
+
+        let config = Configuration {
+            osu_access_token: ACCESS_TOKEN.to_string(),
+            osu_refresh_token: REFRESH_TOKEN.to_string(),
+            osu_token_expires_at: i64::MAX,
+            ..Default::default()
+        };
+        store(config).unwrap();
+    });
+}
+
+#[derive(Default)]
+struct FakeState {
+    beatmapsets: Vec<Beatmapset>,
+    archives: HashMap<i64, Vec<u8>>,
+    failures: AtomicUsize,
+    rate_limits: AtomicUsize,
+    requests: Mutex<Vec<String>>,
+}
+
+impl FakeState {
+    fn record(&self, path: String) -> Option<Response> {
+        self.requests.lock().unwrap().push(path);
+
+        if take_one(&self.rate_limits) {
+            return Some((StatusCode::TOO_MANY_REQUESTS, [("Retry-After", "0")], "").into_response());
+        }
+
+        if take_one(&self.failures) {
+            return Some(StatusCode::INTERNAL_SERVER_ERROR.into_response());
+        }
+
+        None
+    }
+}
+
+fn take_one(counter: &AtomicUsize) -> bool {
+    counter
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| left.checked_sub(1))
+        .is_ok()
+}
+
+fn authorized(headers: &HeaderMap) -> bool {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value == format!("Bearer {}", ACCESS_TOKEN))
+}
+
+async fn token(State(state): State<Arc<FakeState>>) -> Response {
+    if let Some(response) = state.record("/oauth/token".to_string()) {
+        return response;
+    }
+
+    Json(json!({
+        "access_token": ACCESS_TOKEN,
+        "refresh_token": REFRESH_TOKEN,
+        "expires_in": 86400,
+        "token_type": "Bearer",
+    }))
+    .into_response()
+}
+
+async fn me(State(state): State<Arc<FakeState>>, headers: HeaderMap) -> Response {
+    if let Some(response) = state.record("/api/v2/me".to_string()) {
+        return response;
+    }
+
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    Json(json!({ "username": "mirria", "id": 2 })).into_response()
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    sort: Option<String>,
+    s: Option<String>,
+    cursor_string: Option<String>,
+}
+
+async fn search(
+    State(state): State<Arc<FakeState>>,
+    headers: HeaderMap,
+    Query(params): Query<SearchParams>,
+) -> Response {
+    if let Some(response) = state.record("/api/v2/beatmapsets/search".to_string()) {
+        return response;
+    }
+
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let status = params.s.unwrap_or("any".to_string());
+    let sort = params.sort.unwrap_or("updated_desc".to_string());
+
+    // Like osu!, search results leave out what only full lookups carry.
+    let mut matching: Vec<Beatmapset> = state
+        .beatmapsets
+        .iter()
+        .filter(|set| status == "any" || set.status == status)
+        .map(|set| Beatmapset { genre: None, language: None, ..set.clone() })
+        .collect();
+
+    matching.sort_by(|a, b| (&a.last_updated, a.mapset_id).cmp(&(&b.last_updated, b.mapset_id)));
+    if sort.ends_with("_desc") {
+        matching.reverse();
+    }
+
+    // The cursor is opaque for clients, here it is simply the offset of the next page.
+    let offset = params
+        .cursor_string
+        .and_then(|cursor| cursor.parse::<usize>().ok())
+        .unwrap_or(0)
+        .min(matching.len());
+    let end = (offset + PAGE_SIZE).min(matching.len());
+
+    let response = SearchResponse {
+        beatmapsets: matching[offset..end].to_vec(),
+        search: Search { sort },
+        recommended_difficulty: None,
+        error: None,
+        total: matching.len() as i64,
+        cursor: None,
+        cursor_string: (end < matching.len()).then(|| end.to_string()),
+    };
+
+    Json(response).into_response()
+}
+
+async fn beatmapset(
+    State(state): State<Arc<FakeState>>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response {
+    if let Some(response) = state.record(format!("/api/v2/beatmapsets/{}", id)) {
+        return response;
+    }
+
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match state.beatmapsets.iter().find(|set| set.mapset_id == id) {
+        Some(set) => Json(set.clone()).into_response(),
+        None => (StatusCode::NOT_FOUND, Json(json!({ "error": null }))).into_response(),
+    }
+}
+
+async fn beatmaps(
+    State(state): State<Arc<FakeState>>,
+    headers: HeaderMap,
+    Query(params): Query<Vec<(String, String)>>,
+) -> Response {
+    if let Some(response) = state.record("/api/v2/beatmaps".to_string()) {
+        return response;
+    }
+
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let ids: Vec<i64> = params
+        .iter()
+        .filter(|(key, _)| key == "ids[]")
+        .filter_map(|(_, id)| id.parse().ok())
+        .collect();
+    let beatmaps: Vec<Beatmap> = state
+        .beatmapsets
+        .iter()
+        .flat_map(|set| set.beatmaps.iter())
+        .filter(|beatmap| ids.contains(&beatmap.map_id))
+        .cloned()
+        .collect();
+
+    Json(Beatmaps { beatmaps }).into_response()
+}
+
+async fn download(
+    State(state): State<Arc<FakeState>>,
+    headers: HeaderMap,
+    Path(id): Path<i64>,
+) -> Response {
+    if let Some(response) = state.record(format!("/api/v2/beatmapsets/{}/download", id)) {
+        return response;
+    }
+
+    if !authorized(&headers) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    match state.archives.get(&id) {
+        Some(archive) => (
+            [("Content-Type", "application/x-osu-beatmap-archive")],
+            archive.clone(),
+        )
+            .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Stand-in for a cover image served by the fake assets host.
+pub fn fixture_cover(file: &str) -> Vec<u8> {
+    format!("cover {}", file).into_bytes()
+}
+
+async fn cover(State(state): State<Arc<FakeState>>, Path((id, file)): Path<(i64, String)>) -> Response {
+    if let Some(response) = state.record(format!("/assets/beatmaps/{}/covers/{}", id, file)) {
+        return response;
+    }
+
+    match state.beatmapsets.iter().any(|set| set.mapset_id == id) {
+        true => ([("Content-Type", "image/jpeg")], fixture_cover(&file)).into_response(),
+        false => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// A fake osu! listening on a random local port.
+pub struct FakeOsu {
+    pub base_url: String,
+    state: Arc<FakeState>,
+}
+
+impl FakeOsu {
+    /// Serves the fixture set (id 1) and its archive.
+    pub async fn start() -> FakeOsu {
+        let beatmapset = fixture_beatmapset();
+        let archives = HashMap::from([(beatmapset.mapset_id, fixture_archive())]);
+
+        FakeOsu::with_beatmapsets(vec![beatmapset], archives).await
+    }
+
+    pub async fn with_beatmapsets(beatmapsets: Vec<Beatmapset>, archives: HashMap<i64, Vec<u8>>) -> FakeOsu {
+        init_stored_config();
+
+        let state = Arc::new(FakeState {
+            beatmapsets,
+            archives,
+            ..Default::default()
+        });
+
+        let router = Router::new()
+            .route("/oauth/token", post(token))
+            .route("/api/v2/me", get(me))
+            .route("/api/v2/beatmaps", get(beatmaps))
+            .route("/api/v2/beatmapsets/search", get(search))
+            .route("/api/v2/beatmapsets/:id", get(beatmapset))
+            .route("/api/v2/beatmapsets/:id/download", get(download))
+            .route("/assets/beatmaps/:id/covers/:file", get(cover))
+            .with_state(state.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+        FakeOsu {
+            base_url: format!("http://{}", address),
+            state,
+        }
+    }
+
+    /// Answers the next `count` requests with 500.
+    pub fn fail_next(&self, count: usize) {
+        self.state.failures.store(count, Ordering::SeqCst);
+    }
+
+    /// Answers the next `count` requests with 429 and `Retry-After: 0`.
+    pub fn rate_limit_next(&self, count: usize) {
+        self.state.rate_limits.store(count, Ordering::SeqCst);
+    }
+
+    /// Number of requests received for exactly `path`.
+    pub fn requests(&self, path: &str) -> usize {
+        self.state.requests.lock().unwrap().iter().filter(|request| *request == path).count()
+    }
+
+    pub fn configuration(&self, beatmaps_folder: PathBuf) -> Configuration {
+        Configuration {
+            osu_access_token: ACCESS_TOKEN.to_string(),
+            osu_refresh_token: REFRESH_TOKEN.to_string(),
+            osu_token_expires_at: i64::MAX,
+            osu_base_url: self.base_url.clone(),
+            beatmaps_folder: beatmaps_folder.to_string_lossy().to_string(),
+            http: Http {
+                backoff_base_ms: 1,
+                backoff_max_ms: 5,
+                ..Default::default()
+            },
+            rate_limits: RateLimits {
+                api: RateLimit { per_minute: 60_000, burst: 1_000 },
+                download: RateLimit { per_minute: 60_000, burst: 1_000 },
+                reconcile: RateLimit { per_minute: 60_000, burst: 1_000 },
+                assets: RateLimit { per_minute: 60_000, burst: 1_000 },
+            },
+            ..Default::default()
+        }
+    }
+
+    pub async fn client(&self) -> OsuClient {
+        let config = self.configuration(temp_dir("beatmaps"));
+
+        OsuClient::from_tokens(config, ACCESS_TOKEN.to_string(), REFRESH_TOKEN.to_string())
+            .await
+            .unwrap()
+    }
+}
+
+/// Meilisearch from `MEILISEARCH_URL`/`MEILISEARCH_KEY`. End-to-end tests are ignored by default,
+/// CI runs them with `--include-ignored` against a Meilisearch service.
+pub fn meili_client() -> Option<Client> {
+    let url = std::env::var("MEILISEARCH_URL").ok()?;
+    let key = std::env::var("MEILISEARCH_KEY").ok();
+
+    Some(Client::new(url, key).unwrap())
+}
+
+/// Context pointed at `fake` with its own indexes, named after the test, so tests never touch
+/// real indexes or each other's.
+pub async fn context(fake: &FakeOsu, name: &str) -> Context {
+    let meili_client = meili_client().expect("end-to-end tests need MEILISEARCH_URL");
+
+    let mut config = fake.configuration(temp_dir(name));
+    config.meilisearch.index_prefix = format!("test_{}_{}_", std::process::id(), name);
+    ensure_indexes(&meili_client, &config.meilisearch.index_prefix).await;
+
+    let osu = OsuClient::from_tokens(config.clone(), ACCESS_TOKEN.to_string(), REFRESH_TOKEN.to_string())
+        .await
+        .unwrap();
+
+    Context {
+        storage: Storage::Local(LocalStorage::new(&config.beatmaps_folder)),
+        config: Arc::new(config),
+        meili_client: Arc::new(meili_client),
+        osu,
+    }
+}
+
+/// Context pointed at `fake` for code which only talks to osu! and the storage, its Meilisearch is unreachable.
+pub async fn offline_context(fake: &FakeOsu, name: &str) -> Context {
+    let config = fake.configuration(temp_dir(name));
+    let osu = OsuClient::from_tokens(config.clone(), ACCESS_TOKEN.to_string(), REFRESH_TOKEN.to_string())
+        .await
+        .unwrap();
+
+    Context {
+        storage: Storage::Local(LocalStorage::new(&config.beatmaps_folder)),
+        config: Arc::new(config),
+        meili_client: Arc::new(Client::new("http://127.0.0.1:1", None::<String>).unwrap()),
+        osu,
+    }
+}
+
+/// Adds `beatmapsets` to the beatmapset index of `ctx` and waits until they are searchable.
+pub async fn index_beatmapsets(ctx: &Context, beatmapsets: &[Beatmapset]) {
+    let task = add_beatmapsets(ctx, beatmapsets).await.unwrap();
+    task.wait_for_completion(&ctx.meili_client, None, Some(std::time::Duration::from_secs(30)))
+        .await
+        .unwrap();
+}